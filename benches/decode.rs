@@ -0,0 +1,415 @@
+//! Compares `Instruction::decode` against the list-matching decoder it
+//! replaced. Run with `cargo bench --bench decode`.
+
+use std::{hint::black_box, time::Instant};
+
+use chip_eight::Instruction;
+
+const ROUNDS: usize = 20;
+
+fn main()
+{
+    let words: Vec<Instruction> =
+        (0..=u16::MAX).map(|word| Instruction::new(word.to_be_bytes())).collect();
+
+    for ins in &words
+    {
+        // The table also knows the SUPER-CHIP and XO-CHIP extensions, so it
+        // only has to accept everything the legacy matcher accepted.
+        assert!(
+            Operation::get_op_code(ins).is_none() || ins.decode().is_some(),
+            "decode table rejects {:02X}{:02X}",
+            ins.data[0],
+            ins.data[1]
+        );
+    }
+
+    let table = time(|| {
+        for ins in &words
+        {
+            black_box(black_box(ins).decode());
+        }
+    });
+    let legacy = time(|| {
+        for ins in &words
+        {
+            black_box(Operation::get_op_code(black_box(ins)));
+        }
+    });
+
+    let per_decode = |total: f64| total / (ROUNDS * words.len()) as f64 * 1e9;
+    println!("decode table:   {:>10.2} ns/instruction", per_decode(table));
+    println!(
+        "legacy matcher: {:>10.2} ns/instruction",
+        per_decode(legacy)
+    );
+    println!("speedup:        {:>10.1}x", legacy / table);
+}
+
+fn time<F: FnMut()>(mut f: F) -> f64
+{
+    let start = Instant::now();
+    for _ in 0..ROUNDS
+    {
+        f();
+    }
+    start.elapsed().as_secs_f64()
+}
+
+// The original decoder, kept verbatim apart from returning the index of the
+// matched entry, as the baseline for the comparison above.
+struct Operation
+{
+    definition: Vec<OperationComponent>,
+    op_code: usize,
+}
+
+impl Operation
+{
+    fn new(op_code: usize, definition: Vec<OperationComponent>) -> Self
+    {
+        Self {
+            definition,
+            op_code,
+        }
+    }
+
+    fn get_op_code(instruction: &Instruction) -> Option<usize>
+    {
+        let operations = Operation::get_operations();
+        let instruction_bits = instruction.get_bits();
+        let matches = operations
+            .iter()
+            .filter(|cur_op| {
+                let mut i = 0usize;
+                for comp in &cur_op.definition
+                {
+                    match comp
+                    {
+                        OperationComponent::Literal(c) =>
+                        {
+                            if instruction_bits[i] != *c
+                            {
+                                return false;
+                            }
+                            i += 1;
+                        }
+                        OperationComponent::Nnn => i += 3,
+                        OperationComponent::N => i += 1,
+                        OperationComponent::X => i += 1,
+                        OperationComponent::Y => i += 1,
+                        OperationComponent::Kk => i += 2,
+                    }
+                }
+                true
+            })
+            .map(|f| f.op_code)
+            .collect::<Vec<usize>>();
+        matches.first().copied()
+    }
+
+    fn get_operations() -> Vec<Operation>
+    {
+        vec![
+            Self {
+                definition: vec![
+                    OperationComponent::Literal(0x0),
+                    OperationComponent::Literal(0x0),
+                    OperationComponent::Literal(0xE),
+                    OperationComponent::Literal(0x0),
+                ],
+                op_code: 100,
+            },
+            Self {
+                definition: vec![OperationComponent::Literal(0x1), OperationComponent::Nnn],
+                op_code: 101,
+            },
+            Self {
+                definition: vec![
+                    OperationComponent::Literal(0x6),
+                    OperationComponent::X,
+                    OperationComponent::Kk,
+                ],
+                op_code: 102,
+            },
+            Self {
+                definition: vec![
+                    OperationComponent::Literal(0x7),
+                    OperationComponent::X,
+                    OperationComponent::Kk,
+                ],
+                op_code: 103,
+            },
+            Self {
+                definition: vec![OperationComponent::Literal(0xA), OperationComponent::Nnn],
+                op_code: 104,
+            },
+            Self {
+                definition: vec![
+                    OperationComponent::Literal(0xD),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::N,
+                ],
+                op_code: 105,
+            },
+            Self::new(
+                0,
+                vec![
+                    OperationComponent::Literal(0x0),
+                    OperationComponent::Literal(0x0),
+                    OperationComponent::Literal(0xE),
+                    OperationComponent::Literal(0xE),
+                ],
+            ),
+            Self::new(
+                1,
+                vec![OperationComponent::Literal(0x2), OperationComponent::Nnn],
+            ),
+            Self::new(
+                2,
+                vec![
+                    OperationComponent::Literal(0x3),
+                    OperationComponent::X,
+                    OperationComponent::Kk,
+                ],
+            ),
+            Self::new(
+                3,
+                vec![
+                    OperationComponent::Literal(0x4),
+                    OperationComponent::X,
+                    OperationComponent::Kk,
+                ],
+            ),
+            Self::new(
+                4,
+                vec![
+                    OperationComponent::Literal(0x5),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x0),
+                ],
+            ),
+            Self::new(
+                5,
+                vec![
+                    OperationComponent::Literal(0x7),
+                    OperationComponent::X,
+                    OperationComponent::Kk,
+                ],
+            ),
+            Self::new(
+                6,
+                vec![
+                    OperationComponent::Literal(0x8),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x0),
+                ],
+            ),
+            Self::new(
+                7,
+                vec![
+                    OperationComponent::Literal(0x8),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x1),
+                ],
+            ),
+            Self::new(
+                8,
+                vec![
+                    OperationComponent::Literal(0x8),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x4),
+                ],
+            ),
+            Self::new(
+                9,
+                vec![
+                    OperationComponent::Literal(0x8),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x3),
+                ],
+            ),
+            Self::new(
+                10,
+                vec![
+                    OperationComponent::Literal(0x8),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x2),
+                ],
+            ),
+            Self::new(
+                11,
+                vec![
+                    OperationComponent::Literal(0x8),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x5),
+                ],
+            ),
+            Self::new(
+                12,
+                vec![
+                    OperationComponent::Literal(0x8),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x6),
+                ],
+            ),
+            Self::new(
+                13,
+                vec![
+                    OperationComponent::Literal(0x8),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x7),
+                ],
+            ),
+            Self::new(
+                14,
+                vec![
+                    OperationComponent::Literal(0x8),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0xE),
+                ],
+            ),
+            Self::new(
+                15,
+                vec![
+                    OperationComponent::Literal(0x9),
+                    OperationComponent::X,
+                    OperationComponent::Y,
+                    OperationComponent::Literal(0x0),
+                ],
+            ),
+            Self::new(
+                16,
+                vec![OperationComponent::Literal(0xB), OperationComponent::Nnn],
+            ),
+            Self::new(
+                17,
+                vec![
+                    OperationComponent::Literal(0xC),
+                    OperationComponent::X,
+                    OperationComponent::Kk,
+                ],
+            ),
+            Self::new(
+                18,
+                vec![
+                    OperationComponent::Literal(0xE),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x9),
+                    OperationComponent::Literal(0xE),
+                ],
+            ),
+            Self::new(
+                19,
+                vec![
+                    OperationComponent::Literal(0xE),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0xA),
+                    OperationComponent::Literal(0x1),
+                ],
+            ),
+            Self::new(
+                20,
+                vec![
+                    OperationComponent::Literal(0xF),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x0),
+                    OperationComponent::Literal(0x7),
+                ],
+            ),
+            Self::new(
+                21,
+                vec![
+                    OperationComponent::Literal(0xF),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x0),
+                    OperationComponent::Literal(0xA),
+                ],
+            ),
+            Self::new(
+                22,
+                vec![
+                    OperationComponent::Literal(0xF),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x1),
+                    OperationComponent::Literal(0x5),
+                ],
+            ),
+            Self::new(
+                23,
+                vec![
+                    OperationComponent::Literal(0xF),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x1),
+                    OperationComponent::Literal(0x8),
+                ],
+            ),
+            Self::new(
+                24,
+                vec![
+                    OperationComponent::Literal(0xF),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x1),
+                    OperationComponent::Literal(0xE),
+                ],
+            ),
+            Self::new(
+                25,
+                vec![
+                    OperationComponent::Literal(0xF),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x2),
+                    OperationComponent::Literal(0x9),
+                ],
+            ),
+            Self::new(
+                26,
+                vec![
+                    OperationComponent::Literal(0xF),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x3),
+                    OperationComponent::Literal(0x3),
+                ],
+            ),
+            Self::new(
+                27,
+                vec![
+                    OperationComponent::Literal(0xF),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x5),
+                    OperationComponent::Literal(0x5),
+                ],
+            ),
+            Self::new(
+                28,
+                vec![
+                    OperationComponent::Literal(0xF),
+                    OperationComponent::X,
+                    OperationComponent::Literal(0x6),
+                    OperationComponent::Literal(0x5),
+                ],
+            ),
+        ]
+    }
+}
+#[derive(Copy, Clone)]
+enum OperationComponent
+{
+    Literal(u8),
+    Nnn,
+    N,
+    X,
+    Y,
+    Kk,
+}
@@ -0,0 +1,1005 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::instruction::OpCode;
+
+/// Includes nested deeper than this are assumed to be a cycle.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Where in the source something was found. Lines and columns count from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location
+{
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum AsmError
+{
+    Io(io::Error),
+    Source
+    {
+        location: Location,
+        message: String,
+    },
+}
+
+impl AsmError
+{
+    pub(crate) fn at(location: &Location, message: String) -> Self
+    {
+        AsmError::Source {
+            location: location.clone(),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for AsmError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            AsmError::Io(e) => write!(f, "{}", e),
+            AsmError::Source { location, message } => write!(f, "{}: {}", location, message),
+        }
+    }
+}
+
+impl std::error::Error for AsmError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            AsmError::Io(e) => Some(e),
+            AsmError::Source { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for AsmError
+{
+    fn from(e: io::Error) -> Self
+    {
+        AsmError::Io(e)
+    }
+}
+
+/// Assembles the file at `path` into a ROM to be loaded at `base`.
+/// Includes are found relative to the file that includes them.
+pub fn assemble_file(path: &Path, base: u16) -> Result<Vec<u8>, AsmError>
+{
+    let source = fs::read_to_string(path)?;
+    assemble_named(&source, &path.display().to_string(), path.parent(), base)
+}
+
+/// Assembles `source` into a ROM to be loaded at `base`.
+///
+/// The source uses the mnemonics of [`crate::disasm::cowgod`], one
+/// instruction per line, plus:
+///
+/// ```text
+/// SPEED equ 2             ; a constant
+/// loop:                   ; a label
+///     ADD V0, SPEED + 1
+///     JP loop
+/// sprite:
+///     db 0b11110000, 0x90, "text"
+///     dw 0x1234
+/// include "other.asm"
+/// ```
+///
+/// Includes are found relative to the current directory.
+pub fn assemble(source: &str, base: u16) -> Result<Vec<u8>, AsmError>
+{
+    assemble_named(source, "<source>", None, base)
+}
+
+fn assemble_named(
+    source: &str,
+    name: &str,
+    dir: Option<&Path>,
+    base: u16,
+) -> Result<Vec<u8>, AsmError>
+{
+    let mut program = Program {
+        address: base as usize,
+        statements: Vec::new(),
+        symbols: HashMap::new(),
+    };
+    program.read(source, name, dir.unwrap_or(Path::new("")), 0)?;
+    program.encode()
+}
+
+/// A value that may refer to labels and constants.
+#[derive(Clone, Debug)]
+enum Expr
+{
+    Number(i64),
+    Symbol(String, Location),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+}
+
+/// An instruction operand, in the order they are tried.
+#[derive(Clone, Debug)]
+enum Operand
+{
+    V(u8),
+    /// `I`, `[I]`, `DT`, `ST`, `K`, `F`, `B`, `HF`, `R` or `PITCH`, upper case.
+    Special(&'static str),
+    Long(Expr, Location),
+    Value(Expr, Location),
+}
+
+const SPECIAL_OPERANDS: &[&str] = &["I", "DT", "ST", "K", "F", "B", "HF", "R", "PITCH"];
+
+#[derive(Clone, Debug)]
+enum Statement
+{
+    Instruction
+    {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Bytes(Vec<Datum>),
+    Words(Vec<(Expr, Location)>),
+}
+
+#[derive(Clone, Debug)]
+enum Datum
+{
+    Value(Expr, Location),
+    Text(String),
+}
+
+#[derive(Clone, Debug)]
+enum Symbol
+{
+    Label(usize),
+    Constant(Expr),
+}
+
+struct Program
+{
+    /// Where the next statement goes.
+    address: usize,
+    statements: Vec<(Statement, Location)>,
+    symbols: HashMap<String, (Symbol, Location)>,
+}
+
+impl Program
+{
+    /// Collects the statements of `source` and defines its labels and
+    /// constants. Every statement's size is known without evaluating
+    /// anything, so labels can be used before they are defined.
+    fn read(&mut self, source: &str, name: &str, dir: &Path, depth: usize) -> Result<(), AsmError>
+    {
+        for (index, text) in source.lines().enumerate()
+        {
+            let start = Location {
+                file: name.to_string(),
+                line: index + 1,
+                column: 1,
+            };
+            let mut tokens = Tokens::new(text, start.clone())?;
+            if let (Some(Token::Ident(name)), Some(Token::Colon)) = (tokens.peek(0), tokens.peek(1))
+            {
+                let location = tokens.location();
+                let name = name.clone();
+                tokens.skip(2);
+                self.define(name, Symbol::Label(self.address), location)?;
+            }
+            let Some(Token::Ident(word)) = tokens.peek(0).cloned()
+            else
+            {
+                if tokens.peek(0).is_some()
+                {
+                    return Err(AsmError::at(
+                        &tokens.location(),
+                        "expected an instruction".into(),
+                    ));
+                }
+                continue;
+            };
+            let location = tokens.location();
+            tokens.skip(1);
+            if let Some(Token::Ident(equ)) = tokens.peek(0)
+            {
+                if equ.eq_ignore_ascii_case("equ")
+                {
+                    tokens.skip(1);
+                    let value = tokens.expr()?;
+                    tokens.end()?;
+                    self.define(word, Symbol::Constant(value), location)?;
+                    continue;
+                }
+            }
+            let statement = match word.to_ascii_uppercase().as_str()
+            {
+                "INCLUDE" =>
+                {
+                    let Some(Token::Text(path)) = tokens.next()
+                    else
+                    {
+                        return Err(AsmError::at(
+                            &location,
+                            "include expects a file name".into(),
+                        ));
+                    };
+                    tokens.end()?;
+                    self.include(&dir.join(path), &location, depth)?;
+                    continue;
+                }
+                "DB" => Statement::Bytes(tokens.list(|tokens, location| match tokens.peek(0)
+                {
+                    Some(Token::Text(text)) =>
+                    {
+                        let text = text.clone();
+                        tokens.skip(1);
+                        Ok(Datum::Text(text))
+                    }
+                    _ => Ok(Datum::Value(tokens.expr()?, location)),
+                })?),
+                "DW" => Statement::Words(
+                    tokens.list(|tokens, location| Ok((tokens.expr()?, location)))?,
+                ),
+                _ => Statement::Instruction {
+                    mnemonic: word.to_ascii_uppercase(),
+                    operands: tokens.list(Tokens::operand)?,
+                },
+            };
+            self.address += match &statement
+            {
+                Statement::Instruction { operands, .. } =>
+                {
+                    if operands.iter().any(|operand| matches!(operand, Operand::Long(..)))
+                    {
+                        4
+                    }
+                    else
+                    {
+                        2
+                    }
+                }
+                Statement::Bytes(data) => data
+                    .iter()
+                    .map(|datum| match datum
+                    {
+                        Datum::Value(..) => 1,
+                        Datum::Text(text) => text.len(),
+                    })
+                    .sum(),
+                Statement::Words(words) => 2 * words.len(),
+            };
+            self.statements.push((statement, location));
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, path: &Path, location: &Location, depth: usize) -> Result<(), AsmError>
+    {
+        if depth >= MAX_INCLUDE_DEPTH
+        {
+            return Err(AsmError::at(
+                location,
+                "includes are nested too deeply".into(),
+            ));
+        }
+        let source = fs::read_to_string(path).map_err(|e| {
+            AsmError::at(
+                location,
+                format!("could not read '{}': {}", path.display(), e),
+            )
+        })?;
+        let dir = path.parent().map(PathBuf::from).unwrap_or_default();
+        self.read(&source, &path.display().to_string(), &dir, depth + 1)
+    }
+
+    fn define(&mut self, name: String, symbol: Symbol, location: Location) -> Result<(), AsmError>
+    {
+        if parse_register(&name).is_some()
+            || SPECIAL_OPERANDS.iter().any(|special| special.eq_ignore_ascii_case(&name))
+        {
+            return Err(AsmError::at(
+                &location,
+                format!("'{}' is a reserved name", name),
+            ));
+        }
+        if let Some((_, previous)) = self.symbols.get(&name)
+        {
+            return Err(AsmError::at(
+                &location,
+                format!("'{}' is already defined at {}", name, previous),
+            ));
+        }
+        self.symbols.insert(name, (symbol, location));
+        Ok(())
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, AsmError>
+    {
+        let mut rom = Vec::new();
+        for (statement, location) in &self.statements
+        {
+            match statement
+            {
+                Statement::Instruction { mnemonic, operands } =>
+                {
+                    rom.extend(self.instruction(mnemonic, operands, location)?.encode());
+                }
+                Statement::Bytes(data) =>
+                {
+                    for datum in data
+                    {
+                        match datum
+                        {
+                            Datum::Value(expr, location) =>
+                            {
+                                rom.push(self.byte(expr, location)?);
+                            }
+                            Datum::Text(text) => rom.extend(text.bytes()),
+                        }
+                    }
+                }
+                Statement::Words(words) =>
+                {
+                    for (expr, location) in words
+                    {
+                        let word = self.ranged(expr, location, -0x8000, 0xFFFF, "a word")?;
+                        rom.extend((word as u16).to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[Operand],
+        location: &Location,
+    ) -> Result<OpCode, AsmError>
+    {
+        use Operand::{Special, Value, V};
+        let op = match (mnemonic, operands)
+        {
+            ("CLS", []) => OpCode::Cls,
+            ("RET", []) => OpCode::Ret,
+            ("SCR", []) => OpCode::ScrollRight,
+            ("SCL", []) => OpCode::ScrollLeft,
+            ("EXIT", []) => OpCode::Exit,
+            ("LOW", []) => OpCode::Lores,
+            ("HIGH", []) => OpCode::Hires,
+            ("AUDIO", []) => OpCode::Audio,
+            ("JP", [Value(a, l)]) => OpCode::Jmp {
+                nnn: self.address(a, l)?,
+            },
+            ("JP", [V(0), Value(a, l)]) => OpCode::JpV0Addr {
+                nnn: self.address(a, l)?,
+            },
+            ("CALL", [Value(a, l)]) => OpCode::Call {
+                nnn: self.address(a, l)?,
+            },
+            ("SE", [V(x), V(y)]) => OpCode::SeVxVy { x: *x, y: *y },
+            ("SE", [V(x), Value(b, l)]) => OpCode::SeVxBy {
+                x: *x,
+                kk: self.byte(b, l)?,
+            },
+            ("SNE", [V(x), V(y)]) => OpCode::SneVxVy { x: *x, y: *y },
+            ("SNE", [V(x), Value(b, l)]) => OpCode::SneVxBy {
+                x: *x,
+                kk: self.byte(b, l)?,
+            },
+            ("LD", [V(x), V(y)]) => OpCode::LdVxVy { x: *x, y: *y },
+            ("LD", [V(x), Value(b, l)]) => OpCode::LdVxBy {
+                x: *x,
+                kk: self.byte(b, l)?,
+            },
+            ("LD", [Special("I"), Value(a, l)]) => OpCode::LdI {
+                nnn: self.address(a, l)?,
+            },
+            ("LD", [Special("I"), Operand::Long(a, l)]) => OpCode::LdILong {
+                nnnn: self.ranged(a, l, 0, 0xFFFF, "16 bits")? as u16,
+            },
+            ("LD", [V(x), Special("DT")]) => OpCode::LdVxDt { x: *x },
+            ("LD", [V(x), Special("K")]) => OpCode::LdVxK { x: *x },
+            ("LD", [Special("DT"), V(x)]) => OpCode::LdDtVx { x: *x },
+            ("LD", [Special("ST"), V(x)]) => OpCode::LdStVx { x: *x },
+            ("LD", [Special("F"), V(x)]) => OpCode::LdFVx { x: *x },
+            ("LD", [Special("B"), V(x)]) => OpCode::LdBVx { x: *x },
+            ("LD", [Special("[I]"), V(x)]) => OpCode::LdIVx { x: *x },
+            ("LD", [V(x), Special("[I]")]) => OpCode::LdVxI { x: *x },
+            ("LD", [Special("HF"), V(x)]) => OpCode::LdHfVx { x: *x },
+            ("LD", [Special("R"), V(x)]) => OpCode::LdRVx { x: *x },
+            ("LD", [V(x), Special("R")]) => OpCode::LdVxR { x: *x },
+            ("LD", [Special("PITCH"), V(x)]) => OpCode::LdPitchVx { x: *x },
+            ("ADD", [V(x), V(y)]) => OpCode::AddVxVy { x: *x, y: *y },
+            ("ADD", [V(x), Value(b, l)]) => OpCode::Add {
+                x: *x,
+                kk: self.byte(b, l)?,
+            },
+            ("ADD", [Special("I"), V(x)]) => OpCode::AddIVx { x: *x },
+            ("OR", [V(x), V(y)]) => OpCode::OrVxVy { x: *x, y: *y },
+            ("AND", [V(x), V(y)]) => OpCode::AndVxVy { x: *x, y: *y },
+            ("XOR", [V(x), V(y)]) => OpCode::XorVxVy { x: *x, y: *y },
+            ("SUB", [V(x), V(y)]) => OpCode::SubVxVy { x: *x, y: *y },
+            ("SUBN", [V(x), V(y)]) => OpCode::SubnVxVy { x: *x, y: *y },
+            ("SHR", [V(x)]) => OpCode::ShrVxVy { x: *x, y: *x },
+            ("SHR", [V(x), V(y)]) => OpCode::ShrVxVy { x: *x, y: *y },
+            ("SHL", [V(x)]) => OpCode::ShlVxVy { x: *x, y: *x },
+            ("SHL", [V(x), V(y)]) => OpCode::ShlVxVy { x: *x, y: *y },
+            ("RND", [V(x), Value(b, l)]) => OpCode::RndVxBy {
+                x: *x,
+                kk: self.byte(b, l)?,
+            },
+            ("DRW", [V(x), V(y), Value(n, l)]) => OpCode::Display {
+                x: *x,
+                y: *y,
+                n: self.nibble(n, l)?,
+            },
+            ("SKP", [V(x)]) => OpCode::SkpVx { x: *x },
+            ("SKNP", [V(x)]) => OpCode::SknpVx { x: *x },
+            ("SCD", [Value(n, l)]) => OpCode::ScrollDown {
+                n: self.nibble(n, l)?,
+            },
+            ("SCU", [Value(n, l)]) => OpCode::ScrollUp {
+                n: self.nibble(n, l)?,
+            },
+            ("SAVE", [V(x), V(y)]) => OpCode::SaveVxVy { x: *x, y: *y },
+            ("LOAD", [V(x), V(y)]) => OpCode::LoadVxVy { x: *x, y: *y },
+            ("PLANE", [Value(n, l)]) => OpCode::Plane {
+                n: self.nibble(n, l)?,
+            },
+            _ if KNOWN_MNEMONICS.contains(&mnemonic) =>
+            {
+                return Err(AsmError::at(
+                    location,
+                    format!("invalid operands for {}", mnemonic),
+                ));
+            }
+            _ =>
+            {
+                return Err(AsmError::at(
+                    location,
+                    format!("unknown instruction '{}'", mnemonic),
+                ))
+            }
+        };
+        Ok(op)
+    }
+
+    fn address(&self, expr: &Expr, location: &Location) -> Result<u16, AsmError>
+    {
+        Ok(self.ranged(expr, location, 0, 0xFFF, "12 bits")? as u16)
+    }
+
+    /// Bytes may also be given as negative numbers.
+    fn byte(&self, expr: &Expr, location: &Location) -> Result<u8, AsmError>
+    {
+        Ok(self.ranged(expr, location, -0x80, 0xFF, "a byte")? as u8)
+    }
+
+    fn nibble(&self, expr: &Expr, location: &Location) -> Result<u8, AsmError>
+    {
+        Ok(self.ranged(expr, location, 0, 0xF, "4 bits")? as u8)
+    }
+
+    fn ranged(
+        &self,
+        expr: &Expr,
+        location: &Location,
+        min: i64,
+        max: i64,
+        what: &str,
+    ) -> Result<i64, AsmError>
+    {
+        let value = self.eval(expr, 0)?;
+        if (min..=max).contains(&value)
+        {
+            Ok(value)
+        }
+        else
+        {
+            Err(AsmError::at(
+                location,
+                format!("{} does not fit in {}", value, what),
+            ))
+        }
+    }
+
+    fn eval(&self, expr: &Expr, depth: usize) -> Result<i64, AsmError>
+    {
+        let value = match expr
+        {
+            Expr::Number(value) => *value,
+            Expr::Symbol(name, location) => match self.symbols.get(name)
+            {
+                Some((Symbol::Label(address), _)) => *address as i64,
+                Some((Symbol::Constant(_), _)) if depth >= MAX_INCLUDE_DEPTH =>
+                {
+                    return Err(AsmError::at(
+                        location,
+                        format!("'{}' is defined in terms of itself", name),
+                    ));
+                }
+                Some((Symbol::Constant(value), _)) => self.eval(value, depth + 1)?,
+                None => return Err(AsmError::at(location, format!("'{}' is not defined", name))),
+            },
+            Expr::Negate(value) => self.eval(value, depth)?.wrapping_neg(),
+            Expr::Binary(left, op, right) =>
+            {
+                let (left, right) = (self.eval(left, depth)?, self.eval(right, depth)?);
+                match op
+                {
+                    '+' => left.wrapping_add(right),
+                    '-' => left.wrapping_sub(right),
+                    '*' => left.wrapping_mul(right),
+                    _ => left.checked_div(right).unwrap_or(0),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+const KNOWN_MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "JP", "CALL", "SE", "SNE", "LD",
+    "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "SCD",
+    "SCU", "SAVE", "LOAD", "PLANE",
+];
+
+fn parse_register(name: &str) -> Option<u8>
+{
+    let digit = name.strip_prefix(['V', 'v']).filter(|digit| digit.len() == 1)?;
+    u8::from_str_radix(digit, 16).ok()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token
+{
+    Ident(String),
+    Number(i64),
+    Text(String),
+    Colon,
+    Comma,
+    /// One of `+ - * / ( ) [ ]`.
+    Punct(char),
+}
+
+/// The tokens of one line, with the column each starts at.
+struct Tokens
+{
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    line: Location,
+}
+
+impl Tokens
+{
+    fn new(text: &str, line: Location) -> Result<Self, AsmError>
+    {
+        let chars: Vec<char> = text.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len()
+        {
+            let c = chars[i];
+            let start = i;
+            let error = |message: String| {
+                AsmError::at(
+                    &Location {
+                        column: start + 1,
+                        ..line.clone()
+                    },
+                    message,
+                )
+            };
+            let token = match c
+            {
+                ';' => break,
+                _ if c.is_whitespace() =>
+                {
+                    i += 1;
+                    continue;
+                }
+                ':' => Token::Colon,
+                ',' => Token::Comma,
+                '+' | '-' | '*' | '/' | '(' | ')' | '[' | ']' => Token::Punct(c),
+                '"' =>
+                {
+                    let mut text = String::new();
+                    i += 1;
+                    loop
+                    {
+                        match chars.get(i)
+                        {
+                            None => return Err(error("unterminated string".into())),
+                            Some('"') => break,
+                            Some('\\') =>
+                            {
+                                i += 1;
+                                match chars.get(i)
+                                {
+                                    Some('n') => text.push('\n'),
+                                    Some(c @ ('"' | '\\')) => text.push(*c),
+                                    _ => return Err(error("invalid escape in string".into())),
+                                }
+                            }
+                            Some(c) => text.push(*c),
+                        }
+                        i += 1;
+                    }
+                    Token::Text(text)
+                }
+                _ if c.is_ascii_alphanumeric() || c == '_' || c == '.' =>
+                {
+                    while chars
+                        .get(i + 1)
+                        .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '.')
+                    {
+                        i += 1;
+                    }
+                    let word: String = chars[start..=i].iter().collect();
+                    if c.is_ascii_digit()
+                    {
+                        Token::Number(
+                            parse_number(&word)
+                                .ok_or_else(|| error(format!("invalid number '{}'", word)))?,
+                        )
+                    }
+                    else
+                    {
+                        Token::Ident(word)
+                    }
+                }
+                _ => return Err(error(format!("unexpected '{}'", c))),
+            };
+            tokens.push((token, start + 1));
+            i += 1;
+        }
+        Ok(Self {
+            tokens,
+            position: 0,
+            line,
+        })
+    }
+
+    fn peek(&self, ahead: usize) -> Option<&Token>
+    {
+        self.tokens.get(self.position + ahead).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token>
+    {
+        let token = self.peek(0).cloned();
+        self.skip(1);
+        token
+    }
+
+    fn skip(&mut self, count: usize)
+    {
+        self.position = (self.position + count).min(self.tokens.len());
+    }
+
+    /// Where the next token starts, or the end of the line.
+    fn location(&self) -> Location
+    {
+        let column = match self.tokens.get(self.position)
+        {
+            Some((_, column)) => *column,
+            None => self.tokens.last().map_or(1, |(_, column)| column + 1),
+        };
+        Location {
+            column,
+            ..self.line.clone()
+        }
+    }
+
+    fn end(&self) -> Result<(), AsmError>
+    {
+        match self.peek(0)
+        {
+            None => Ok(()),
+            Some(_) => Err(AsmError::at(
+                &self.location(),
+                "expected the end of the line".into(),
+            )),
+        }
+    }
+
+    /// Reads comma-separated items up to the end of the line, telling `item`
+    /// where each one starts.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self, Location) -> Result<T, AsmError>,
+    ) -> Result<Vec<T>, AsmError>
+    {
+        let mut items = Vec::new();
+        if self.peek(0).is_none()
+        {
+            return Ok(items);
+        }
+        loop
+        {
+            let location = self.location();
+            items.push(item(self, location)?);
+            match self.next()
+            {
+                None => return Ok(items),
+                Some(Token::Comma) => (),
+                Some(_) =>
+                {
+                    self.position -= 1;
+                    return Err(AsmError::at(&self.location(), "expected ','".into()));
+                }
+            }
+        }
+    }
+
+    fn operand(&mut self, location: Location) -> Result<Operand, AsmError>
+    {
+        match (self.peek(0), self.peek(1), self.peek(2))
+        {
+            (Some(Token::Punct('[')), Some(Token::Ident(i)), Some(Token::Punct(']')))
+                if i.eq_ignore_ascii_case("I") =>
+            {
+                self.skip(3);
+                return Ok(Operand::Special("[I]"));
+            }
+            (Some(Token::Ident(word)), None | Some(Token::Comma), _) =>
+            {
+                if let Some(x) = parse_register(word)
+                {
+                    self.skip(1);
+                    return Ok(Operand::V(x));
+                }
+                if let Some(special) =
+                    SPECIAL_OPERANDS.iter().find(|special| special.eq_ignore_ascii_case(word))
+                {
+                    self.skip(1);
+                    return Ok(Operand::Special(special));
+                }
+            }
+            (Some(Token::Ident(word)), Some(_), _) if word.eq_ignore_ascii_case("LONG") =>
+            {
+                self.skip(1);
+                let location = self.location();
+                return Ok(Operand::Long(self.expr()?, location));
+            }
+            _ => (),
+        }
+        Ok(Operand::Value(self.expr()?, location))
+    }
+
+    fn expr(&mut self) -> Result<Expr, AsmError>
+    {
+        let mut left = self.term()?;
+        while let Some(Token::Punct(op @ ('+' | '-'))) = self.peek(0).cloned()
+        {
+            self.skip(1);
+            left = Expr::Binary(Box::new(left), op, Box::new(self.term()?));
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<Expr, AsmError>
+    {
+        let mut left = self.unary()?;
+        while let Some(Token::Punct(op @ ('*' | '/'))) = self.peek(0).cloned()
+        {
+            self.skip(1);
+            left = Expr::Binary(Box::new(left), op, Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, AsmError>
+    {
+        let location = self.location();
+        match self.next()
+        {
+            Some(Token::Punct('-')) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Punct('(')) =>
+            {
+                let value = self.expr()?;
+                match self.next()
+                {
+                    Some(Token::Punct(')')) => Ok(value),
+                    _ => Err(AsmError::at(&location, "unclosed '('".into())),
+                }
+            }
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Ident(name)) => Ok(Expr::Symbol(name, location)),
+            _ => Err(AsmError::at(&location, "expected a value".into())),
+        }
+    }
+}
+
+/// Reads decimal, `0x` hexadecimal or `0b` binary.
+fn parse_number(word: &str) -> Option<i64>
+{
+    let lower = word.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x")
+    {
+        i64::from_str_radix(hex, 16).ok()
+    }
+    else if let Some(binary) = lower.strip_prefix("0b")
+    {
+        i64::from_str_radix(binary, 2).ok()
+    }
+    else
+    {
+        lower.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::disasm::{self, Syntax};
+    use crate::instruction::Instruction;
+
+    const SOURCE: &str = r#"
+        ; Bounce a sprite across the top row.
+        SPEED equ 2
+        start:  LD I, sprite
+                LD V0, 0
+        loop:   DRW V0, V1, sprite_end - sprite
+                ADD V0, SPEED * 2 - 1
+                SE V0, -1
+                JP loop
+                LD I, LONG 0x1234
+                SHR V2
+                LD [I], V3
+        sprite: db 0b11110000, 0x90, "AB"
+        sprite_end:
+                dw start
+    "#;
+
+    /// The line, column and message of the error assembling `source`.
+    fn error(source: &str) -> (usize, usize, String)
+    {
+        match assemble(source, 0x200)
+        {
+            Err(AsmError::Source { location, message }) =>
+            {
+                (location.line, location.column, message)
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn assemble_test()
+    {
+        assert_eq!(
+            assemble(SOURCE, 0x200).unwrap(),
+            [
+                0xA2, 0x14, 0x60, 0x00, 0xD0, 0x14, 0x70, 0x03, 0x30, 0xFF, 0x12, 0x04, 0xF0, 0x00,
+                0x12, 0x34, 0x82, 0x26, 0xF3, 0x55, 0xF0, 0x90, b'A', b'B', 0x02, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn disassembly_round_trip_test()
+    {
+        // Disassembling and assembling again gives back the same bytes.
+        let rom = assemble(SOURCE, 0x200).unwrap();
+        let lines = disasm::disassemble(&rom, 0x200);
+        let listing = disasm::source(&lines, Syntax::Cowgod);
+        assert_eq!(assemble(&listing, 0x200).unwrap(), rom);
+    }
+
+    #[test]
+    fn every_opcode_round_trip_test()
+    {
+        // Every opcode is written in a form the assembler reads back.
+        let mut listing = String::new();
+        let mut words = Vec::new();
+        for word in 0..=u16::MAX
+        {
+            let instruction = Instruction::new(word.to_be_bytes());
+            if let (Some(op), false) = (instruction.decode(), instruction.is_long_load())
+            {
+                assert_eq!(op.encode(), word.to_be_bytes());
+                listing.push_str(&disasm::cowgod(op));
+                listing.push('\n');
+                words.extend(word.to_be_bytes());
+            }
+        }
+        assert_eq!(assemble(&listing, 0x200).unwrap(), words);
+    }
+
+    #[test]
+    fn byte_out_of_range_test()
+    {
+        assert_eq!(
+            error("CLS\n  LD V3, 0x1FF"),
+            (2, 10, "511 does not fit in a byte".to_string())
+        );
+    }
+
+    #[test]
+    fn undefined_label_test()
+    {
+        assert_eq!(
+            error("JP nowhere"),
+            (1, 4, "'nowhere' is not defined".to_string())
+        );
+    }
+
+    #[test]
+    fn duplicate_label_test()
+    {
+        assert_eq!(
+            error("a:\na: CLS"),
+            (2, 1, "'a' is already defined at <source>:1:1".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_operands_test()
+    {
+        assert_eq!(
+            error("DRW V0, 5"),
+            (1, 1, "invalid operands for DRW".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_instruction_test()
+    {
+        assert_eq!(
+            error("MOV V0, V1"),
+            (1, 1, "unknown instruction 'MOV'".to_string())
+        );
+    }
+
+    #[test]
+    fn unterminated_string_test()
+    {
+        assert_eq!(
+            error("db \"open"),
+            (1, 4, "unterminated string".to_string())
+        );
+    }
+
+    #[test]
+    fn negate_overflow_test()
+    {
+        // Negating the most negative number wraps like the other operators.
+        let error = error("LD V0, -(-9223372036854775807 - 1)");
+        assert_eq!(error.2, "-9223372036854775808 does not fit in a byte");
+    }
+
+    #[test]
+    fn include_test()
+    {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("main.asm"), "include \"lib/font.asm\"\nJP 0x200\n").unwrap();
+        std::fs::write(dir.join("lib/font.asm"), "font: db 0xF0\n").unwrap();
+        let rom = assemble_file(&dir.join("main.asm"), 0x200);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rom.unwrap(), [0xF0, 0x12, 0x00]);
+    }
+}
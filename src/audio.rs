@@ -0,0 +1,152 @@
+use std::io::{self, Write};
+
+/// Bytes in an XO-CHIP audio pattern; played back as 128 one-bit samples.
+pub const PATTERN_SIZE: usize = 16;
+/// The pitch register value that plays the pattern at 4000 bits per second.
+pub const DEFAULT_PITCH: u8 = 64;
+/// The buzzer used until a program loads its own pattern with `F002`: a
+/// square wave of 250 Hz at the default pitch.
+pub const DEFAULT_PATTERN: [u8; PATTERN_SIZE] = [
+    0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF,
+];
+
+const AMPLITUDE: i16 = i16::MAX / 4;
+
+/// Everything needed to render the machine's sound output.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AudioState
+{
+    pub pattern: [u8; PATTERN_SIZE],
+    pub pitch: u8,
+    /// Whether the sound timer is running.
+    pub playing: bool,
+}
+
+/// Renders the 1-bit audio pattern to signed 16-bit mono PCM.
+///
+/// The generator keeps its position in the pattern between calls, so a
+/// front-end can ask for one frame's worth of samples at a time and hand them
+/// to a WAV writer or a live audio device.
+pub struct SampleGenerator
+{
+    sample_rate: u32,
+    position: f64,
+}
+
+impl SampleGenerator
+{
+    pub fn new(sample_rate: u32) -> Self
+    {
+        Self {
+            sample_rate,
+            position: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32
+    {
+        self.sample_rate
+    }
+
+    /// Pattern bits played per second for a given pitch register value.
+    pub fn playback_rate(pitch: u8) -> f64
+    {
+        4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+    }
+
+    /// Fills `out` with samples; silence while the sound timer is zero.
+    pub fn generate(&mut self, state: &AudioState, out: &mut [i16])
+    {
+        if !state.playing
+        {
+            out.fill(0);
+            self.position = 0.0;
+            return;
+        }
+        let step = Self::playback_rate(state.pitch) / self.sample_rate as f64;
+        let pattern_bits = (PATTERN_SIZE * 8) as f64;
+        for sample in out.iter_mut()
+        {
+            let bit = self.position as usize;
+            let lit = state.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *sample = if lit { AMPLITUDE } else { -AMPLITUDE };
+            self.position = (self.position + step) % pattern_bits;
+        }
+    }
+}
+
+/// Writes `samples` as a 16-bit mono PCM WAV file.
+pub fn write_wav<W: Write>(writer: &mut W, sample_rate: u32, samples: &[i16]) -> io::Result<()>
+{
+    let data_len = (samples.len() * 2) as u32;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    for sample in samples
+    {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn pattern_state(playing: bool) -> AudioState
+    {
+        let mut pattern = [0; PATTERN_SIZE];
+        pattern[0] = 0xF0;
+        AudioState {
+            pattern,
+            pitch: 112,
+            playing,
+        }
+    }
+
+    #[test]
+    fn playback_rate_test()
+    {
+        // Pitch 112 is one octave up: 8000 pattern bits per second.
+        assert_eq!(SampleGenerator::playback_rate(112), 8000.0);
+    }
+
+    #[test]
+    fn generate_pattern_test()
+    {
+        let mut generator = SampleGenerator::new(8000);
+        let mut samples = [0i16; 8];
+        generator.generate(&pattern_state(true), &mut samples);
+        assert!(samples[..4].iter().all(|s| *s > 0));
+        assert!(samples[4..].iter().all(|s| *s < 0));
+    }
+
+    #[test]
+    fn silent_when_not_playing_test()
+    {
+        let mut generator = SampleGenerator::new(8000);
+        let mut samples = [1i16; 8];
+        generator.generate(&pattern_state(false), &mut samples);
+        assert_eq!(samples, [0; 8]);
+    }
+
+    #[test]
+    fn write_wav_test()
+    {
+        let samples = [0i16; 8];
+        let mut wav = Vec::new();
+        write_wav(&mut wav, 8000, &samples).unwrap();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav.len(), 44 + samples.len() * 2);
+    }
+}
@@ -0,0 +1,99 @@
+use std::{env, fs, path::PathBuf, process::ExitCode};
+
+use chip_eight::{
+    asm,
+    disasm::{self, DEFAULT_BASE},
+};
+
+const USAGE: &str = "\
+usage: chip8-asm [OPTIONS] <SOURCE>
+
+options:
+    -o, --output <PATH>    where to write the ROM (default: SOURCE with a .ch8
+                           extension)
+    --base <ADDR>          address the ROM is loaded at, decimal or 0x-prefixed
+                           hex (default: 0x200)
+    -h, --help             print this message";
+
+fn main() -> ExitCode
+{
+    let (source_path, output, base) = match parse_args(env::args().skip(1))
+    {
+        Ok(Some(args)) => args,
+        Ok(None) =>
+        {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) =>
+        {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let rom = match asm::assemble_file(&source_path, base)
+    {
+        Ok(rom) => rom,
+        Err(asm::AsmError::Io(e)) =>
+        {
+            eprintln!("error: could not read '{}': {}", source_path.display(), e);
+            return ExitCode::FAILURE;
+        }
+        Err(e) =>
+        {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let output = output.unwrap_or_else(|| source_path.with_extension("ch8"));
+    if let Err(e) = fs::write(&output, rom)
+    {
+        eprintln!("error: could not write '{}': {}", output.display(), e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// Returns `None` when help was asked for.
+fn parse_args(
+    args: impl Iterator<Item = String>,
+) -> Result<Option<(PathBuf, Option<PathBuf>, u16)>, String>
+{
+    let mut source_path = None;
+    let mut output = None;
+    let mut base = DEFAULT_BASE;
+    let mut args = args;
+    while let Some(arg) = args.next()
+    {
+        let (flag, inline_value) = match arg.split_once('=')
+        {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} expects a value", flag))
+        };
+        match flag.as_str()
+        {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--base" =>
+            {
+                let value = value()?;
+                base = disasm::parse_address(&value)
+                    .ok_or_else(|| format!("invalid value '{}' for --base", value))?;
+            }
+            _ if arg.starts_with('-') && arg != "-" =>
+            {
+                return Err(format!("unknown option '{}'", arg))
+            }
+            _ if source_path.is_some() => return Err(format!("unexpected argument '{}'", arg)),
+            _ => source_path = Some(PathBuf::from(arg)),
+        }
+    }
+    let source_path = source_path.ok_or("no source file given")?;
+    Ok(Some((source_path, output, base)))
+}
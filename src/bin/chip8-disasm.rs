@@ -0,0 +1,100 @@
+use std::{env, fs, process::ExitCode};
+
+use chip_eight::disasm::{self, Syntax, DEFAULT_BASE};
+
+const USAGE: &str = "\
+usage: chip8-disasm [OPTIONS] <ROM>
+
+options:
+    --syntax <NAME>    how to write instructions: cowgod (default), octo, hex
+    --base <ADDR>      address the ROM is loaded at, decimal or 0x-prefixed
+                       hex (default: 0x200)
+    --source           leave out addresses and bytes, so that the cowgod
+                       output can be fed to chip8-asm
+    -h, --help         print this message";
+
+fn main() -> ExitCode
+{
+    let (rom_path, syntax, base, source) = match parse_args(env::args().skip(1))
+    {
+        Ok(Some(args)) => args,
+        Ok(None) =>
+        {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) =>
+        {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let rom = match fs::read(&rom_path)
+    {
+        Ok(rom) => rom,
+        Err(e) =>
+        {
+            eprintln!("error: could not read '{}': {}", rom_path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let lines = disasm::disassemble(&rom, base);
+    if source
+    {
+        print!("{}", disasm::source(&lines, syntax));
+    }
+    else
+    {
+        for line in lines
+        {
+            println!("{}", line.format(syntax));
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Returns `None` when help was asked for.
+fn parse_args(
+    args: impl Iterator<Item = String>,
+) -> Result<Option<(String, Syntax, u16, bool)>, String>
+{
+    let mut source = false;
+    let mut rom_path = None;
+    let mut syntax = Syntax::default();
+    let mut base = DEFAULT_BASE;
+    let mut args = args;
+    while let Some(arg) = args.next()
+    {
+        let (flag, inline_value) = match arg.split_once('=')
+        {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} expects a value", flag))
+        };
+        match flag.as_str()
+        {
+            "-h" | "--help" => return Ok(None),
+            "--source" => source = true,
+            "--syntax" => syntax = value()?.parse().map_err(|e| format!("{}", e))?,
+            "--base" =>
+            {
+                let value = value()?;
+                base = disasm::parse_address(&value)
+                    .ok_or_else(|| format!("invalid value '{}' for --base", value))?;
+            }
+            _ if arg.starts_with('-') && arg != "-" =>
+            {
+                return Err(format!("unknown option '{}'", arg))
+            }
+            _ if rom_path.is_some() => return Err(format!("unexpected argument '{}'", arg)),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let rom_path = rom_path.ok_or("no ROM given")?;
+    Ok(Some((rom_path, syntax, base, source)))
+}
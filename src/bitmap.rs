@@ -0,0 +1,277 @@
+use std::fmt::Write as _;
+
+use crate::guest_graphics::ChipDisplay;
+use crate::host_input::Capabilities;
+use crate::palette::Palette;
+
+/// Screen pixels per CHIP-8 pixel in 64x32 mode. SUPER-CHIP's 128x64 mode
+/// uses half as many so the picture stays the same size.
+pub const DEFAULT_SCALE: u32 = 8;
+/// The largest scale allowed; 16 already makes a 1024x512 picture.
+pub const MAX_SCALE: u32 = 16;
+
+/// How many bytes of base64 go in each kitty graphics escape.
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Ways of sending a picture to the terminal as real pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitmapProtocol
+{
+    Sixel,
+    Kitty,
+}
+
+impl BitmapProtocol
+{
+    /// Whether the terminal said it understands this protocol.
+    pub fn is_supported(self, capabilities: &Capabilities) -> bool
+    {
+        match self
+        {
+            BitmapProtocol::Sixel => capabilities.sixel,
+            BitmapProtocol::Kitty => capabilities.kitty_graphics,
+        }
+    }
+
+    /// The escape sequence that draws `bitmap` at the cursor.
+    pub fn encode(self, bitmap: &Bitmap, palette: &Palette) -> String
+    {
+        match self
+        {
+            BitmapProtocol::Sixel => sixel(bitmap, palette),
+            BitmapProtocol::Kitty => kitty(bitmap, palette),
+        }
+    }
+}
+
+/// The framebuffer scaled up to screen pixels, one pixel value per pixel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bitmap
+{
+    pub width: usize,
+    pub height: usize,
+    /// Row by row, top to bottom.
+    pub pixels: Vec<u8>,
+}
+
+impl Bitmap
+{
+    pub fn from_display(display: &ChipDisplay, scale: u32) -> Self
+    {
+        let scale = if display.hires { scale / 2 } else { scale }.clamp(1, MAX_SCALE) as usize;
+        let (width, height) = (display.width() * scale, display.height() * scale);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                let value = display.get_pixel((x / scale) as u8, (y / scale) as u8);
+                pixels.push(value.unwrap_or(0) & 0b11);
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8
+    {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Encodes `bitmap` as DEC sixel graphics with one colour register per pixel
+/// value.
+pub fn sixel(bitmap: &Bitmap, palette: &Palette) -> String
+{
+    // Every pixel gets painted, so the background never shows through.
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", bitmap.width, bitmap.height);
+    let percent = |channel: u8| (channel as u32 * 100 + 127) / 255;
+    for (register, color) in palette.colors.iter().enumerate()
+    {
+        let _ = write!(
+            out,
+            "#{};2;{};{};{}",
+            register,
+            percent(color.0),
+            percent(color.1),
+            percent(color.2)
+        );
+    }
+    // Each sixel character covers a column of six pixels.
+    for band in (0..bitmap.height).step_by(6)
+    {
+        let rows = band..(band + 6).min(bitmap.height);
+        let mut first_color = true;
+        for value in 0..4
+        {
+            let sixels: Vec<u8> = (0..bitmap.width)
+                .map(|x| {
+                    rows.clone()
+                        .filter(|y| bitmap.pixel(x, *y) == value)
+                        .fold(0, |bits, y| bits | 1 << (y - band))
+                })
+                .collect();
+            if sixels.iter().all(|bits| *bits == 0)
+            {
+                continue;
+            }
+            if !first_color
+            {
+                // Back to the start of the band to paint the next colour.
+                out.push('$');
+            }
+            first_color = false;
+            let _ = write!(out, "#{}", value);
+            push_sixel_runs(&mut out, &sixels);
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Writes sixels with runs of four or more shortened to `!<count><sixel>`.
+fn push_sixel_runs(out: &mut String, sixels: &[u8])
+{
+    let mut rest = sixels;
+    while let Some(&bits) = rest.first()
+    {
+        let run = rest.iter().take_while(|other| **other == bits).count();
+        let ch = (b'?' + bits) as char;
+        if run >= 4
+        {
+            let _ = write!(out, "!{}{}", run, ch);
+        }
+        else
+        {
+            out.extend(std::iter::repeat_n(ch, run));
+        }
+        rest = &rest[run..];
+    }
+}
+
+/// Encodes `bitmap` as 24-bit RGB for the kitty graphics protocol. Image and
+/// placement ids are fixed so each frame replaces the last one.
+pub fn kitty(bitmap: &Bitmap, palette: &Palette) -> String
+{
+    let rgb: Vec<u8> = bitmap
+        .pixels
+        .iter()
+        .flat_map(|value| {
+            let color = palette.color(*value);
+            [color.0, color.1, color.2]
+        })
+        .collect();
+    let data = base64(&rgb);
+    let mut out = String::with_capacity(data.len() + data.len() / KITTY_CHUNK_SIZE * 16 + 64);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+    for (i, chunk) in chunks.iter().enumerate()
+    {
+        let more = (i + 1 < chunks.len()) as u8;
+        out.push_str("\x1b_G");
+        if i == 0
+        {
+            // C=1 leaves the cursor where it was; q=2 turns off replies.
+            let _ = write!(
+                out,
+                "a=T,f=24,s={},v={},i=1,p=1,q=2,C=1,",
+                bitmap.width, bitmap.height
+            );
+        }
+        let _ = write!(out, "m={};", more);
+        // Base64 output is always ASCII.
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\x1b\\");
+    }
+    out
+}
+
+/// Standard base64 with padding.
+pub fn base64(bytes: &[u8]) -> String
+{
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3)
+    {
+        let n = group.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4
+        {
+            if i <= group.len()
+            {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            }
+            else
+            {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn corner_pixel() -> ChipDisplay
+    {
+        let mut display = ChipDisplay::new();
+        display.set_pixel(0, 0, true);
+        display.debuff();
+        display
+    }
+
+    #[test]
+    fn unsupported_without_capabilities_test()
+    {
+        assert!(!BitmapProtocol::Sixel.is_supported(&Capabilities::default()));
+    }
+
+    #[test]
+    fn from_display_test()
+    {
+        let bitmap = Bitmap::from_display(&corner_pixel(), 2);
+        assert_eq!((bitmap.width, bitmap.height), (128, 64));
+        assert_eq!((bitmap.pixel(1, 1), bitmap.pixel(2, 0)), (1, 0));
+    }
+
+    #[test]
+    fn hires_pixels_are_half_size_test()
+    {
+        let mut display = ChipDisplay::new();
+        display.set_hires(true);
+        display.debuff();
+        assert_eq!(Bitmap::from_display(&display, 2).width, 128);
+    }
+
+    #[test]
+    fn sixel_test()
+    {
+        let sixel = sixel(&Bitmap::from_display(&corner_pixel(), 1), &Palette::OCTO);
+        assert!(sixel.starts_with("\x1bP0;1;0q\"1;1;64;32#0;2;60;40;0#1;2;100;80;0"));
+        assert!(sixel.contains("#0}!63~$#1@!63?-#0!64~-"));
+        assert!(sixel.ends_with("#0!64B-\x1b\\"));
+    }
+
+    #[test]
+    fn base64_test()
+    {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+    }
+
+    #[test]
+    fn kitty_test()
+    {
+        let kitty = kitty(&Bitmap::from_display(&corner_pixel(), 4), &Palette::OCTO);
+        assert!(kitty.starts_with("\x1b_Ga=T,f=24,s=256,v=128,i=1,p=1,q=2,C=1,m=1;/8wA"));
+        // 256x128 RGB pixels is 131072 base64 bytes, sent in 32 chunks.
+        assert_eq!(kitty.matches("\x1b_G").count(), 32);
+        assert!(kitty.contains("\x1b_Gm=0;"));
+    }
+}
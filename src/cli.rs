@@ -0,0 +1,347 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use crate::bitmap::MAX_SCALE;
+use crate::headless::{RunLimit, DEFAULT_CYCLES_PER_FRAME};
+use crate::keymap::Layout;
+use crate::palette::ColorMode;
+use crate::quirks::QuirkProfile;
+use crate::renderer::RendererKind;
+
+pub const USAGE: &str = "\
+usage: chip-eight [run] [OPTIONS] [--] <ROM>
+
+ROMs ending in .8o are compiled from Octo source first.
+
+options:
+    --ips <N>                 instructions executed per second
+    --cycles-per-frame <N>    instructions executed per 60 Hz frame
+    --quirks <PROFILE>        compatibility profile: vip (default), chip48, schip,
+                              xochip
+    --headless                run without a terminal and print the final state
+    --frames <N>              number of frames to run in headless mode
+    --cycles <N>              number of instructions to run in headless mode
+    --debug                   start paused at a debugger prompt; type 'help'
+                              there for its commands
+    --seed <N>                seed for the random number generator
+    --scale <N>               screen pixels per CHIP-8 pixel for the sixel and
+                              kitty renderers, 1-16 (default 8, halved in
+                              hi-res)
+    --renderer <NAME>         how to draw the display: half-block (default),
+                              braille, ascii, emoji, sixel, kitty; sixel and
+                              kitty fall back to half-block if unsupported
+    --palette <NAME>          colours: octo, phosphor, amber, high-contrast, or
+                              a palette from the settings file
+    --color <MODE>            colour output: auto, none, 256, truecolor
+    --config <PATH>           settings file with key bindings and palettes
+                              (default: ~/.config/chip-eight/config.toml)
+    --layout <NAME>           keypad layout: qwerty (default), azerty, dvorak
+    --key-hold <MS>           how long a key stays down after a press when the
+                              terminal cannot report key releases
+    --rom-hash                print the hash that names the ROM's [rom.<HASH>]
+                              sections in the settings file, then exit
+    -h, --help                print this message";
+
+const DEFAULT_HEADLESS_FRAMES: usize = 60;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command
+{
+    Run(Options),
+    Help,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Options
+{
+    pub rom_path: PathBuf,
+    pub ips: Option<usize>,
+    pub cycles_per_frame: Option<usize>,
+    pub quirks: Option<QuirkProfile>,
+    pub headless: bool,
+    pub debug: bool,
+    pub frames: Option<usize>,
+    pub cycles: Option<usize>,
+    pub seed: Option<u64>,
+    pub scale: Option<u32>,
+    pub renderer: Option<RendererKind>,
+    pub palette: Option<String>,
+    pub color: Option<ColorMode>,
+    pub config: Option<PathBuf>,
+    pub layout: Option<Layout>,
+    pub key_hold_ms: Option<u64>,
+    pub rom_hash: bool,
+}
+
+impl Options
+{
+    /// Resolves `--cycles-per-frame` and `--ips` into a per-frame budget.
+    pub fn cycles_per_frame(&self) -> usize
+    {
+        if let Some(cycles) = self.cycles_per_frame
+        {
+            cycles
+        }
+        else if let Some(ips) = self.ips
+        {
+            (ips / 60).max(1)
+        }
+        else
+        {
+            DEFAULT_CYCLES_PER_FRAME
+        }
+    }
+
+    pub fn run_limit(&self) -> RunLimit
+    {
+        match (self.cycles, self.frames)
+        {
+            (Some(cycles), _) => RunLimit::Cycles(cycles),
+            (None, Some(frames)) => RunLimit::Frames(frames),
+            (None, None) => RunLimit::Frames(DEFAULT_HEADLESS_FRAMES),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError
+{
+    MissingValue(String),
+    InvalidValue
+    {
+        flag: String,
+        value: String,
+    },
+    UnknownFlag(String),
+    /// A switch such as `--headless` was given `=value`.
+    UnexpectedValue(String),
+    UnexpectedArgument(String),
+    MissingRom,
+}
+
+impl fmt::Display for CliError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            CliError::MissingValue(flag) => write!(f, "{} expects a value", flag),
+            CliError::InvalidValue { flag, value } =>
+            {
+                write!(f, "invalid value '{}' for {}", value, flag)
+            }
+            CliError::UnknownFlag(flag) => write!(f, "unknown option '{}'", flag),
+            CliError::UnexpectedValue(flag) => write!(f, "{} does not take a value", flag),
+            CliError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{}'", arg),
+            CliError::MissingRom => write!(f, "no ROM given"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Parses the process arguments, not including the program name.
+pub fn parse_args<I>(args: I) -> Result<Command, CliError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next()
+    {
+        if arg == "--"
+        {
+            positional.extend(args.by_ref());
+            break;
+        }
+        if !arg.starts_with('-')
+        {
+            positional.push(arg);
+            continue;
+        }
+
+        let (flag, inline_value) = match arg.split_once('=')
+        {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| CliError::MissingValue(flag.clone()))
+        };
+        let is_switch = matches!(
+            flag.as_str(),
+            "-h" | "--help" | "--headless" | "--debug" | "--rom-hash"
+        );
+        if is_switch && inline_value.is_some()
+        {
+            return Err(CliError::UnexpectedValue(flag));
+        }
+        match flag.as_str()
+        {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--headless" => options.headless = true,
+            "--debug" => options.debug = true,
+            "--rom-hash" => options.rom_hash = true,
+            "--ips" => options.ips = Some(parse_value(&flag, value()?)?),
+            "--cycles-per-frame" => options.cycles_per_frame = Some(parse_value(&flag, value()?)?),
+            "--quirks" => options.quirks = Some(parse_value(&flag, value()?)?),
+            "--frames" => options.frames = Some(parse_value(&flag, value()?)?),
+            "--cycles" => options.cycles = Some(parse_value(&flag, value()?)?),
+            "--seed" => options.seed = Some(parse_value(&flag, value()?)?),
+            "--scale" =>
+            {
+                let value = value()?;
+                let scale = parse_value(&flag, value.clone())?;
+                if !(1..=MAX_SCALE).contains(&scale)
+                {
+                    return Err(CliError::InvalidValue { flag, value });
+                }
+                options.scale = Some(scale);
+            }
+            "--renderer" => options.renderer = Some(parse_value(&flag, value()?)?),
+            "--palette" => options.palette = Some(value()?),
+            "--color" => options.color = Some(parse_value(&flag, value()?)?),
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+            "--layout" => options.layout = Some(parse_value(&flag, value()?)?),
+            "--key-hold" => options.key_hold_ms = Some(parse_value(&flag, value()?)?),
+            _ => return Err(CliError::UnknownFlag(flag)),
+        }
+    }
+
+    // `chip-eight run game.8o` reads better in scripts; `run` is optional, so it
+    // is only the subcommand when a ROM follows it.
+    if positional.len() > 1 && positional[0] == "run"
+    {
+        positional.remove(0);
+    }
+    let mut positional = positional.into_iter();
+    let rom_path = positional.next().ok_or(CliError::MissingRom)?;
+    if let Some(arg) = positional.next()
+    {
+        return Err(CliError::UnexpectedArgument(arg));
+    }
+    options.rom_path = PathBuf::from(rom_path);
+    Ok(Command::Run(options))
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: String) -> Result<T, CliError>
+{
+    value.parse().map_err(|_| CliError::InvalidValue {
+        flag: flag.to_string(),
+        value,
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, CliError>
+    {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn options(args: &[&str]) -> Options
+    {
+        match parse(args)
+        {
+            Ok(Command::Run(options)) => options,
+            other => panic!("expected a run command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cli_parse_test()
+    {
+        let options = options(&["--headless", "--frames=10", "--ips", "600", "game.ch8"]);
+        assert!(options.headless);
+        assert_eq!(options.rom_path.to_str(), Some("game.ch8"));
+        assert_eq!(options.cycles_per_frame(), 10);
+        assert_eq!(options.run_limit(), RunLimit::Frames(10));
+    }
+
+    #[test]
+    fn missing_value_test()
+    {
+        assert_eq!(
+            parse(&["--ips"]),
+            Err(CliError::MissingValue("--ips".to_string()))
+        );
+    }
+
+    #[test]
+    fn missing_rom_test()
+    {
+        assert_eq!(parse(&[]), Err(CliError::MissingRom));
+    }
+
+    #[test]
+    fn switch_with_value_test()
+    {
+        assert_eq!(
+            parse(&["--headless=false", "game.ch8"]),
+            Err(CliError::UnexpectedValue("--headless".to_string()))
+        );
+    }
+
+    #[test]
+    fn run_subcommand_test()
+    {
+        assert_eq!(
+            options(&["run", "game.8o"]).rom_path.to_str(),
+            Some("game.8o")
+        );
+        assert_eq!(
+            options(&["--headless", "run", "--frames", "5", "game.8o"]).rom_path.to_str(),
+            Some("game.8o")
+        );
+        assert_eq!(options(&["run"]).rom_path.to_str(), Some("run"));
+        assert_eq!(
+            parse(&["run", "a.ch8", "b.ch8"]),
+            Err(CliError::UnexpectedArgument("b.ch8".to_string()))
+        );
+    }
+
+    #[test]
+    fn end_of_options_test()
+    {
+        assert_eq!(
+            options(&["--", "--headless"]).rom_path.to_str(),
+            Some("--headless")
+        );
+        assert_eq!(options(&["--", "run"]).rom_path.to_str(), Some("run"));
+        assert_eq!(options(&["--", "-"]).rom_path.to_str(), Some("-"));
+        assert_eq!(parse(&["-"]), Err(CliError::UnknownFlag("-".to_string())));
+    }
+
+    #[test]
+    fn rom_hash_test()
+    {
+        assert!(options(&["--rom-hash", "game.ch8"]).rom_hash);
+        assert_eq!(
+            parse(&["--rom-hash=1", "game.ch8"]),
+            Err(CliError::UnexpectedValue("--rom-hash".to_string()))
+        );
+    }
+
+    #[test]
+    fn scale_range_test()
+    {
+        assert_eq!(options(&["--scale", "1", "game.ch8"]).scale, Some(1));
+        assert_eq!(options(&["--scale", "16", "game.ch8"]).scale, Some(16));
+        for scale in ["0", "17", "100000"]
+        {
+            assert_eq!(
+                parse(&["--scale", scale, "game.ch8"]),
+                Err(CliError::InvalidValue {
+                    flag: "--scale".to_string(),
+                    value: scale.to_string(),
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::{collections::HashMap, fmt, fs, io, path::Path};
+
+use crate::keymap::KeymapConfig;
+use crate::palette::{Palette, Rgb};
+
+/// The settings file: key bindings and colour palettes.
+///
+/// The file is a small subset of TOML:
+///
+/// ```toml
+/// layout = "qwerty"
+/// palette = "phosphor"
+///
+/// [keys]
+/// down = 8
+/// up = "5"
+///
+/// [actions]
+/// quit = ["escape", "m"]
+/// pause = "p"
+///
+/// [rom.af63bd4c8601b7df]
+/// layout = "dvorak"
+///
+/// [rom.af63bd4c8601b7df.keys]
+/// i = 0x5
+///
+/// [palettes.sunset]
+/// background = "#2b0f54"
+/// foreground = "#ff4f69"
+/// plane2 = "#ab1f65"
+/// blend = "#fff7f8"
+/// ```
+///
+/// ROM sections are named by [`crate::keymap::rom_hash`] in hex, which
+/// `chip-eight --rom-hash <ROM>` prints.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Config
+{
+    pub keymap: KeymapConfig,
+    /// The palette to use unless one is given on the command line.
+    pub palette: Option<String>,
+    /// Palettes defined in the file, which may shadow the presets.
+    pub palettes: HashMap<String, Palette>,
+}
+
+impl Config
+{
+    pub fn load(path: &Path) -> Result<Self, ConfigError>
+    {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError>
+    {
+        let mut config = Self::default();
+        // Colours of each custom palette, in `Palette::colors` order.
+        let mut palettes: Vec<(String, usize, [Option<Rgb>; 4])> = Vec::new();
+        for entry in read_entries(text)?
+        {
+            match entry.section.as_slice()
+            {
+                [] if entry.name == "palette" =>
+                {
+                    config.palette = Some(parse_string(&entry.value).map_err(|e| entry.error(e))?);
+                }
+                [palettes_section, name] if palettes_section == "palettes" =>
+                {
+                    let slot = ["background", "foreground", "plane2", "blend"]
+                        .iter()
+                        .position(|slot| *slot == entry.name)
+                        .ok_or_else(|| entry.error(format!("unknown colour '{}'", entry.name)))?;
+                    let color = parse_string(&entry.value)
+                        .and_then(|color| color.parse())
+                        .map_err(|e| entry.error(e))?;
+                    let index = match palettes.iter().position(|(n, ..)| n == name)
+                    {
+                        Some(index) => index,
+                        None =>
+                        {
+                            palettes.push((name.clone(), entry.line, [None; 4]));
+                            palettes.len() - 1
+                        }
+                    };
+                    palettes[index].2[slot] = Some(color);
+                }
+                _ => config.keymap.apply(&entry)?,
+            }
+        }
+        for (name, line, colors) in palettes
+        {
+            let [Some(background), Some(foreground), plane2, blend] = colors
+            else
+            {
+                return Err(ConfigError::Parse {
+                    line,
+                    message: format!("palette '{}' needs a background and a foreground", name),
+                });
+            };
+            let palette = Palette {
+                colors: [
+                    background,
+                    foreground,
+                    plane2.unwrap_or(foreground),
+                    blend.unwrap_or(foreground),
+                ],
+            };
+            config.palettes.insert(name, palette);
+        }
+        Ok(config)
+    }
+
+    /// Looks up a palette defined in the file or, failing that, a preset.
+    pub fn find_palette(&self, name: &str) -> Option<Palette>
+    {
+        self.palettes.get(name).copied().or_else(|| Palette::preset(name))
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError
+{
+    Io(io::Error),
+    Parse
+    {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            ConfigError::Io(e) => Some(e),
+            ConfigError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError
+{
+    fn from(e: io::Error) -> Self
+    {
+        ConfigError::Io(e)
+    }
+}
+
+/// One `name = value` line of the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry
+{
+    pub line: usize,
+    /// The dotted name of the enclosing `[section]`, empty at the top level.
+    pub section: Vec<String>,
+    pub name: String,
+    /// The value as written, to be read with [`parse_string`] and friends.
+    pub value: String,
+}
+
+impl Entry
+{
+    pub fn error(&self, message: String) -> ConfigError
+    {
+        ConfigError::Parse {
+            line: self.line,
+            message,
+        }
+    }
+}
+
+/// Splits the file into entries, tracking which section each one is in.
+pub fn read_entries(text: &str) -> Result<Vec<Entry>, ConfigError>
+{
+    let mut entries = Vec::new();
+    let mut section = Vec::new();
+    for (index, line) in text.lines().enumerate()
+    {
+        let error = |message: String| ConfigError::Parse {
+            line: index + 1,
+            message,
+        };
+        let line = strip_comment(line).trim();
+        if line.is_empty()
+        {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[')
+        {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| error("unterminated section header".to_string()))?;
+            section = header.split('.').map(|part| part.trim().to_string()).collect();
+            continue;
+        }
+        let (name, value) = split_assignment(line).map_err(error)?;
+        entries.push(Entry {
+            line: index + 1,
+            section: section.clone(),
+            name,
+            value: value.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Drops a trailing `#` comment, leaving `#` inside quotes alone.
+fn strip_comment(line: &str) -> &str
+{
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices()
+    {
+        match c
+        {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Splits `name = value`, where the name may be quoted.
+fn split_assignment(line: &str) -> Result<(String, &str), String>
+{
+    let (name, rest) = if line.starts_with('"')
+    {
+        let (name, len) = read_string(line)?;
+        (name, &line[len..])
+    }
+    else
+    {
+        let end = line.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(line.len());
+        (line[..end].to_string(), &line[end..])
+    };
+    let value = rest
+        .trim_start()
+        .strip_prefix('=')
+        .ok_or_else(|| format!("expected '=' after '{}'", name))?;
+    Ok((name, value.trim()))
+}
+
+/// Reads a quoted string from the start of `s`, returning it and the number
+/// of bytes it took up.
+fn read_string(s: &str) -> Result<(String, usize), String>
+{
+    let mut value = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next()
+    {
+        match c
+        {
+            '"' => return Ok((value, i + 1)),
+            '\\' => match chars.next().map(|(_, c)| c)
+            {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(c @ ('"' | '\\')) => value.push(c),
+                _ => return Err("invalid escape in string".to_string()),
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+pub fn parse_string(value: &str) -> Result<String, String>
+{
+    if !value.starts_with('"')
+    {
+        return Err(format!("expected a string, found '{}'", value));
+    }
+    let (string, len) = read_string(value)?;
+    if value[len..].trim().is_empty()
+    {
+        Ok(string)
+    }
+    else
+    {
+        Err(format!("unexpected '{}' after string", value[len..].trim()))
+    }
+}
+
+/// Accepts a single string or an array of strings.
+pub fn parse_string_list(value: &str) -> Result<Vec<String>, String>
+{
+    let Some(inner) = value.strip_prefix('[')
+    else
+    {
+        return Ok(vec![parse_string(value)?]);
+    };
+    let mut rest = inner.trim_start();
+    let mut strings = Vec::new();
+    loop
+    {
+        if let Some(after) = rest.strip_prefix(']')
+        {
+            if !after.trim().is_empty()
+            {
+                return Err(format!("unexpected '{}' after array", after.trim()));
+            }
+            return Ok(strings);
+        }
+        if !rest.starts_with('"')
+        {
+            return Err("expected a string or ']' in array".to_string());
+        }
+        let (string, len) = read_string(rest)?;
+        strings.push(string);
+        rest = rest[len..].trim_start();
+        if let Some(after) = rest.strip_prefix(',')
+        {
+            rest = after.trim_start();
+        }
+        else if !rest.starts_with(']')
+        {
+            return Err("expected ',' or ']' in array".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn parse_errors_test()
+    {
+        let error = Config::parse("[keys]\nw = 16\n").unwrap_err();
+        assert!(matches!(error, ConfigError::Parse { line: 2, .. }));
+        assert!(Config::parse("[actions]\nfly = \"f\"").is_err());
+        assert!(Config::parse("layout = \"colemak\"").is_err());
+    }
+
+    #[test]
+    fn palettes_test()
+    {
+        let config = Config::parse(
+            r##"
+            palette = "sunset"
+            [palettes.sunset]
+            background = "#2b0f54"
+            foreground = "#FF4F69"
+            [palettes.amber] # shadows the preset
+            background = "#000000"
+            foreground = "#ffb000"
+            blend = "#ffffff"
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.palette.as_deref(), Some("sunset"));
+        let sunset = config.find_palette("sunset").unwrap();
+        assert_eq!(sunset.colors[1], Rgb(0xff, 0x4f, 0x69));
+        assert_eq!(sunset.colors[2], sunset.colors[1]);
+        assert_eq!(
+            config.find_palette("amber").unwrap().color(3),
+            Rgb(255, 255, 255)
+        );
+        assert_eq!(config.find_palette("phosphor"), Some(Palette::PHOSPHOR));
+        assert_eq!(config.find_palette("vaporwave"), None);
+    }
+
+    #[test]
+    fn bad_palette_colors_test()
+    {
+        assert!(Config::parse("[palettes.bad]\nforeground = \"#fff\"").is_err());
+        assert!(Config::parse("[palettes.bad]\nforeground = \"#ffffff\"").is_err());
+    }
+}
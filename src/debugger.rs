@@ -0,0 +1,1372 @@
+use std::{
+    fmt,
+    fmt::Write as _,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::disasm::{self, Line, Syntax};
+use crate::error::Chip8Error;
+use crate::instruction::OpCode;
+use crate::machine::{AccessKind, Chip8, ChipRegisters, MachineState, RegisterName};
+use crate::scheduler::Scheduler;
+
+pub const HELP: &str = "\
+commands:
+    c, continue            run until a breakpoint, a fault or Ctrl-C
+    s, step [N]            execute N instructions (default 1)
+    n, next                step, running a whole subroutine if this is a call
+    finish                 run until the current subroutine returns
+    b, break <ADDR>        stop when the PC reaches ADDR
+    b, break <REG> <OP> <N>
+                           stop when a register comparison becomes true, e.g.
+                           'break v3 == 0x10'; OP is one of == != < <= > >=
+    watch <ADDR> [LEN]     stop when an instruction writes to LEN bytes of
+                           memory at ADDR (default 1)
+    rwatch <ADDR> [LEN]    stop when an instruction reads them
+    awatch <ADDR> [LEN]    stop when an instruction reads or writes them
+    watch|rwatch|awatch <REG>
+                           the same for v0-vf, i, sp, dt or st; a write counts
+                           even if it leaves the value unchanged
+    breakpoints            list breakpoints and watchpoints
+    delete <NUMBER>        remove a breakpoint or watchpoint
+    regs                   show the registers and the stack
+    mem <ADDR> [LEN]       dump LEN bytes of memory (default 16)
+    disasm [ADDR] [COUNT]  disassemble COUNT instructions (default: 8 at the PC)
+    set <REG> <N>          change v0-vf, i, pc, sp, dt or st
+    key <KEY> [up]         press or release a keypad key
+    screen                 show the display
+    help                   print this message
+    q, quit                leave the debugger
+
+Numbers are decimal or hex with 0x. An empty line repeats the last command.";
+
+/// Instructions shown by `disasm` when no count is given.
+const DEFAULT_DISASM_COUNT: usize = 8;
+/// Bytes shown by `mem` when no length is given.
+const DEFAULT_MEM_LEN: usize = 16;
+const MEM_BYTES_PER_LINE: usize = 16;
+
+/// A register that can be read, compared and set from the debugger.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Register
+{
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Delay,
+    Sound,
+}
+
+impl Register
+{
+    pub fn get(self, registers: &ChipRegisters) -> u16
+    {
+        match self
+        {
+            Register::V(x) => registers.v[x as usize] as u16,
+            Register::I => registers.i,
+            Register::Pc => registers.pc,
+            Register::Sp => registers.sp as u16,
+            Register::Delay => registers.delay as u16,
+            Register::Sound => registers.sound as u16,
+        }
+    }
+
+    /// How the machine records accesses to this register. The program
+    /// counter is not recorded.
+    pub fn name(self) -> Option<RegisterName>
+    {
+        match self
+        {
+            Register::V(x) => Some(RegisterName::V(x)),
+            Register::I => Some(RegisterName::I),
+            Register::Pc => None,
+            Register::Sp => Some(RegisterName::Sp),
+            Register::Delay => Some(RegisterName::Delay),
+            Register::Sound => Some(RegisterName::Sound),
+        }
+    }
+
+    /// Stores `value`, or returns the largest value that would fit.
+    pub fn set(self, registers: &mut ChipRegisters, value: u16) -> Result<(), u16>
+    {
+        let max = match self
+        {
+            Register::I | Register::Pc => u16::MAX,
+            Register::Sp => registers.stack.len() as u16,
+            _ => u8::MAX as u16,
+        };
+        if value > max
+        {
+            return Err(max);
+        }
+        match self
+        {
+            Register::V(x) => registers.v[x as usize] = value as u8,
+            Register::I => registers.i = value,
+            Register::Pc => registers.pc = value,
+            Register::Sp => registers.sp = value as u8,
+            Register::Delay => registers.delay = value as u8,
+            Register::Sound => registers.sound = value as u8,
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Register
+{
+    type Err = DebugError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let name = s.to_ascii_lowercase();
+        let register = match name.as_str()
+        {
+            "i" => Register::I,
+            "pc" => Register::Pc,
+            "sp" => Register::Sp,
+            "dt" | "delay" => Register::Delay,
+            "st" | "sound" => Register::Sound,
+            _ => name
+                .strip_prefix('v')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                .map(Register::V)
+                .ok_or_else(|| DebugError::UnknownRegister(s.to_string()))?,
+        };
+        Ok(register)
+    }
+}
+
+impl fmt::Display for Register
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Register::V(x) => write!(f, "v{:x}", x),
+            Register::I => f.write_str("i"),
+            Register::Pc => f.write_str("pc"),
+            Register::Sp => f.write_str("sp"),
+            Register::Delay => f.write_str("dt"),
+            Register::Sound => f.write_str("st"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Comparison
+{
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison
+{
+    pub const NAMES: &'static [&'static str] = &["==", "!=", "<", "<=", ">", ">="];
+
+    pub fn holds(self, left: u16, right: u16) -> bool
+    {
+        match self
+        {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+        }
+    }
+}
+
+impl FromStr for Comparison
+{
+    type Err = DebugError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s
+        {
+            "==" => Ok(Comparison::Eq),
+            "!=" => Ok(Comparison::Ne),
+            "<" => Ok(Comparison::Lt),
+            "<=" => Ok(Comparison::Le),
+            ">" => Ok(Comparison::Gt),
+            ">=" => Ok(Comparison::Ge),
+            _ => Err(DebugError::UnknownComparison(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Comparison
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.write_str(match self
+        {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        })
+    }
+}
+
+/// Which memory accesses a watchpoint stops on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Watch
+{
+    Read,
+    Write,
+    Access,
+}
+
+impl Watch
+{
+    pub fn matches(self, kind: AccessKind) -> bool
+    {
+        match self
+        {
+            Watch::Read => kind == AccessKind::Read,
+            Watch::Write => kind == AccessKind::Write,
+            Watch::Access => true,
+        }
+    }
+
+    fn describe(self) -> &'static str
+    {
+        match self
+        {
+            Watch::Read => "reads of",
+            Watch::Write => "writes to",
+            Watch::Access => "accesses to",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint
+{
+    /// Stops before the instruction at this address runs.
+    Address(u16),
+    /// Stops after an instruction makes the comparison true.
+    Condition
+    {
+        register: Register,
+        comparison: Comparison,
+        value: u16,
+    },
+    /// Stops after an instruction touches any of the `len` bytes at `start`.
+    Memory
+    {
+        start: u16, len: u16, watch: Watch
+    },
+    /// Stops after an instruction reads or writes the register.
+    Register
+    {
+        register: Register, watch: Watch
+    },
+}
+
+impl Breakpoint
+{
+    pub fn is_watchpoint(&self) -> bool
+    {
+        matches!(
+            self,
+            Breakpoint::Memory { .. } | Breakpoint::Register { .. }
+        )
+    }
+
+    fn noun(&self) -> &'static str
+    {
+        if self.is_watchpoint()
+        {
+            "watchpoint"
+        }
+        else
+        {
+            "breakpoint"
+        }
+    }
+
+    fn condition_holds(&self, registers: &ChipRegisters) -> bool
+    {
+        match *self
+        {
+            Breakpoint::Address(_) | Breakpoint::Memory { .. } | Breakpoint::Register { .. } =>
+            {
+                false
+            }
+            Breakpoint::Condition {
+                register,
+                comparison,
+                value,
+            } => comparison.holds(register.get(registers), value),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Breakpoint::Address(address) => write!(f, "pc == 0x{:03X}", address),
+            Breakpoint::Condition {
+                register,
+                comparison,
+                value,
+            } => write!(f, "{} {} 0x{:02X}", register, comparison, value),
+            Breakpoint::Memory { start, len, watch } =>
+            {
+                let end = *start as usize + *len as usize;
+                write!(
+                    f,
+                    "{} {}",
+                    watch.describe(),
+                    format_range(*start as usize..end)
+                )
+            }
+            Breakpoint::Register { register, watch } =>
+            {
+                write!(f, "{} {}", watch.describe(), register)
+            }
+        }
+    }
+}
+
+/// A line typed at the debugger prompt.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugCommand
+{
+    Continue,
+    Step(usize),
+    Next,
+    Finish,
+    Break(Breakpoint),
+    Breakpoints,
+    Delete(usize),
+    Regs,
+    Mem
+    {
+        address: u16,
+        len: usize,
+    },
+    Disasm
+    {
+        address: Option<u16>,
+        count: usize,
+    },
+    Set
+    {
+        register: Register,
+        value: u16,
+    },
+    Key
+    {
+        key: u8,
+        down: bool,
+    },
+    Screen,
+    Help,
+    Quit,
+}
+
+impl FromStr for DebugCommand
+{
+    type Err = DebugError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first()
+        else
+        {
+            return Err(DebugError::Empty);
+        };
+        let arg = |index: usize| {
+            args.get(index).copied().ok_or_else(|| DebugError::MissingArgument(name.to_string()))
+        };
+        let optional = |index: usize| args.get(index).map(|arg| parse_number(arg)).transpose();
+        let (command, expected_args) = match name
+        {
+            "c" | "continue" => (DebugCommand::Continue, 0),
+            "s" | "step" => (DebugCommand::Step(optional(0)?.unwrap_or(1) as usize), 1),
+            "n" | "next" => (DebugCommand::Next, 0),
+            "finish" => (DebugCommand::Finish, 0),
+            "b" | "break" if args.len() > 1 =>
+            {
+                let breakpoint = Breakpoint::Condition {
+                    register: arg(0)?.parse()?,
+                    comparison: arg(1)?.parse()?,
+                    value: parse_number(arg(2)?)?,
+                };
+                (DebugCommand::Break(breakpoint), 3)
+            }
+            "b" | "break" =>
+            {
+                let address = parse_number(arg(0)?)?;
+                (DebugCommand::Break(Breakpoint::Address(address)), 1)
+            }
+            "watch" | "rwatch" | "awatch" =>
+            {
+                let target = arg(0)?;
+                let watch = match name
+                {
+                    "rwatch" => Watch::Read,
+                    "awatch" => Watch::Access,
+                    _ => Watch::Write,
+                };
+                if let Ok(register) = target.parse::<Register>()
+                {
+                    if register == Register::Pc
+                    {
+                        return Err(DebugError::UnwatchableRegister(target.to_string()));
+                    }
+                    (
+                        DebugCommand::Break(Breakpoint::Register { register, watch }),
+                        1,
+                    )
+                }
+                else
+                {
+                    let start = parse_number(target)?;
+                    let len = optional(1)?.unwrap_or(1);
+                    if len == 0
+                    {
+                        return Err(DebugError::InvalidNumber(arg(1)?.to_string()));
+                    }
+                    (
+                        DebugCommand::Break(Breakpoint::Memory { start, len, watch }),
+                        2,
+                    )
+                }
+            }
+            "breakpoints" => (DebugCommand::Breakpoints, 0),
+            "delete" => (DebugCommand::Delete(parse_number(arg(0)?)? as usize), 1),
+            "regs" => (DebugCommand::Regs, 0),
+            "mem" =>
+            {
+                let address = parse_number(arg(0)?)?;
+                let len = optional(1)?.map_or(DEFAULT_MEM_LEN, |len| len as usize);
+                (DebugCommand::Mem { address, len }, 2)
+            }
+            "disasm" =>
+            {
+                let address = optional(0)?;
+                let count = optional(1)?.map_or(DEFAULT_DISASM_COUNT, |count| count as usize);
+                (DebugCommand::Disasm { address, count }, 2)
+            }
+            "set" =>
+            {
+                let register = arg(0)?.parse()?;
+                let value = parse_number(arg(1)?)?;
+                (DebugCommand::Set { register, value }, 2)
+            }
+            "key" =>
+            {
+                let key = parse_number(arg(0)?)?;
+                if key > 0xF
+                {
+                    return Err(DebugError::InvalidNumber(arg(0)?.to_string()));
+                }
+                let down = match args.get(1)
+                {
+                    None | Some(&"down") => true,
+                    Some(&"up") => false,
+                    Some(other) => return Err(DebugError::UnexpectedArgument(other.to_string())),
+                };
+                (
+                    DebugCommand::Key {
+                        key: key as u8,
+                        down,
+                    },
+                    2,
+                )
+            }
+            "screen" => (DebugCommand::Screen, 0),
+            "h" | "help" => (DebugCommand::Help, 0),
+            "q" | "quit" => (DebugCommand::Quit, 0),
+            _ => return Err(DebugError::UnknownCommand(name.to_string())),
+        };
+        if let Some(extra) = args.get(expected_args)
+        {
+            return Err(DebugError::UnexpectedArgument(extra.to_string()));
+        }
+        Ok(command)
+    }
+}
+
+/// Reads `0x1F` as hex and anything else as decimal.
+fn parse_number(s: &str) -> Result<u16, DebugError>
+{
+    disasm::parse_address(s).ok_or_else(|| DebugError::InvalidNumber(s.to_string()))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DebugError
+{
+    Empty,
+    UnknownCommand(String),
+    UnknownRegister(String),
+    UnknownComparison(String),
+    MissingArgument(String),
+    UnexpectedArgument(String),
+    InvalidNumber(String),
+    UnwatchableRegister(String),
+}
+
+impl fmt::Display for DebugError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            DebugError::Empty => write!(f, "no command given"),
+            DebugError::UnknownCommand(name) =>
+            {
+                write!(f, "unknown command '{}'; type 'help' for a list", name)
+            }
+            DebugError::UnknownRegister(name) =>
+            {
+                write!(
+                    f,
+                    "unknown register '{}', expected v0-vf, i, pc, sp, dt or st",
+                    name
+                )
+            }
+            DebugError::UnknownComparison(op) => write!(
+                f,
+                "unknown comparison '{}', expected one of: {}",
+                op,
+                Comparison::NAMES.join(" ")
+            ),
+            DebugError::MissingArgument(command) => write!(f, "{} needs more arguments", command),
+            DebugError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{}'", arg),
+            DebugError::InvalidNumber(s) => write!(f, "invalid number '{}'", s),
+            DebugError::UnwatchableRegister(name) => write!(
+                f,
+                "{} changes with every instruction; use 'break' instead",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DebugError {}
+
+/// Why the debugger gave control back to the user.
+#[derive(Debug)]
+pub enum Stop
+{
+    /// The requested instructions ran.
+    Done,
+    /// The breakpoint with this index was hit.
+    Breakpoint(usize),
+    /// The watchpoint with this index was set off by the `trigger`
+    /// instruction, as it was before it ran; `detail` says how.
+    Watchpoint
+    {
+        index: usize,
+        trigger: Line,
+        detail: String,
+    },
+    Halted,
+    WaitingForKey,
+    Fault(Chip8Error),
+    /// The interrupt flag was raised, usually by Ctrl-C.
+    Interrupted,
+}
+
+/// Runs a [`Chip8`] under user control through [`Chip8::step`].
+///
+/// Timers tick every `cycles_per_frame` instructions as they would in a
+/// normal run, so programs see the same timing, just not in real time.
+pub struct Debugger
+{
+    pub breakpoints: Vec<Breakpoint>,
+    scheduler: Scheduler,
+    interrupt: Arc<AtomicBool>,
+}
+
+impl Debugger
+{
+    pub fn new(cycles_per_frame: usize) -> Self
+    {
+        Self {
+            breakpoints: Vec::new(),
+            scheduler: Scheduler::new(cycles_per_frame),
+            interrupt: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Setting this flag pauses a running program at the next instruction.
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool>
+    {
+        self.interrupt.clone()
+    }
+
+    /// Executes one instruction, ignoring breakpoints.
+    pub fn step(&mut self, machine: &mut Chip8) -> Stop
+    {
+        self.run_until(machine, false, |_| true)
+    }
+
+    /// Like [`Debugger::step`], except that a call runs until it returns.
+    pub fn step_over(&mut self, machine: &mut Chip8) -> Stop
+    {
+        let line = disasm::line_at(&machine.ram, machine.registers.pc);
+        if !matches!(line.op, Some(OpCode::Call { .. }))
+        {
+            return self.step(machine);
+        }
+        let sp = machine.registers.sp;
+        let return_address = machine.registers.pc.wrapping_add(2);
+        self.run_until(machine, true, |machine| {
+            machine.registers.sp == sp && machine.registers.pc == return_address
+        })
+    }
+
+    /// Runs until the subroutine the machine is in returns to its caller.
+    pub fn finish(&mut self, machine: &mut Chip8) -> Stop
+    {
+        let sp = machine.registers.sp;
+        self.run_until(machine, true, |machine| machine.registers.sp < sp)
+    }
+
+    /// Runs until a breakpoint is hit or something else stops the machine.
+    pub fn resume(&mut self, machine: &mut Chip8) -> Stop
+    {
+        self.run_until(machine, true, |_| false)
+    }
+
+    fn run_until(
+        &mut self,
+        machine: &mut Chip8,
+        use_breakpoints: bool,
+        mut done: impl FnMut(&Chip8) -> bool,
+    ) -> Stop
+    {
+        self.interrupt.store(false, Ordering::SeqCst);
+        let mut held: Vec<bool> = self
+            .breakpoints
+            .iter()
+            .map(|breakpoint| breakpoint.condition_holds(&machine.registers))
+            .collect();
+        let watching =
+            use_breakpoints && self.breakpoints.iter().any(|breakpoint| breakpoint.is_watchpoint());
+        loop
+        {
+            match machine.state
+            {
+                MachineState::Halted => return Stop::Halted,
+                // Nothing can run until a key is pressed with `key`.
+                MachineState::WaitingForKey { .. } => return Stop::WaitingForKey,
+                MachineState::Running => (),
+            }
+            // A draw under the display_wait quirk stalls the machine until
+            // the next frame. Run out this frame, as a normal run would, so
+            // that the step executes an instruction.
+            if machine.is_waiting_for_vblank()
+            {
+                loop
+                {
+                    match self.scheduler.step(machine)
+                    {
+                        Ok(false) => (),
+                        Ok(true) => break,
+                        Err(fault) => return Stop::Fault(fault),
+                    }
+                }
+                self.scheduler.end_frame(machine);
+            }
+            // Watchpoints compare against the state before the instruction.
+            let before = watching.then(|| {
+                let trigger = disasm::line_at(&machine.ram, machine.registers.pc);
+                (machine.registers.clone(), trigger)
+            });
+            let frame_done = match self.scheduler.step(machine)
+            {
+                Ok(frame_done) => frame_done,
+                Err(fault) => return Stop::Fault(fault),
+            };
+            let watch_stop = before
+                .and_then(|(registers, trigger)| self.watch_hit(machine, &registers, trigger));
+            // Timers tick after the check, so that only changes made by the
+            // instruction count.
+            if frame_done
+            {
+                self.scheduler.end_frame(machine);
+            }
+            if let Some(stop) = watch_stop
+            {
+                return stop;
+            }
+            if done(machine)
+            {
+                return Stop::Done;
+            }
+            if use_breakpoints
+            {
+                for (index, breakpoint) in self.breakpoints.iter().enumerate()
+                {
+                    let hit = match breakpoint
+                    {
+                        Breakpoint::Address(address) => machine.registers.pc == *address,
+                        // Only the change from false to true counts, so
+                        // that resuming does not stop straight away.
+                        Breakpoint::Condition { .. } =>
+                        {
+                            let holds = breakpoint.condition_holds(&machine.registers);
+                            !std::mem::replace(&mut held[index], holds) && holds
+                        }
+                        Breakpoint::Memory { .. } | Breakpoint::Register { .. } => false,
+                    };
+                    if hit
+                    {
+                        return Stop::Breakpoint(index);
+                    }
+                }
+            }
+            match machine.state
+            {
+                MachineState::Halted => return Stop::Halted,
+                MachineState::WaitingForKey { .. } => return Stop::WaitingForKey,
+                MachineState::Running => (),
+            }
+            if self.interrupt.load(Ordering::SeqCst)
+            {
+                return Stop::Interrupted;
+            }
+        }
+    }
+
+    /// The first watchpoint set off by the instruction that just ran.
+    fn watch_hit(&self, machine: &Chip8, before: &ChipRegisters, trigger: Line) -> Option<Stop>
+    {
+        for (index, breakpoint) in self.breakpoints.iter().enumerate()
+        {
+            let detail = match *breakpoint
+            {
+                Breakpoint::Memory { start, len, watch } =>
+                {
+                    let watched = start as usize..start as usize + len as usize;
+                    machine
+                        .last_accesses()
+                        .iter()
+                        .find(|access| {
+                            watch.matches(access.kind)
+                                && access.range.start < watched.end
+                                && watched.start < access.range.end
+                        })
+                        .map(|access| {
+                            let verb = match access.kind
+                            {
+                                AccessKind::Read => "read",
+                                AccessKind::Write => "wrote",
+                            };
+                            format!("{} {}", verb, format_range(access.range.clone()))
+                        })
+                }
+                Breakpoint::Register { register, watch } =>
+                {
+                    let name = register.name();
+                    machine
+                        .last_register_accesses()
+                        .iter()
+                        .find(|access| watch.matches(access.kind) && Some(access.register) == name)
+                        .map(|access| {
+                            let old = register.get(before);
+                            match access.kind
+                            {
+                                AccessKind::Read => format!("read {} = 0x{:02X}", register, old),
+                                AccessKind::Write => format!(
+                                    "wrote {} = 0x{:02X}, was 0x{:02X}",
+                                    register,
+                                    register.get(&machine.registers),
+                                    old
+                                ),
+                            }
+                        })
+                }
+                Breakpoint::Address(_) | Breakpoint::Condition { .. } => None,
+            };
+            if let Some(detail) = detail
+            {
+                return Some(Stop::Watchpoint {
+                    index,
+                    trigger,
+                    detail,
+                });
+            }
+        }
+        None
+    }
+
+    /// Carries out `command` and returns what to show the user. `Quit` is
+    /// left to the caller.
+    pub fn execute(&mut self, machine: &mut Chip8, command: DebugCommand) -> String
+    {
+        let mut out = String::new();
+        match command
+        {
+            DebugCommand::Continue =>
+            {
+                let stop = self.resume(machine);
+                self.report(machine, &stop, &mut out);
+            }
+            DebugCommand::Step(count) =>
+            {
+                let mut stop = Stop::Done;
+                for _ in 0..count
+                {
+                    stop = self.step(machine);
+                    if !matches!(stop, Stop::Done)
+                    {
+                        break;
+                    }
+                }
+                self.report(machine, &stop, &mut out);
+            }
+            DebugCommand::Next =>
+            {
+                let stop = self.step_over(machine);
+                self.report(machine, &stop, &mut out);
+            }
+            DebugCommand::Finish if machine.registers.sp == 0 =>
+            {
+                out.push_str("not in a subroutine\n");
+            }
+            DebugCommand::Finish =>
+            {
+                let stop = self.finish(machine);
+                self.report(machine, &stop, &mut out);
+            }
+            DebugCommand::Break(breakpoint) =>
+            {
+                self.breakpoints.push(breakpoint);
+                let _ = writeln!(
+                    out,
+                    "{} {}: {}",
+                    breakpoint.noun(),
+                    self.breakpoints.len(),
+                    breakpoint
+                );
+            }
+            DebugCommand::Breakpoints if self.breakpoints.is_empty() =>
+            {
+                out.push_str("no breakpoints or watchpoints\n");
+            }
+            DebugCommand::Breakpoints =>
+            {
+                for (index, breakpoint) in self.breakpoints.iter().enumerate()
+                {
+                    let _ = writeln!(out, "{}: {}", index + 1, breakpoint);
+                }
+            }
+            DebugCommand::Delete(number) =>
+            {
+                if (1..=self.breakpoints.len()).contains(&number)
+                {
+                    self.breakpoints.remove(number - 1);
+                }
+                else
+                {
+                    let _ = writeln!(out, "no breakpoint or watchpoint {}", number);
+                }
+            }
+            DebugCommand::Regs =>
+            {
+                let registers = &machine.registers;
+                let _ = writeln!(out, "{}", registers);
+                let stack = &registers.stack[..(registers.sp as usize).min(registers.stack.len())];
+                if !stack.is_empty()
+                {
+                    let stack: Vec<String> =
+                        stack.iter().map(|address| format!("{:03X}", address)).collect();
+                    let _ = writeln!(out, "stack: {}", stack.join(" "));
+                }
+            }
+            DebugCommand::Mem { address, len } =>
+            {
+                let start = (address as usize).min(machine.ram.len());
+                let end = start.saturating_add(len).min(machine.ram.len());
+                if start == end
+                {
+                    let _ = writeln!(out, "0x{:03X} is outside of memory", address);
+                }
+                for (row, bytes) in machine.ram[start..end].chunks(MEM_BYTES_PER_LINE).enumerate()
+                {
+                    let bytes: Vec<String> =
+                        bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                    let _ = writeln!(
+                        out,
+                        "{:03X}: {}",
+                        start + row * MEM_BYTES_PER_LINE,
+                        bytes.join(" ")
+                    );
+                }
+            }
+            DebugCommand::Disasm { address, count } =>
+            {
+                let mut address = address.unwrap_or(machine.registers.pc);
+                for _ in 0..count
+                {
+                    if address as usize >= machine.ram.len()
+                    {
+                        break;
+                    }
+                    let line = disasm::line_at(&machine.ram, address);
+                    let _ = writeln!(out, "{}", line.format(Syntax::Cowgod));
+                    address = address.wrapping_add(line.bytes.len().max(1) as u16);
+                }
+            }
+            DebugCommand::Set { register, value } =>
+            {
+                if let Err(max) = register.set(&mut machine.registers, value)
+                {
+                    let _ = writeln!(out, "{} only holds values up to 0x{:X}", register, max);
+                }
+            }
+            DebugCommand::Key { key, down } => machine.set_key(key as usize, down),
+            DebugCommand::Screen =>
+            {
+                for y in 0..machine.display.height() as u8
+                {
+                    for x in 0..machine.display.width() as u8
+                    {
+                        let lit = machine.display.get_pixel(x, y).unwrap_or(0) != 0;
+                        out.push(if lit { '#' } else { '.' });
+                    }
+                    out.push('\n');
+                }
+            }
+            DebugCommand::Help =>
+            {
+                out.push_str(HELP);
+                out.push('\n');
+            }
+            DebugCommand::Quit => (),
+        }
+        out
+    }
+
+    /// Says why the machine stopped and shows the next instruction.
+    fn report(&self, machine: &Chip8, stop: &Stop, out: &mut String)
+    {
+        let _ = match stop
+        {
+            Stop::Done => Ok(()),
+            Stop::Breakpoint(index) =>
+            {
+                writeln!(
+                    out,
+                    "breakpoint {}: {}",
+                    index + 1,
+                    self.breakpoints[*index]
+                )
+            }
+            Stop::Watchpoint {
+                index,
+                trigger,
+                detail,
+            } =>
+            {
+                let _ = writeln!(
+                    out,
+                    "watchpoint {}: {}",
+                    index + 1,
+                    self.breakpoints[*index]
+                );
+                writeln!(out, "{} at {}", detail, trigger)
+            }
+            Stop::Halted => writeln!(out, "the program exited"),
+            Stop::WaitingForKey => match machine.state
+            {
+                MachineState::WaitingForKey { register } =>
+                {
+                    writeln!(
+                        out,
+                        "waiting for a key to store in V{:X}; use 'key'",
+                        register
+                    )
+                }
+                _ => Ok(()),
+            },
+            Stop::Fault(fault) => writeln!(out, "fault: {}", fault),
+            Stop::Interrupted => writeln!(out, "paused"),
+        };
+        let line = disasm::line_at(&machine.ram, machine.registers.pc);
+        let _ = writeln!(out, "{}", line.format(Syntax::Cowgod));
+    }
+}
+
+/// `0x300`, or `0x300-0x303` for more than one byte.
+fn format_range(range: std::ops::Range<usize>) -> String
+{
+    if range.len() <= 1
+    {
+        format!("0x{:03X}", range.start)
+    }
+    else
+    {
+        format!("0x{:03X}-0x{:03X}", range.start, range.end - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::machine::MemoryAccess;
+    use crate::octo;
+    use crate::quirks::QuirkProfile;
+
+    /// Counts v0 up and calls a subroutine that adds 2 to v1:
+    ///
+    /// ```text
+    /// 200: jump 0x202
+    /// 202: v0 := 0
+    /// 204: v0 += 1
+    /// 206: call 0x20A
+    /// 208: jump 0x204
+    /// 20A: v1 += 2
+    /// 20C: return
+    /// ```
+    const LOOP: &str = r#"
+        : main
+            v0 := 0
+            loop
+                v0 += 1
+                add-two
+            again
+        : add-two
+            v1 += 2
+        ;
+    "#;
+
+    /// Copies two registers through a buffer, then a stray save runs over
+    /// its own code at 0x214.
+    const WATCHED: &str = r#"
+        : main
+            i := buffer
+            v0 := 1
+            v1 := 2
+            save v1
+            load v0
+            v3 := 7
+            i := target
+            v2 := 0xAA
+            save v2
+        : target
+            v4 := 1
+            loop again
+        : buffer
+            0 0 0 0
+    "#;
+
+    struct Session
+    {
+        machine: Chip8,
+        debugger: Debugger,
+    }
+
+    impl Session
+    {
+        fn rom(rom: &[u8]) -> Self
+        {
+            let mut machine = Chip8::new();
+            machine.load_rom(rom).unwrap();
+            Self {
+                machine,
+                debugger: Debugger::new(16),
+            }
+        }
+
+        fn octo(source: &str) -> Self
+        {
+            Self::rom(&octo::compile(source).unwrap())
+        }
+
+        fn run(&mut self, line: &str) -> String
+        {
+            let command = line.parse::<DebugCommand>().unwrap();
+            self.debugger.execute(&mut self.machine, command)
+        }
+    }
+
+    #[test]
+    fn condition_breakpoint_test()
+    {
+        let mut session = Session::octo(LOOP);
+        assert_eq!(session.run("break v0 == 3"), "breakpoint 1: v0 == 0x03\n");
+        assert_eq!(
+            session.run("continue"),
+            "breakpoint 1: v0 == 0x03\n206: 22 0A        CALL 0x20A\n"
+        );
+        assert_eq!(session.machine.registers.v[1], 4);
+    }
+
+    #[test]
+    fn address_breakpoint_test()
+    {
+        let mut session = Session::octo(LOOP);
+        session.run("b 0x20A");
+        assert_eq!(
+            session.run("c"),
+            "breakpoint 1: pc == 0x20A\n20A: 71 02        ADD V1, 0x02\n"
+        );
+    }
+
+    #[test]
+    fn next_steps_over_call_test()
+    {
+        let mut session = Session::octo(LOOP);
+        session.run("b 0x206");
+        session.run("c");
+        assert_eq!(session.run("next"), "208: 12 04        JP 0x204\n");
+        assert_eq!(session.machine.registers.v[1], 2);
+    }
+
+    #[test]
+    fn step_into_and_finish_test()
+    {
+        let mut session = Session::octo(LOOP);
+        session.run("step 3");
+        assert_eq!(session.run("s"), "20A: 71 02        ADD V1, 0x02\n");
+        assert_eq!(session.machine.registers.sp, 1);
+        assert_eq!(session.run("finish"), "208: 12 04        JP 0x204\n");
+        assert_eq!(session.machine.registers.sp, 0);
+        assert_eq!(session.run("finish"), "not in a subroutine\n");
+    }
+
+    #[test]
+    fn set_register_test()
+    {
+        let mut session = Session::octo(LOOP);
+        session.run("set v3 0x10");
+        session.run("set i 0x300");
+        assert_eq!(session.machine.registers.v[3], 0x10);
+        assert_eq!(session.machine.registers.i, 0x300);
+        assert_eq!(
+            session.run("set sp 300"),
+            "sp only holds values up to 0x10\n"
+        );
+    }
+
+    #[test]
+    fn regs_test()
+    {
+        let mut session = Session::octo(LOOP);
+        session.run("b 0x20A");
+        session.run("c");
+        session.run("set v3 0x10");
+        let regs = session.run("regs");
+        assert!(regs.contains("V3=10"));
+        assert!(regs.ends_with("stack: 208\n"));
+    }
+
+    #[test]
+    fn mem_test()
+    {
+        let mut session = Session::octo(LOOP);
+        assert_eq!(
+            session.run("mem 0x200 20"),
+            "200: 12 02 60 00 70 01 22 0A 12 04 71 02 00 EE 00 00\n210: 00 00 00 00\n"
+        );
+    }
+
+    #[test]
+    fn disasm_test()
+    {
+        let mut session = Session::octo(LOOP);
+        assert_eq!(
+            session.run("disasm 0x20A 2"),
+            "20A: 71 02        ADD V1, 0x02\n20C: 00 EE        RET\n"
+        );
+    }
+
+    #[test]
+    fn fault_stops_run_test()
+    {
+        // A fault stops the run and leaves the PC on the bad instruction.
+        let mut session = Session::octo(LOOP);
+        session.machine.ram[0x20C..0x20E].fill(0xFF);
+        assert!(session.run("c").starts_with("fault: "));
+        assert_eq!(session.machine.registers.pc, 0x20C);
+    }
+
+    #[test]
+    fn parse_errors_test()
+    {
+        assert_eq!(
+            "mem".parse::<DebugCommand>(),
+            Err(DebugError::MissingArgument("mem".to_string()))
+        );
+        assert_eq!(
+            "set vg 1".parse::<DebugCommand>(),
+            Err(DebugError::UnknownRegister("vg".to_string()))
+        );
+        assert_eq!(
+            "step 1 2".parse::<DebugCommand>(),
+            Err(DebugError::UnexpectedArgument("2".to_string()))
+        );
+        assert_eq!("  ".parse::<DebugCommand>(), Err(DebugError::Empty));
+    }
+
+    #[test]
+    fn step_through_display_wait_test()
+    {
+        // Under display_wait a draw stalls until the next frame, and stepping
+        // has to run out the frame instead of standing still.
+        let mut machine = Chip8::with_quirks(QuirkProfile::CosmacVip.quirks());
+        machine
+            .load_rom(&[0x00, 0xE0, 0xD0, 0x05, 0x60, 0x01, 0x61, 0x02, 0x12, 0x08])
+            .unwrap();
+        let mut debugger = Debugger::new(16);
+        let mut pcs = Vec::new();
+        for _ in 0..5
+        {
+            debugger.execute(&mut machine, DebugCommand::Step(1));
+            pcs.push(machine.registers.pc);
+        }
+        assert_eq!(pcs, [0x202, 0x204, 0x206, 0x208, 0x208]);
+        assert_eq!(machine.registers.v[..2], [1, 2]);
+        assert!(!machine.is_waiting_for_vblank());
+    }
+
+    #[test]
+    fn next_over_drawing_call_test()
+    {
+        let mut machine = Chip8::with_quirks(QuirkProfile::CosmacVip.quirks());
+        machine.load_rom(&[0x22, 0x04, 0x12, 0x02, 0xD0, 0x05, 0x00, 0xEE]).unwrap();
+        assert_eq!(
+            Debugger::new(16).execute(&mut machine, DebugCommand::Next),
+            "202: 12 02        JP 0x202\n"
+        );
+    }
+
+    #[test]
+    fn memory_write_watch_test()
+    {
+        let mut session = Session::octo(WATCHED);
+        assert_eq!(
+            session.run("watch 0x218 2"),
+            "watchpoint 1: writes to 0x218-0x219\n"
+        );
+        assert_eq!(
+            session.run("c"),
+            "watchpoint 1: writes to 0x218-0x219\n\
+             wrote 0x218-0x219 at 208: F1 55        LD [I], V1\n\
+             20A: F0 65        LD V0, [I]\n"
+        );
+        assert_eq!(
+            session.machine.last_accesses(),
+            [MemoryAccess {
+                kind: AccessKind::Write,
+                range: 0x218..0x21A,
+            }]
+        );
+    }
+
+    #[test]
+    fn memory_read_watch_test()
+    {
+        // save moved i past the buffer, so load reads the byte after it.
+        let mut session = Session::octo(WATCHED);
+        session.run("rwatch 0x21A");
+        assert!(session.run("c").contains("read 0x21A at 20A: F0 65"));
+    }
+
+    #[test]
+    fn memory_access_watch_test()
+    {
+        // The stray save over the code is caught before the damage runs.
+        let mut session = Session::octo(WATCHED);
+        session.run("awatch 0x214 2");
+        let report = session.run("c");
+        assert!(report.starts_with("watchpoint 1: accesses to 0x214-0x215\n"));
+        assert!(report.contains("wrote 0x214-0x216 at 212: F2 55        LD [I], V2\n"));
+        assert_eq!(session.machine.ram[0x214..0x217], [0, 2, 0xAA]);
+    }
+
+    #[test]
+    fn sprite_read_watch_test()
+    {
+        // LD I, 0x300; DRW V0, V0, 1; JP 0x204
+        let mut session = Session::rom(&[0xA3, 0x00, 0xD0, 0x01, 0x12, 0x04]);
+        session.run("rwatch 0x300");
+        assert!(session.run("c").contains("read 0x300 at 202: D0 01        DRW V0, V0, 1"));
+    }
+
+    #[test]
+    fn register_write_watch_test()
+    {
+        let mut session = Session::octo(WATCHED);
+        session.run("watch v3");
+        assert!(session.run("c").contains("wrote v3 = 0x07, was 0x00 at 20C: 63 07"));
+    }
+
+    #[test]
+    fn register_watch_parse_test()
+    {
+        assert_eq!(
+            "watch pc".parse::<DebugCommand>(),
+            Err(DebugError::UnwatchableRegister("pc".to_string()))
+        );
+        assert_eq!(
+            "rwatch v3".parse::<DebugCommand>(),
+            Ok(DebugCommand::Break(Breakpoint::Register {
+                register: Register::V(3),
+                watch: Watch::Read,
+            }))
+        );
+    }
+
+    /// LD V3, 5 twice; XOR V0, V1; JP 0x204
+    const REGISTERS: [u8; 8] = [0x63, 0x05, 0x63, 0x05, 0x80, 0x13, 0x12, 0x04];
+
+    #[test]
+    fn same_value_write_watch_test()
+    {
+        // Writing the value a register already holds still counts.
+        let mut session = Session::rom(&REGISTERS);
+        session.run("step");
+        session.run("watch v3");
+        let report = session.run("c");
+        assert!(report.starts_with("watchpoint 1: writes to v3\n"));
+        assert!(report.contains("wrote v3 = 0x05, was 0x05 at 202: 63 05"));
+    }
+
+    #[test]
+    fn register_read_watch_test()
+    {
+        // 8013 only reads V1.
+        let mut session = Session::rom(&REGISTERS);
+        session.run("rwatch v1");
+        let report = session.run("c");
+        assert!(report.starts_with("watchpoint 1: reads of v1\n"));
+        assert!(report.contains("read v1 = 0x00 at 204: 80 13"));
+    }
+
+    #[test]
+    fn register_access_watch_test()
+    {
+        let mut session = Session::rom(&REGISTERS);
+        session.run("awatch v0");
+        assert!(session.run("c").contains("watchpoint 1: accesses to v0\nread v0 = 0x00"));
+    }
+}
@@ -0,0 +1,471 @@
+use std::{fmt, str::FromStr};
+
+use crate::instruction::{Instruction, OpCode};
+
+/// Address programs are loaded at and start running from.
+pub const DEFAULT_BASE: u16 = 0x200;
+
+/// Reads an address such as `--base 0x300` or `768`: hex with a `0x`
+/// prefix, decimal otherwise.
+pub fn parse_address(s: &str) -> Option<u16>
+{
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Most bytes shown on one line of data.
+const DATA_PER_LINE: usize = 4;
+
+/// How instructions are written out.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Syntax
+{
+    /// The mnemonics of Cowgod's Chip-8 technical reference: `LD V3, 0x1F`.
+    #[default]
+    Cowgod,
+    /// Octo statements: `v3 := 0x1F`.
+    Octo,
+    /// Only the bytes, split into code and data.
+    Hex,
+}
+
+impl Syntax
+{
+    pub const NAMES: &'static [&'static str] = &["cowgod", "octo", "hex"];
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownSyntax(pub String);
+
+impl fmt::Display for UnknownSyntax
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(
+            f,
+            "unknown syntax '{}', expected one of: {}",
+            self.0,
+            Syntax::NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownSyntax {}
+
+impl FromStr for Syntax
+{
+    type Err = UnknownSyntax;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_ascii_lowercase().as_str()
+        {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            "hex" | "raw" => Ok(Syntax::Hex),
+            _ => Err(UnknownSyntax(s.to_string())),
+        }
+    }
+}
+
+/// One line of a listing: an instruction, or bytes that are never run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line
+{
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    /// `None` for data.
+    pub op: Option<OpCode>,
+}
+
+impl Line
+{
+    /// The instruction or data in `syntax`, without the address and bytes.
+    pub fn text(&self, syntax: Syntax) -> String
+    {
+        match (self.op, syntax)
+        {
+            (_, Syntax::Hex) => String::new(),
+            (Some(op), Syntax::Cowgod) => cowgod(op),
+            (Some(op), Syntax::Octo) => octo(op),
+            (None, Syntax::Cowgod) => format!("DB {}", hex_bytes(&self.bytes, ", ")),
+            (None, Syntax::Octo) => hex_bytes(&self.bytes, " "),
+        }
+    }
+
+    /// The address, the bytes and the text, e.g. `200: 6A 1F        LD VA, 0x1F`.
+    pub fn format(&self, syntax: Syntax) -> String
+    {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let line = format!(
+            "{:03X}: {:<width$}  {}",
+            self.address,
+            bytes.join(" "),
+            self.text(syntax),
+            width = DATA_PER_LINE * 3 - 1
+        );
+        line.trim_end().to_string()
+    }
+}
+
+impl fmt::Display for Line
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.write_str(&self.format(Syntax::Cowgod))
+    }
+}
+
+/// The lines as source code without addresses or bytes, which
+/// [`crate::asm::assemble`] turns back into the same ROM when written in
+/// Cowgod syntax. Hex syntax writes every line as data.
+pub fn source(lines: &[Line], syntax: Syntax) -> String
+{
+    let mut source = String::new();
+    for line in lines
+    {
+        match syntax
+        {
+            Syntax::Hex => source.push_str(&hex_bytes(&line.bytes, " ")),
+            _ => source.push_str(&line.text(syntax)),
+        }
+        source.push('\n');
+    }
+    source
+}
+
+/// Disassembles a ROM loaded at `base`.
+///
+/// Only bytes that can be reached from `base` by following jumps, calls and
+/// skips are shown as instructions; everything else, such as sprites, is
+/// shown as data. `JP V0, addr` is assumed to land on its base address.
+pub fn disassemble(rom: &[u8], base: u16) -> Vec<Line>
+{
+    let is_code = find_code(rom, base);
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len()
+    {
+        let address = base.wrapping_add(offset as u16);
+        if is_code[offset]
+        {
+            if let Some((op, len)) = decode_at(rom, offset)
+            {
+                lines.push(Line {
+                    address,
+                    bytes: rom[offset..offset + len].to_vec(),
+                    op: Some(op),
+                });
+                offset += len;
+                continue;
+            }
+        }
+        let end = (offset + 1..rom.len())
+            .find(|end| is_code[*end] || end - offset == DATA_PER_LINE)
+            .unwrap_or(rom.len());
+        lines.push(Line {
+            address,
+            bytes: rom[offset..end].to_vec(),
+            op: None,
+        });
+        offset = end;
+    }
+    lines
+}
+
+/// The instruction at `address` in `memory`, decoded without following the
+/// program flow the way [`disassemble`] does. Bytes that do not decode come
+/// back as a line of data.
+pub fn line_at(memory: &[u8], address: u16) -> Line
+{
+    let offset = address as usize;
+    match decode_at(memory, offset)
+    {
+        Some((op, len)) => Line {
+            address,
+            bytes: memory[offset..offset + len].to_vec(),
+            op: Some(op),
+        },
+        None => Line {
+            address,
+            bytes: memory.get(offset..(offset + 2).min(memory.len())).unwrap_or_default().to_vec(),
+            op: None,
+        },
+    }
+}
+
+/// The instruction at `offset` and how many bytes it takes up.
+fn decode_at(rom: &[u8], offset: usize) -> Option<(OpCode, usize)>
+{
+    let instruction = Instruction::new([*rom.get(offset)?, *rom.get(offset + 1)?]);
+    if instruction.is_long_load()
+    {
+        let operand = rom.get(offset + 2..offset + 4)?;
+        let nnnn = u16::from_be_bytes([operand[0], operand[1]]);
+        return Some((OpCode::LdILong { nnnn }, 4));
+    }
+    Some((instruction.decode()?, 2))
+}
+
+/// Marks the offsets where reachable instructions start.
+fn find_code(rom: &[u8], base: u16) -> Vec<bool>
+{
+    let mut is_code = vec![false; rom.len()];
+    let mut pending = vec![0usize];
+    while let Some(offset) = pending.pop()
+    {
+        if offset >= rom.len() || is_code[offset]
+        {
+            continue;
+        }
+        let Some((op, len)) = decode_at(rom, offset)
+        else
+        {
+            continue;
+        };
+        is_code[offset] = true;
+        let next = offset + len;
+        // Targets outside of the ROM end up past its end and are ignored.
+        let target = |nnn: u16| (nnn as usize).wrapping_sub(base as usize);
+        match op
+        {
+            OpCode::Ret | OpCode::Exit => (),
+            OpCode::Jmp { nnn } | OpCode::JpV0Addr { nnn } => pending.push(target(nnn)),
+            OpCode::Call { nnn } =>
+            {
+                pending.push(next);
+                pending.push(target(nnn));
+            }
+            OpCode::SeVxBy { .. }
+            | OpCode::SneVxBy { .. }
+            | OpCode::SeVxVy { .. }
+            | OpCode::SneVxVy { .. }
+            | OpCode::SkpVx { .. }
+            | OpCode::SknpVx { .. } =>
+            {
+                pending.push(next);
+                // Skipping over a long load skips all four bytes.
+                let skipped = if rom.get(next..next + 2) == Some(&[0xF0, 0x00])
+                {
+                    4
+                }
+                else
+                {
+                    2
+                };
+                pending.push(next + skipped);
+            }
+            _ => pending.push(next),
+        }
+    }
+    is_code
+}
+
+fn hex_bytes(bytes: &[u8], separator: &str) -> String
+{
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+    bytes.join(separator)
+}
+
+/// `op` as in Cowgod's reference, with the SUPER-CHIP and XO-CHIP additions
+/// named after their Octo counterparts.
+pub fn cowgod(op: OpCode) -> String
+{
+    match op
+    {
+        OpCode::Cls => "CLS".to_string(),
+        OpCode::Ret => "RET".to_string(),
+        OpCode::Jmp { nnn } => format!("JP 0x{:03X}", nnn),
+        OpCode::Call { nnn } => format!("CALL 0x{:03X}", nnn),
+        OpCode::SeVxBy { x, kk } => format!("SE V{:X}, 0x{:02X}", x, kk),
+        OpCode::SneVxBy { x, kk } => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        OpCode::SeVxVy { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        OpCode::LdVxBy { x, kk } => format!("LD V{:X}, 0x{:02X}", x, kk),
+        OpCode::Add { x, kk } => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        OpCode::LdVxVy { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        OpCode::OrVxVy { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        OpCode::AndVxVy { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        OpCode::XorVxVy { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        OpCode::AddVxVy { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        OpCode::SubVxVy { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        OpCode::ShrVxVy { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        OpCode::SubnVxVy { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        OpCode::ShlVxVy { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        OpCode::SneVxVy { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        OpCode::LdI { nnn } => format!("LD I, 0x{:03X}", nnn),
+        OpCode::JpV0Addr { nnn } => format!("JP V0, 0x{:03X}", nnn),
+        OpCode::RndVxBy { x, kk } => format!("RND V{:X}, 0x{:02X}", x, kk),
+        OpCode::Display { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        OpCode::SkpVx { x } => format!("SKP V{:X}", x),
+        OpCode::SknpVx { x } => format!("SKNP V{:X}", x),
+        OpCode::LdVxDt { x } => format!("LD V{:X}, DT", x),
+        OpCode::LdVxK { x } => format!("LD V{:X}, K", x),
+        OpCode::LdDtVx { x } => format!("LD DT, V{:X}", x),
+        OpCode::LdStVx { x } => format!("LD ST, V{:X}", x),
+        OpCode::AddIVx { x } => format!("ADD I, V{:X}", x),
+        OpCode::LdFVx { x } => format!("LD F, V{:X}", x),
+        OpCode::LdBVx { x } => format!("LD B, V{:X}", x),
+        OpCode::LdIVx { x } => format!("LD [I], V{:X}", x),
+        OpCode::LdVxI { x } => format!("LD V{:X}, [I]", x),
+        OpCode::ScrollDown { n } => format!("SCD {}", n),
+        OpCode::ScrollRight => "SCR".to_string(),
+        OpCode::ScrollLeft => "SCL".to_string(),
+        OpCode::Exit => "EXIT".to_string(),
+        OpCode::Lores => "LOW".to_string(),
+        OpCode::Hires => "HIGH".to_string(),
+        OpCode::LdHfVx { x } => format!("LD HF, V{:X}", x),
+        OpCode::LdRVx { x } => format!("LD R, V{:X}", x),
+        OpCode::LdVxR { x } => format!("LD V{:X}, R", x),
+        OpCode::ScrollUp { n } => format!("SCU {}", n),
+        OpCode::SaveVxVy { x, y } => format!("SAVE V{:X}, V{:X}", x, y),
+        OpCode::LoadVxVy { x, y } => format!("LOAD V{:X}, V{:X}", x, y),
+        OpCode::Plane { n } => format!("PLANE {}", n),
+        OpCode::LdILong { nnnn } => format!("LD I, LONG 0x{:04X}", nnnn),
+        OpCode::Audio => "AUDIO".to_string(),
+        OpCode::LdPitchVx { x } => format!("LD PITCH, V{:X}", x),
+    }
+}
+
+/// `op` as an Octo statement. Skips become `if ... then` with the condition
+/// under which the next instruction runs.
+pub fn octo(op: OpCode) -> String
+{
+    match op
+    {
+        OpCode::Cls => "clear".to_string(),
+        OpCode::Ret => "return".to_string(),
+        OpCode::Jmp { nnn } => format!("jump 0x{:03X}", nnn),
+        OpCode::Call { nnn } => format!(":call 0x{:03X}", nnn),
+        OpCode::SeVxBy { x, kk } => format!("if v{:x} != 0x{:02X} then", x, kk),
+        OpCode::SneVxBy { x, kk } => format!("if v{:x} == 0x{:02X} then", x, kk),
+        OpCode::SeVxVy { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        OpCode::LdVxBy { x, kk } => format!("v{:x} := 0x{:02X}", x, kk),
+        OpCode::Add { x, kk } => format!("v{:x} += 0x{:02X}", x, kk),
+        OpCode::LdVxVy { x, y } => format!("v{:x} := v{:x}", x, y),
+        OpCode::OrVxVy { x, y } => format!("v{:x} |= v{:x}", x, y),
+        OpCode::AndVxVy { x, y } => format!("v{:x} &= v{:x}", x, y),
+        OpCode::XorVxVy { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        OpCode::AddVxVy { x, y } => format!("v{:x} += v{:x}", x, y),
+        OpCode::SubVxVy { x, y } => format!("v{:x} -= v{:x}", x, y),
+        OpCode::ShrVxVy { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        OpCode::SubnVxVy { x, y } => format!("v{:x} =- v{:x}", x, y),
+        OpCode::ShlVxVy { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        OpCode::SneVxVy { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        OpCode::LdI { nnn } => format!("i := 0x{:03X}", nnn),
+        OpCode::JpV0Addr { nnn } => format!("jump0 0x{:03X}", nnn),
+        OpCode::RndVxBy { x, kk } => format!("v{:x} := random 0x{:02X}", x, kk),
+        OpCode::Display { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        OpCode::SkpVx { x } => format!("if v{:x} -key then", x),
+        OpCode::SknpVx { x } => format!("if v{:x} key then", x),
+        OpCode::LdVxDt { x } => format!("v{:x} := delay", x),
+        OpCode::LdVxK { x } => format!("v{:x} := key", x),
+        OpCode::LdDtVx { x } => format!("delay := v{:x}", x),
+        OpCode::LdStVx { x } => format!("buzzer := v{:x}", x),
+        OpCode::AddIVx { x } => format!("i += v{:x}", x),
+        OpCode::LdFVx { x } => format!("i := hex v{:x}", x),
+        OpCode::LdBVx { x } => format!("bcd v{:x}", x),
+        OpCode::LdIVx { x } => format!("save v{:x}", x),
+        OpCode::LdVxI { x } => format!("load v{:x}", x),
+        OpCode::ScrollDown { n } => format!("scroll-down {}", n),
+        OpCode::ScrollRight => "scroll-right".to_string(),
+        OpCode::ScrollLeft => "scroll-left".to_string(),
+        OpCode::Exit => "exit".to_string(),
+        OpCode::Lores => "lores".to_string(),
+        OpCode::Hires => "hires".to_string(),
+        OpCode::LdHfVx { x } => format!("i := bighex v{:x}", x),
+        OpCode::LdRVx { x } => format!("saveflags v{:x}", x),
+        OpCode::LdVxR { x } => format!("loadflags v{:x}", x),
+        OpCode::ScrollUp { n } => format!("scroll-up {}", n),
+        OpCode::SaveVxVy { x, y } => format!("save v{:x} - v{:x}", x, y),
+        OpCode::LoadVxVy { x, y } => format!("load v{:x} - v{:x}", x, y),
+        OpCode::Plane { n } => format!("plane {}", n),
+        OpCode::LdILong { nnnn } => format!("i := long 0x{:04X}", nnnn),
+        OpCode::Audio => "audio".to_string(),
+        OpCode::LdPitchVx { x } => format!("pitch := v{:x}", x),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const ROM: [u8; 20] = [
+        0xA2, 0x0C, // LD I, 0x20C
+        0x22, 0x10, // CALL 0x210
+        0x30, 0x01, // SE V0, 0x01
+        0x12, 0x06, // JP 0x206
+        0x12, 0x08, // JP 0x208
+        0x00, 0x00, // never reached
+        0x60, 0x90, 0x90, 0x60, // sprite
+        0x60, 0x05, // LD V0, 0x05
+        0x00, 0xEE, // RET
+    ];
+
+    #[test]
+    fn listing_test()
+    {
+        let lines = disassemble(&ROM, 0x200);
+        let listing: Vec<String> = lines.iter().map(|line| line.format(Syntax::Cowgod)).collect();
+        assert_eq!(
+            listing,
+            [
+                "200: A2 0C        LD I, 0x20C",
+                "202: 22 10        CALL 0x210",
+                "204: 30 01        SE V0, 0x01",
+                "206: 12 06        JP 0x206",
+                "208: 12 08        JP 0x208",
+                "20A: 00 00 60 90  DB 0x00, 0x00, 0x60, 0x90",
+                "20E: 90 60        DB 0x90, 0x60",
+                "210: 60 05        LD V0, 0x05",
+                "212: 00 EE        RET",
+            ]
+        );
+    }
+
+    #[test]
+    fn other_syntaxes_test()
+    {
+        let lines = disassemble(&ROM, 0x200);
+        assert_eq!(lines[1].text(Syntax::Octo), ":call 0x210");
+        assert_eq!(lines[2].text(Syntax::Octo), "if v0 != 0x01 then");
+        assert_eq!(lines[6].text(Syntax::Octo), "0x90 0x60");
+        assert_eq!(lines[0].format(Syntax::Hex), "200: A2 0C");
+        assert_eq!("octo".parse::<Syntax>(), Ok(Syntax::Octo));
+    }
+
+    #[test]
+    fn mnemonics_test()
+    {
+        assert_eq!(
+            cowgod(OpCode::Display { x: 0, y: 1, n: 5 }),
+            "DRW V0, V1, 5"
+        );
+        assert_eq!(cowgod(OpCode::LdVxBy { x: 3, kk: 0x1F }), "LD V3, 0x1F");
+        assert_eq!(cowgod(OpCode::Jmp { nnn: 0x2A0 }), "JP 0x2A0");
+        assert_eq!(octo(OpCode::SubnVxVy { x: 0xA, y: 2 }), "va =- v2");
+    }
+
+    #[test]
+    fn parse_address_test()
+    {
+        assert_eq!(parse_address("0x200"), Some(0x200));
+        assert_eq!(parse_address("0X2a0"), Some(0x2A0));
+        assert_eq!(parse_address("512"), Some(512));
+        assert_eq!(parse_address("0x10000"), None);
+        assert_eq!(parse_address("2a0"), None);
+    }
+
+    #[test]
+    fn long_load_test()
+    {
+        // The long load is one four-byte instruction, skipped as a whole.
+        let lines = disassemble(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD], 0x200);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].text(Syntax::Octo), "i := long 0x1234");
+        assert_eq!(lines[2].op, Some(OpCode::Exit));
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fmt, io};
+
+/// A fault raised while loading or executing a program.
+#[derive(Debug)]
+pub enum Chip8Error
+{
+    UnknownOpcode
+    {
+        pc: u16,
+        opcode: u16,
+    },
+    StackOverflow
+    {
+        pc: u16,
+    },
+    StackUnderflow
+    {
+        pc: u16,
+    },
+    MemoryOutOfBounds
+    {
+        pc: u16,
+        address: usize,
+    },
+    RomTooLarge
+    {
+        size: usize,
+        max: usize,
+    },
+    Io(io::Error),
+}
+
+impl Chip8Error
+{
+    /// The address of the instruction that caused the fault, if any.
+    pub fn pc(&self) -> Option<u16>
+    {
+        match self
+        {
+            Chip8Error::UnknownOpcode { pc, .. }
+            | Chip8Error::StackOverflow { pc }
+            | Chip8Error::StackUnderflow { pc }
+            | Chip8Error::MemoryOutOfBounds { pc, .. } => Some(*pc),
+            Chip8Error::RomTooLarge { .. } | Chip8Error::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Chip8Error::UnknownOpcode { pc, opcode } =>
+            {
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, pc)
+            }
+            Chip8Error::StackOverflow { pc } => write!(f, "stack overflow at {:03X}", pc),
+            Chip8Error::StackUnderflow { pc } => write!(f, "return with empty stack at {:03X}", pc),
+            Chip8Error::MemoryOutOfBounds { pc, address } =>
+            {
+                write!(
+                    f,
+                    "memory access out of bounds ({:X}) at {:03X}",
+                    address, pc
+                )
+            }
+            Chip8Error::RomTooLarge { size, max } =>
+            {
+                write!(f, "ROM is {} bytes but at most {} fit in memory", size, max)
+            }
+            Chip8Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            Chip8Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error
+{
+    fn from(e: io::Error) -> Self
+    {
+        Chip8Error::Io(e)
+    }
+}
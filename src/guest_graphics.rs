@@ -1,23 +1,5 @@
-use std::{
-    fmt,
-    sync::{Arc, Mutex},
-};
+use std::fmt;
 
-use crate::host_graphics;
-
-pub fn display_loop(display_threaded: ThreadedDisplay)
-{
-    loop
-    {
-        let mut display = display_threaded.lock().unwrap();
-        if display.buffer_tainted
-        {
-            host_graphics::Terminal::clear_terminal();
-            display.debuff();
-            print!("{}\r\n", display);
-        }
-    }
-}
 pub fn get_fonts() -> [Sprite; 16]
 {
     let font: [Sprite; 16] = [
@@ -120,20 +102,65 @@ pub fn get_fonts() -> [Sprite; 16]
     ];
     font
 }
-pub type ThreadedDisplay = Arc<Mutex<ChipDisplay>>;
+/// The SUPER-CHIP 8x10 font for the hex digits, loaded by `FX30`.
+pub fn get_big_fonts() -> [[u8; 10]; 16]
+{
+    [
+        [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF],
+        [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+        [0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03],
+        [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18],
+        [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF],
+        [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3],
+        [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC],
+        [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C],
+        [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC],
+        [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0],
+    ]
+}
 
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+const DISPLAY_SIZE: usize = HIRES_WIDTH * HIRES_HEIGHT;
+
+/// Bit mask covering every XO-CHIP bit plane.
+pub const ALL_PLANES: u8 = 0b11;
+
+/// The framebuffer. Each pixel holds one bit per XO-CHIP plane, so plain
+/// CHIP-8 and SUPER-CHIP programs only ever produce 0 and 1.
+///
+/// Pixels are stored row by row, 128 to a row; the 64x32 mode uses the top
+/// left corner.
+#[derive(Clone)]
 pub struct ChipDisplay
 {
-    pub data: [u8; 64 * 32],
-    pub buffer: [u8; 64 * 32],
+    pub data: [u8; DISPLAY_SIZE],
+    pub buffer: [u8; DISPLAY_SIZE],
     pub buffer_tainted: bool,
+    /// SUPER-CHIP 128x64 mode; the normal 64x32 mode otherwise.
+    pub hires: bool,
+    /// Planes affected by drawing, clearing and scrolling (XO-CHIP `FN01`).
+    pub selected_planes: u8,
 }
 
 impl ChipDisplay
 {
+    /// Clears the selected planes.
     pub fn clear(&mut self)
     {
-        self.buffer = [0; 64 * 32];
+        let keep = !self.selected_planes;
+        for pixel in self.buffer.iter_mut()
+        {
+            *pixel &= keep;
+        }
         self.buffer_tainted = true;
     }
     pub fn debuff(&mut self)
@@ -142,57 +169,188 @@ impl ChipDisplay
         self.buffer_tainted = false;
     }
 
+    pub fn width(&self) -> usize
+    {
+        if self.hires
+        {
+            HIRES_WIDTH
+        }
+        else
+        {
+            LORES_WIDTH
+        }
+    }
+    pub fn height(&self) -> usize
+    {
+        if self.hires
+        {
+            HIRES_HEIGHT
+        }
+        else
+        {
+            LORES_HEIGHT
+        }
+    }
+    /// Switches between 64x32 and 128x64 mode, clearing every plane.
+    pub fn set_hires(&mut self, hires: bool)
+    {
+        self.hires = hires;
+        self.buffer = [0; DISPLAY_SIZE];
+        self.buffer_tainted = true;
+    }
+
+    /// The planes lit at `x`, `y`, or `None` outside the current resolution.
     pub fn get_pixel(&self, x: u8, y: u8) -> Option<u8>
     {
+        if !self.contains(x, y)
+        {
+            return None;
+        }
         self.data.get(ChipDisplay::get_buffer_position_from_x_and_y(x, y)).copied()
     }
     pub fn new() -> Self
     {
         Self {
-            data: [0u8; 64 * 32],
-            buffer: [0u8; 64 * 32],
+            data: [0u8; DISPLAY_SIZE],
+            buffer: [0u8; DISPLAY_SIZE],
             buffer_tainted: true,
+            hires: false,
+            selected_planes: 1,
         }
     }
+    /// Where `x`, `y` lives in the 128 pixel wide buffer. Nothing is clipped.
     pub fn get_buffer_position_from_x_and_y(x: u8, y: u8) -> usize
     {
-        (32 * x as usize) + y as usize
+        (HIRES_WIDTH * y as usize) + x as usize
     }
+    /// Does nothing outside the current resolution.
     pub fn set_pixel(&mut self, x: u8, y: u8, state: bool)
     {
+        if !self.contains(x, y)
+        {
+            return;
+        }
         self.buffer[ChipDisplay::get_buffer_position_from_x_and_y(x, y)] =
             if state { 1 } else { 0 };
         self.buffer_tainted = true;
     }
-    pub fn draw_sprite(&mut self, x: u8, y: u8, sprite: Sprite) -> bool
+
+    fn contains(&self, x: u8, y: u8) -> bool
     {
+        (x as usize) < self.width() && (y as usize) < self.height()
+    }
+
+    /// Moves the picture down by `n` rows, filling the top with blank rows.
+    pub fn scroll_down(&mut self, n: usize)
+    {
+        self.scroll(0, n as isize);
+    }
+    /// Moves the picture up by `n` rows (XO-CHIP).
+    pub fn scroll_up(&mut self, n: usize)
+    {
+        self.scroll(0, -(n as isize));
+    }
+    /// Moves the picture 4 pixels to the right.
+    pub fn scroll_right(&mut self)
+    {
+        self.scroll(4, 0);
+    }
+    /// Moves the picture 4 pixels to the left.
+    pub fn scroll_left(&mut self)
+    {
+        self.scroll(-4, 0);
+    }
+    fn scroll(&mut self, dx: isize, dy: isize)
+    {
+        let source = self.buffer;
+        let planes = self.selected_planes;
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let inside = (0..width).contains(&from_x) && (0..height).contains(&from_y);
+                let moved = if inside
+                {
+                    source
+                        [ChipDisplay::get_buffer_position_from_x_and_y(from_x as u8, from_y as u8)]
+                }
+                else
+                {
+                    0
+                };
+                let pixel = &mut self.buffer
+                    [ChipDisplay::get_buffer_position_from_x_and_y(x as u8, y as u8)];
+                *pixel = (*pixel & !planes) | (moved & planes);
+            }
+        }
+        self.buffer_tainted = true;
+    }
+
+    /// XORs `sprite` onto `plane` and reports whether any lit pixel was
+    /// turned off. The start position always wraps; pixels that run off the
+    /// edge are dropped if `clip` is set and wrap around otherwise.
+    pub fn draw_sprite(&mut self, x: u8, y: u8, sprite: Sprite, plane: u8, clip: bool) -> bool
+    {
+        let rows =
+            sprite.sprite_data[..sprite.height as usize].iter().map(|row| (*row as u16) << 8);
+        self.draw_rows(x, y, rows, plane, clip)
+    }
+    /// Draws a SUPER-CHIP 16x16 sprite, given as 32 bytes of two-byte rows.
+    pub fn draw_large_sprite(
+        &mut self,
+        x: u8,
+        y: u8,
+        sprite_data: &[u8],
+        plane: u8,
+        clip: bool,
+    ) -> bool
+    {
+        let rows = sprite_data.chunks_exact(2).map(|row| u16::from_be_bytes([row[0], row[1]]));
+        self.draw_rows(x, y, rows, plane, clip)
+    }
+    fn draw_rows<I>(&mut self, x: u8, y: u8, rows: I, plane: u8, clip: bool) -> bool
+    where
+        I: Iterator<Item = u16>,
+    {
+        let (width, height) = (self.width(), self.height());
         let mut xor_cleared_data_marker = false;
-        let x = x % 64;
-        let y = y % 32;
-        for (sprite_y, sprite_byte) in sprite.sprite_data.iter().enumerate()
+        let x = x as usize % width;
+        let y = y as usize % height;
+        for (sprite_y, sprite_row) in rows.enumerate()
         {
-            if sprite_y >= sprite.height as usize
+            let mut pixel_y = y + sprite_y;
+            if pixel_y >= height
             {
-                break;
+                if clip
+                {
+                    break;
+                }
+                pixel_y %= height;
             }
-            for sprite_x in 0..8
+            for sprite_x in 0..16
             {
-                let sprite_bit = sprite_byte & (128u8 >> sprite_x) != 0;
-                if sprite_bit
+                let mut pixel_x = x + sprite_x;
+                if pixel_x >= width
                 {
-                    let display_bit = self
-                        .get_pixel(x + sprite_x as u8, y + sprite_y as u8)
-                        .unwrap()
-                        != 0;
-                    if display_bit
+                    if clip
                     {
-                        xor_cleared_data_marker = true;
-                        self.set_pixel(x + sprite_x as u8, y + sprite_y as u8, false);
+                        break;
                     }
-                    else
+                    pixel_x %= width;
+                }
+                let sprite_bit = sprite_row & (0x8000u16 >> sprite_x) != 0;
+                if sprite_bit
+                {
+                    let position =
+                        ChipDisplay::get_buffer_position_from_x_and_y(pixel_x as u8, pixel_y as u8);
+                    if self.buffer[position] & plane != 0
                     {
-                        self.set_pixel(x + sprite_x as u8, y + sprite_y as u8, true);
+                        xor_cleared_data_marker = true;
                     }
+                    self.buffer[position] ^= plane;
+                    self.buffer_tainted = true;
                 }
             }
         }
@@ -212,31 +370,14 @@ impl fmt::Display for ChipDisplay
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
-        for y in 0..32
+        for y in 0..self.height() as u8
         {
-            for x in 0..64
+            for x in 0..self.width() as u8
             {
-                // f.write_str(self.data.get(x * y));
-                write!(
-                    f,
-                    "{}",
-                    if self
-                        .get_pixel(x, y)
-                        .expect("Tried to display value outside of bounds of graphics data")
-                        == 1
-                    {
-                        // 1
-                        "⬛"
-                    }
-                    else
-                    {
-                        // 0
-                        "⬜"
-                    }
-                )
-                .unwrap();
+                let lit = self.get_pixel(x, y).unwrap_or(0) != 0;
+                f.write_str(if lit { "⬛" } else { "⬜" })?;
             }
-            f.write_str("\n\r").unwrap();
+            f.write_str("\n\r")?;
         }
         Ok(())
     }
@@ -292,3 +433,212 @@ impl fmt::Display for Sprite
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use std::collections::{hash_map::Entry, HashMap};
+
+    use super::*;
+
+    /// The pixels in a `width` x `height` window of the framebuffer as `#`
+    /// and `.`.
+    fn image(display: &ChipDisplay, x: u8, y: u8, width: u8, height: u8) -> Vec<String>
+    {
+        (y..y + height)
+            .map(|y| {
+                (x..x + width)
+                    .map(|x| {
+                        let position = ChipDisplay::get_buffer_position_from_x_and_y(x, y);
+                        if display.buffer[position] != 0
+                        {
+                            '#'
+                        }
+                        else
+                        {
+                            '.'
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn block() -> Sprite
+    {
+        Sprite::new_from_bytes(&[0xFF, 0x81, 0xFF])
+    }
+
+    #[test]
+    fn check_if_collision_in_buffer_and_x_y_test()
+    {
+        let mut hs: HashMap<usize, (u8, u8)> = HashMap::new();
+        for x in 0..128
+        {
+            for y in 0..64
+            {
+                let buf_pos = ChipDisplay::get_buffer_position_from_x_and_y(x, y);
+                if let Entry::Vacant(e) = hs.entry(buf_pos)
+                {
+                    e.insert((x, y));
+                }
+                else
+                {
+                    let (hs_x, hs_y) = *hs.get(&buf_pos).unwrap();
+                    panic!(
+                        "{}, {} = {} collides with {},{} ={}",
+                        x, y, buf_pos, hs_x, hs_y, buf_pos
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn buffer_is_row_major_test()
+    {
+        assert_eq!(ChipDisplay::get_buffer_position_from_x_and_y(1, 0), 1);
+        assert_eq!(ChipDisplay::get_buffer_position_from_x_and_y(0, 1), 128);
+        assert_eq!(
+            ChipDisplay::get_buffer_position_from_x_and_y(127, 63),
+            128 * 64 - 1
+        );
+    }
+
+    #[test]
+    fn sprite_print_test()
+    {
+        let font = get_fonts();
+        println!("{}", font[0]);
+    }
+
+    #[test]
+    fn get_pixel_outside_lores_screen_test()
+    {
+        let mut display = ChipDisplay::new();
+        display.debuff();
+        assert_eq!(display.get_pixel(63, 31), Some(0));
+        assert_eq!(display.get_pixel(64, 0), None);
+        assert_eq!(display.get_pixel(0, 32), None);
+        assert_eq!(display.get_pixel(130, 0), None);
+    }
+
+    #[test]
+    fn get_pixel_outside_hires_screen_test()
+    {
+        let mut display = ChipDisplay::new();
+        display.set_hires(true);
+        display.debuff();
+        assert_eq!(display.get_pixel(127, 63), Some(0));
+        assert_eq!(display.get_pixel(128, 0), None);
+        assert_eq!(display.get_pixel(130, 0), None);
+        assert_eq!(display.get_pixel(0, 64), None);
+    }
+
+    #[test]
+    fn set_pixel_outside_screen_test()
+    {
+        // Pixels past the edge are ignored rather than landing on the next row.
+        let mut display = ChipDisplay::new();
+        display.set_pixel(64, 0, true);
+        display.set_pixel(0, 32, true);
+        assert!(image(&display, 0, 0, 128, 64).iter().all(|row| !row.contains('#')));
+
+        display.set_hires(true);
+        display.set_pixel(130, 0, true);
+        display.set_pixel(0, 64, true);
+        display.set_pixel(255, 255, true);
+        assert!(image(&display, 0, 0, 128, 64).iter().all(|row| !row.contains('#')));
+    }
+
+    #[test]
+    fn sprite_clips_at_edges_test()
+    {
+        let mut display = ChipDisplay::new();
+        assert!(!display.draw_sprite(60, 30, block(), 1, true));
+        assert_eq!(
+            image(&display, 58, 28, 6, 4),
+            ["......", "......", "..####", "..#..."]
+        );
+        assert_eq!(image(&display, 0, 0, 4, 2), ["....", "...."]);
+    }
+
+    #[test]
+    fn sprite_wraps_at_edges_test()
+    {
+        let mut display = ChipDisplay::new();
+        assert!(!display.draw_sprite(60, 30, block(), 1, false));
+        assert_eq!(image(&display, 60, 30, 4, 2), ["####", "#..."]);
+        assert_eq!(image(&display, 0, 30, 4, 2), ["####", "...#"]);
+        assert_eq!(image(&display, 60, 0, 4, 1), ["####"]);
+        assert_eq!(image(&display, 0, 0, 4, 1), ["####"]);
+    }
+
+    #[test]
+    fn sprite_start_position_wraps_test()
+    {
+        for clip in [true, false]
+        {
+            let mut display = ChipDisplay::new();
+            display.draw_sprite(64 + 2, 32 + 1, block(), 1, clip);
+            assert_eq!(
+                image(&display, 0, 0, 12, 5),
+                [
+                    "............",
+                    "..########..",
+                    "..#......#..",
+                    "..########..",
+                    "............"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn sprite_collision_test()
+    {
+        // Drawing over lit pixels turns them off and sets the collision flag.
+        let mut display = ChipDisplay::new();
+        display.draw_sprite(0, 0, block(), 1, true);
+        assert!(display.draw_sprite(4, 1, Sprite::new_from_bytes(&[0xF0]), 1, true));
+        assert_eq!(
+            image(&display, 0, 0, 10, 3),
+            ["########..", "#...###...", "########.."]
+        );
+        assert!(!display.draw_sprite(8, 1, Sprite::new_from_bytes(&[0x60]), 1, true));
+    }
+
+    #[test]
+    fn sprite_collision_at_edges_test()
+    {
+        // Pixels that wrap around collide too, clipped ones cannot.
+        let mut display = ChipDisplay::new();
+        display.draw_sprite(0, 0, Sprite::new_from_bytes(&[0x80]), 1, true);
+        assert!(!display.draw_sprite(60, 0, block(), 1, true));
+        assert!(display.draw_sprite(60, 0, Sprite::new_from_bytes(&[0x0F]), 1, false));
+        assert_eq!(image(&display, 60, 0, 4, 1), ["####"]);
+        assert_eq!(image(&display, 0, 0, 4, 1), [".###"]);
+    }
+
+    #[test]
+    fn large_sprite_clips_in_hires_corner_test()
+    {
+        let mut display = ChipDisplay::new();
+        display.set_hires(true);
+        assert!(!display.draw_large_sprite(124, 62, &[0xFF; 32], 1, true));
+        assert_eq!(
+            image(&display, 122, 61, 6, 3),
+            ["......", "..####", "..####"]
+        );
+        assert_eq!(image(&display, 0, 0, 2, 1), [".."]);
+    }
+
+    #[test]
+    fn large_sprite_wraps_in_hires_corner_test()
+    {
+        let mut display = ChipDisplay::new();
+        display.set_hires(true);
+        display.draw_large_sprite(124, 62, &[0xFF; 32], 1, false);
+        assert_eq!(image(&display, 0, 0, 12, 14)[13], "############");
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+
+use crate::error::Chip8Error;
+use crate::guest_graphics::ChipDisplay;
+use crate::machine::{Chip8, ChipRegisters, MachineState};
+use crate::scheduler::Scheduler;
+
+pub const DEFAULT_CYCLES_PER_FRAME: usize = 16;
+
+/// How long a headless run should last.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RunLimit
+{
+    Cycles(usize),
+    Frames(usize),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct HeadlessOptions
+{
+    pub cycles_per_frame: usize,
+    pub limit: RunLimit,
+}
+
+impl Default for HeadlessOptions
+{
+    fn default() -> Self
+    {
+        Self {
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            limit: RunLimit::Frames(60),
+        }
+    }
+}
+
+/// The machine state left behind by a headless run.
+pub struct HeadlessReport
+{
+    pub display: ChipDisplay,
+    pub registers: ChipRegisters,
+    pub cycles: usize,
+    pub frames: usize,
+    pub state: MachineState,
+    /// The random number generator seed the run started with.
+    pub seed: u64,
+    /// The fault that stopped the run early, if any.
+    pub fault: Option<Chip8Error>,
+}
+
+/// Runs `machine` as fast as possible without touching the terminal.
+///
+/// This uses the same [`Scheduler`] as the interactive front-end, minus the
+/// pacing, so the result only depends on the ROM and the options. A cycle
+/// limit that is not a whole number of frames ends with a partial frame in
+/// which the timers are not decremented.
+pub fn run(mut machine: Chip8, options: &HeadlessOptions) -> HeadlessReport
+{
+    let seed = machine.rng.seed();
+    let mut scheduler = Scheduler::new(options.cycles_per_frame);
+    let cycles_per_frame = scheduler.cycles_per_frame;
+    let total_cycles = match options.limit
+    {
+        RunLimit::Cycles(n) => n,
+        RunLimit::Frames(n) => n.saturating_mul(cycles_per_frame),
+    };
+
+    let mut fault = None;
+    while (scheduler.cycles as usize) < total_cycles && machine.state != MachineState::Halted
+    {
+        let remaining = total_cycles - scheduler.cycles as usize;
+        let result = if remaining >= cycles_per_frame
+        {
+            scheduler.run_frame(&mut machine).map(|_| ())
+        }
+        else
+        {
+            scheduler.run_cycles(&mut machine, remaining)
+        };
+        if let Err(e) = result
+        {
+            fault = Some(e);
+            break;
+        }
+    }
+    machine.display.debuff();
+
+    HeadlessReport {
+        display: machine.display,
+        registers: machine.registers,
+        cycles: scheduler.cycles as usize,
+        frames: scheduler.frames as usize,
+        state: machine.state,
+        seed,
+        fault,
+    }
+}
+
+impl fmt::Display for HeadlessReport
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        writeln!(
+            f,
+            "cycles: {} frames: {} seed: {}",
+            self.cycles, self.frames, self.seed
+        )?;
+        if let MachineState::WaitingForKey { register } = self.state
+        {
+            writeln!(f, "waiting for a key to store in V{:X}", register)?;
+        }
+        if let Some(fault) = &self.fault
+        {
+            writeln!(f, "fault: {}", fault)?;
+        }
+        writeln!(f, "{}", self.registers)?;
+        for y in 0..self.display.height() as u8
+        {
+            for x in 0..self.display.width() as u8
+            {
+                let lit = self.display.get_pixel(x, y).unwrap_or(0) != 0;
+                f.write_str(if lit { "#" } else { "." })?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn headless_run_test()
+    {
+        let mut machine = Chip8::new();
+        // LD DT, V0 with V0 = 10, then draw the "0" glyph at 0,0 and spin.
+        machine
+            .load_rom(&[0x60, 0x0A, 0xF0, 0x15, 0x60, 0x00, 0xD0, 0x05, 0x12, 0x08])
+            .unwrap();
+        let options = HeadlessOptions {
+            cycles_per_frame: 10,
+            limit: RunLimit::Frames(4),
+        };
+        let report = run(machine, &options);
+        assert_eq!(report.frames, 4);
+        assert_eq!(report.cycles, 40);
+        assert_eq!(report.registers.delay, 6);
+        assert_eq!(report.display.get_pixel(0, 0), Some(1));
+        assert_eq!(report.display.get_pixel(4, 0), Some(0));
+    }
+
+    #[test]
+    fn huge_frame_limit_test()
+    {
+        // A huge frame limit does not overflow the cycle count.
+        let mut machine = Chip8::new();
+        machine.load_rom(&[0x00, 0xFD]).unwrap();
+        let options = HeadlessOptions {
+            cycles_per_frame: usize::MAX / 2,
+            limit: RunLimit::Frames(usize::MAX / 2),
+        };
+        let report = run(machine, &options);
+        assert_eq!(report.state, MachineState::Halted);
+        assert_eq!(report.cycles, 1);
+    }
+}
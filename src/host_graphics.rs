@@ -1,46 +1,201 @@
 use std::{
-    io::{stdin, stdout, Write},
+    io::{self, stdout, Stdout, Write},
     sync::{mpsc, Arc, Mutex},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use termion::{input::TermRead, raw::IntoRawMode};
+use signal_hook::consts::SIGINT;
+use termion::{
+    cursor,
+    raw::{IntoRawMode, RawTerminal},
+    screen,
+};
+
+use crate::host_input::{
+    Capabilities, EventParser, InputEvent, Key, KeyAction, KeyEvent, POP_KEYBOARD_FLAGS,
+    PUSH_KEYBOARD_FLAGS, QUERY_DEVICE_ATTRIBUTES, QUERY_KEYBOARD_FLAGS, QUERY_KITTY_GRAPHICS,
+};
+use crate::keymap::{Action, Binding, Keymap};
+use crate::keypad::{KeypadState, KEY_COUNT};
+use crate::shutdown::QuitSignal;
 
-const MIN_MILLISEC_KEY_CONSIDERED_PRESSED: u128 = 10;
+/// How long a key counts as held after a press or repeat when the terminal
+/// cannot report releases. It has to outlast the gap between auto-repeats.
+pub const DEFAULT_HOLD_TIMEOUT: Duration = Duration::from_millis(150);
 
 pub struct Terminal
 {
     pub key_pressed: [bool; 16],
 }
 
+/// Which keypad keys the host is holding down.
+///
+/// When the terminal reports releases a key is down from its press until its
+/// release. Otherwise each press or repeat keeps it down for the hold timeout.
 pub struct Input
 {
-    time_last_pressed: [Option<Instant>; 16],
+    time_last_pressed: [Option<Instant>; KEY_COUNT],
+    release_events: bool,
+    hold_timeout: Duration,
 }
 
 impl Input
 {
-    pub fn get_threaded_input() -> ThreadedInput
+    pub fn new(hold_timeout: Duration) -> Self
     {
-        let inp = Input {
-            time_last_pressed: [None; 16],
-        };
-        Arc::new(Mutex::new(inp))
+        Self {
+            time_last_pressed: [None; KEY_COUNT],
+            release_events: false,
+            hold_timeout,
+        }
     }
-    pub fn key_is_down(&self, key: usize) -> bool
+
+    pub fn get_threaded_input(hold_timeout: Duration) -> ThreadedInput
+    {
+        Arc::new(Mutex::new(Input::new(hold_timeout)))
+    }
+
+    /// Whether key releases are being reported, so no timeout is needed.
+    pub fn release_events(&self) -> bool
+    {
+        self.release_events
+    }
+
+    pub fn set_release_events(&mut self, release_events: bool)
+    {
+        self.release_events = release_events;
+    }
+
+    pub fn key_event(&mut self, key: usize, action: KeyAction)
+    {
+        self.key_event_at(key, action, Instant::now());
+    }
+
+    pub fn key_event_at(&mut self, key: usize, action: KeyAction, now: Instant)
     {
-        if let Some(t) = self.time_last_pressed[key]
+        if action == KeyAction::Release
         {
-            Instant::now().duration_since(t).as_millis() <= MIN_MILLISEC_KEY_CONSIDERED_PRESSED
+            self.release_events = true;
+            self.time_last_pressed[key] = None;
         }
         else
         {
-            false
+            self.time_last_pressed[key] = Some(now);
         }
     }
+
+    pub fn key_is_down(&self, key: usize) -> bool
+    {
+        self.key_is_down_at(key, Instant::now())
+    }
+
+    pub fn key_is_down_at(&self, key: usize, now: Instant) -> bool
+    {
+        match self.time_last_pressed[key]
+        {
+            Some(_) if self.release_events => true,
+            Some(t) => now.saturating_duration_since(t) <= self.hold_timeout,
+            None => false,
+        }
+    }
+
+    pub fn keypad(&self) -> KeypadState
+    {
+        self.keypad_at(Instant::now())
+    }
+
+    pub fn keypad_at(&self, now: Instant) -> KeypadState
+    {
+        let mut keypad = KeypadState::new();
+        for key in 0..KEY_COUNT
+        {
+            keypad.set(key, self.key_is_down_at(key, now));
+        }
+        keypad
+    }
 }
 pub type ThreadedInput = Arc<Mutex<Input>>;
 
+/// How often the input thread checks whether it should stop.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait for the terminal to answer the capability queries.
+/// Anything slower is treated as not supporting them.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Owns the terminal while the emulator runs: raw mode, the alternate screen,
+/// a hidden cursor and key release reporting. Everything is put back when
+/// this is dropped, including while unwinding from a panic.
+pub struct TerminalSession
+{
+    raw_terminal: RawTerminal<Stdout>,
+    capabilities: Capabilities,
+}
+
+impl TerminalSession
+{
+    /// Takes over the terminal and asks it what it supports.
+    ///
+    /// Terminals without the kitty keyboard protocol ignore the request for
+    /// key releases, and [`Terminal::key_update_loop`] keeps using the hold
+    /// timeout for them.
+    pub fn start() -> io::Result<Self>
+    {
+        // Built before anything else is written so that an error below still
+        // runs `Drop` and puts the terminal back.
+        let mut session = Self {
+            raw_terminal: stdout().into_raw_mode()?,
+            capabilities: Capabilities::default(),
+        };
+        write!(
+            session.raw_terminal,
+            "{}{}",
+            screen::ToAlternateScreen,
+            cursor::Hide
+        )?;
+        session.raw_terminal.write_all(PUSH_KEYBOARD_FLAGS.as_bytes())?;
+        for query in [
+            QUERY_KEYBOARD_FLAGS,
+            QUERY_KITTY_GRAPHICS,
+            QUERY_DEVICE_ATTRIBUTES,
+        ]
+        {
+            session.raw_terminal.write_all(query.as_bytes())?;
+        }
+        session.raw_terminal.flush()?;
+        session.capabilities = probe_capabilities()?;
+        Ok(session)
+    }
+
+    pub fn capabilities(&self) -> Capabilities
+    {
+        self.capabilities
+    }
+}
+
+impl Drop for TerminalSession
+{
+    fn drop(&mut self)
+    {
+        let _ = self.raw_terminal.write_all(POP_KEYBOARD_FLAGS.as_bytes());
+        let _ = write!(
+            self.raw_terminal,
+            "{}{}",
+            cursor::Show,
+            screen::ToMainScreen
+        );
+        let _ = self.raw_terminal.flush();
+    }
+}
+
+impl Default for Terminal
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
 impl Terminal
 {
     pub fn new() -> Self
@@ -55,35 +210,175 @@ impl Terminal
         // Clear screen
         print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
     }
-    fn get_output_into_raw() {}
-    pub fn key_update_loop(&mut self, tx: mpsc::Sender<usize>, input: &mut ThreadedInput)
+    /// Reads key events from stdin until it closes or `quit` is set, updating
+    /// `input` for keypad keys and sending emulator actions down `tx`. Ctrl+C
+    /// sets `quit` with SIGINT, as it would have outside of raw mode.
+    pub fn key_update_loop(
+        &mut self,
+        keymap: &Keymap,
+        tx: mpsc::Sender<Action>,
+        input: &mut ThreadedInput,
+        quit: &QuitSignal,
+    )
     {
-        //TODO: Change these to more... ergonomic bindings
-        let key_bindings: [char; 16] = [
-            'x', '1', '2', '3', 'q', 'w', 'e', 'a', 's', 'd', 'z', 'c', '4', 'r', 'f', 'v',
-        ];
-        let mut _stdout = stdout().into_raw_mode().unwrap();
-        let stdin = stdin();
-        for c in stdin.keys()
+        let mut parser = EventParser::new();
+        let mut bytes = [0; 64];
+        while !quit.is_requested()
         {
-            if let termion::event::Key::Char(c) = c.unwrap()
+            let len = match read_stdin(&mut bytes, INPUT_POLL_INTERVAL)
+            {
+                Ok(Some(0)) | Err(_) => return,
+                Ok(Some(len)) => len,
+                Ok(None) => continue,
+            };
+            for event in parser.feed(&bytes[..len])
             {
-                if c == 'm'
+                let KeyEvent { key, action } = match event
                 {
-                    panic!("Quitting!");
+                    InputEvent::KeyboardFlags(_) =>
+                    {
+                        let mut inp = input.lock().unwrap();
+                        inp.set_release_events(event.reports_releases());
+                        continue;
+                    }
+                    InputEvent::Key(key_event) => key_event,
+                    InputEvent::DeviceAttributes { .. } | InputEvent::GraphicsReply { .. } =>
+                    {
+                        continue
+                    }
+                };
+                if key == Key::Ctrl('c')
+                {
+                    quit.request_signal(SIGINT);
                     return;
                 }
-                let pos = key_bindings.iter().position(|x| c == *x);
-                if pos != None
+                match keymap.lookup(key)
                 {
-                    let pos = pos.unwrap();
-                    self.key_pressed[pos] = true;
-                    let mut inp = input.lock().unwrap();
-                    inp.time_last_pressed[pos] = Some(Instant::now());
-
-                    tx.send(pos).unwrap();
+                    Some(Binding::Keypad(pos)) =>
+                    {
+                        let pos = pos as usize;
+                        self.key_pressed[pos] = action != KeyAction::Release;
+                        let mut inp = input.lock().unwrap();
+                        inp.key_event(pos, action);
+                    }
+                    // The receiver only goes away when the emulator stops.
+                    Some(Binding::Action(emulator_action))
+                        if action == KeyAction::Press && tx.send(emulator_action).is_err() =>
+                    {
+                        return;
+                    }
+                    _ => (),
                 }
             }
         }
     }
 }
+
+/// Reads the replies to the queries sent by [`TerminalSession::start`] up to
+/// the device attributes, which every terminal sends. Keys pressed meanwhile
+/// are dropped.
+fn probe_capabilities() -> io::Result<Capabilities>
+{
+    let mut capabilities = Capabilities::default();
+    let mut parser = EventParser::new();
+    let mut bytes = [0; 64];
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    loop
+    {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero()
+        {
+            return Ok(capabilities);
+        }
+        let len = match read_stdin(&mut bytes, remaining)?
+        {
+            Some(0) => return Ok(capabilities),
+            Some(len) => len,
+            None => continue,
+        };
+        for event in parser.feed(&bytes[..len])
+        {
+            if capabilities.update(&event)
+            {
+                return Ok(capabilities);
+            }
+        }
+    }
+}
+
+/// Reads whatever is available on stdin, waiting at most `timeout` for
+/// something to arrive. This bypasses `std::io::Stdin`, whose buffering would
+/// hide pending bytes from `poll`.
+fn read_stdin(bytes: &mut [u8], timeout: Duration) -> io::Result<Option<usize>>
+{
+    let mut poll_fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `poll_fd` is a single valid pollfd for the duration of the call.
+    let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+    if check_os_result(ready as isize)? != Some(1)
+    {
+        return Ok(None);
+    }
+    // SAFETY: the pointer and length describe `bytes`, which outlives the call.
+    let len = unsafe { libc::read(libc::STDIN_FILENO, bytes.as_mut_ptr().cast(), bytes.len()) };
+    check_os_result(len)
+}
+
+/// Turns a libc return value into a result, treating an interrupted call as
+/// having produced nothing.
+fn check_os_result(result: isize) -> io::Result<Option<usize>>
+{
+    if result >= 0
+    {
+        return Ok(Some(result as usize));
+    }
+    let error = io::Error::last_os_error();
+    if error.kind() == io::ErrorKind::Interrupted
+    {
+        Ok(None)
+    }
+    else
+    {
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::machine::Chip8;
+
+    #[test]
+    fn key_hold_timeout_test()
+    {
+        let start = Instant::now();
+        let later = start + Duration::from_millis(100);
+        let mut input = Input::new(Duration::from_millis(50));
+        input.key_event_at(0x4, KeyAction::Press, start);
+        assert_eq!(input.keypad_at(start), KeypadState(1 << 4));
+        assert_eq!(input.keypad_at(later), KeypadState(0));
+    }
+
+    #[test]
+    fn key_release_events_test()
+    {
+        let start = Instant::now();
+        let later = start + Duration::from_millis(100);
+        let mut input = Input::new(Duration::from_millis(50));
+        input.set_release_events(true);
+        input.key_event_at(0x4, KeyAction::Press, start);
+        input.key_event_at(0xA, KeyAction::Press, start);
+        assert_eq!(
+            input.keypad_at(later).keys_down().collect::<Vec<_>>(),
+            [0x4, 0xA]
+        );
+        input.key_event_at(0x4, KeyAction::Release, later);
+        let mut machine = Chip8::new();
+        machine.set_keypad(input.keypad_at(later));
+        assert!(machine.keypad.is_down(0xA) && !machine.keypad.is_down(0x4));
+    }
+}
@@ -0,0 +1,437 @@
+/// Asks the terminal to report every key as an escape code, with event types.
+///
+/// The flags are: disambiguate escape codes (1), report event types (2) and
+/// report all keys as escape codes (8).
+pub const PUSH_KEYBOARD_FLAGS: &str = "\x1b[>11u";
+/// Restores the keyboard mode that was active before [`PUSH_KEYBOARD_FLAGS`].
+pub const POP_KEYBOARD_FLAGS: &str = "\x1b[<u";
+/// Asks for the current keyboard flags.
+pub const QUERY_KEYBOARD_FLAGS: &str = "\x1b[?u";
+/// Asks whether a 1x1 image could be shown with the kitty graphics protocol.
+pub const QUERY_KITTY_GRAPHICS: &str = "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\";
+/// Asks for the primary device attributes. Every terminal answers this, so
+/// it goes last to mark the end of the replies to the other queries.
+pub const QUERY_DEVICE_ATTRIBUTES: &str = "\x1b[c";
+
+/// Set in the reported keyboard flags when key releases will be sent.
+const REPORT_EVENT_TYPES: u16 = 2;
+
+const ESC: u8 = 0x1b;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key
+{
+    Char(char),
+    /// A character typed with Ctrl held, such as `Ctrl('c')`.
+    Ctrl(char),
+    /// F1 to F12.
+    Function(u8),
+    Escape,
+    Enter,
+    Tab,
+    Backspace,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyAction
+{
+    Press,
+    Repeat,
+    Release,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyEvent
+{
+    pub key: Key,
+    pub action: KeyAction,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InputEvent
+{
+    Key(KeyEvent),
+    /// The reply to a keyboard flags query; only kitty-protocol terminals
+    /// send it.
+    KeyboardFlags(u16),
+    /// The reply to a primary device attributes query.
+    DeviceAttributes
+    {
+        sixel: bool,
+    },
+    /// The reply to a kitty graphics protocol command.
+    GraphicsReply
+    {
+        ok: bool,
+    },
+}
+
+/// What the terminal said it supports when probed by
+/// [`crate::host_graphics::TerminalSession::start`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities
+{
+    pub key_releases: bool,
+    pub sixel: bool,
+    pub kitty_graphics: bool,
+}
+
+impl Capabilities
+{
+    /// Takes in a reply to the probe and reports whether it was the last one.
+    pub fn update(&mut self, event: &InputEvent) -> bool
+    {
+        match *event
+        {
+            InputEvent::KeyboardFlags(_) => self.key_releases = event.reports_releases(),
+            InputEvent::GraphicsReply { ok } => self.kitty_graphics = ok,
+            InputEvent::DeviceAttributes { sixel } =>
+            {
+                self.sixel = sixel;
+                return true;
+            }
+            InputEvent::Key(_) => (),
+        }
+        false
+    }
+}
+
+impl InputEvent
+{
+    /// Whether this reply means key releases will be reported.
+    pub fn reports_releases(&self) -> bool
+    {
+        matches!(self, InputEvent::KeyboardFlags(flags) if flags & REPORT_EVENT_TYPES != 0)
+    }
+}
+
+/// Turns chunks of terminal input into [`InputEvent`]s.
+///
+/// Besides plain characters and the usual escape sequences this understands
+/// the kitty keyboard protocol, which reports releases as well as presses, and
+/// xterm's `modifyOtherKeys` encoding.
+///
+/// Incomplete escape sequences are kept until the next chunk arrives, except
+/// for a lone `ESC` at the end of a chunk, which is taken to be the Escape key.
+pub struct EventParser
+{
+    buffer: Vec<u8>,
+}
+
+impl Default for EventParser
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl EventParser
+{
+    pub fn new() -> Self
+    {
+        Self { buffer: Vec::new() }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<InputEvent>
+    {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        let mut start = 0;
+        while start < self.buffer.len()
+        {
+            match parse_event(&self.buffer[start..])
+            {
+                Parsed::Event(event, len) =>
+                {
+                    events.extend(event);
+                    start += len;
+                }
+                Parsed::Incomplete if self.buffer[start..] == [ESC] =>
+                {
+                    events.push(press(Key::Escape));
+                    start += 1;
+                }
+                Parsed::Incomplete => break,
+            }
+        }
+        self.buffer.drain(..start);
+        events
+    }
+}
+
+enum Parsed
+{
+    /// An event (or an ignored sequence) and the number of bytes it used.
+    Event(Option<InputEvent>, usize),
+    Incomplete,
+}
+
+fn press(key: Key) -> InputEvent
+{
+    InputEvent::Key(KeyEvent {
+        key,
+        action: KeyAction::Press,
+    })
+}
+
+fn parse_event(bytes: &[u8]) -> Parsed
+{
+    match bytes[0]
+    {
+        ESC => parse_escape(bytes),
+        b'\r' | b'\n' => Parsed::Event(Some(press(Key::Enter)), 1),
+        b'\t' => Parsed::Event(Some(press(Key::Tab)), 1),
+        0x7f | 0x08 => Parsed::Event(Some(press(Key::Backspace)), 1),
+        byte @ 0x00..=0x1f => Parsed::Event(Some(press(Key::Ctrl((byte + 0x60) as char))), 1),
+        _ => parse_utf8(bytes),
+    }
+}
+
+fn parse_utf8(bytes: &[u8]) -> Parsed
+{
+    let len = match bytes[0]
+    {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Parsed::Event(None, 1),
+    };
+    if bytes.len() < len
+    {
+        return Parsed::Incomplete;
+    }
+    match std::str::from_utf8(&bytes[..len])
+    {
+        Ok(s) => Parsed::Event(s.chars().next().map(|c| press(Key::Char(c))), len),
+        Err(_) => Parsed::Event(None, 1),
+    }
+}
+
+fn parse_escape(bytes: &[u8]) -> Parsed
+{
+    match bytes.get(1)
+    {
+        None => Parsed::Incomplete,
+        Some(b'[') => parse_csi(bytes),
+        Some(b'_') => parse_apc(bytes),
+        Some(b'O') => match bytes.get(2)
+        {
+            None => Parsed::Incomplete,
+            Some(&final_byte) => Parsed::Event(legacy_key(final_byte).map(press), 3),
+        },
+        // Alt+key; the modifier is of no use to us.
+        Some(_) => match parse_event(&bytes[1..])
+        {
+            Parsed::Event(event, len) => Parsed::Event(event, len + 1),
+            Parsed::Incomplete => Parsed::Incomplete,
+        },
+    }
+}
+
+/// Parses `ESC [ <parameters> <final byte>`.
+fn parse_csi(bytes: &[u8]) -> Parsed
+{
+    let Some(end) = bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b))
+    else
+    {
+        return Parsed::Incomplete;
+    };
+    let len = end + 3;
+    let final_byte = bytes[len - 1];
+    let params = String::from_utf8_lossy(&bytes[2..len - 1]);
+
+    if let Some(private) = params.strip_prefix('?')
+    {
+        let event = match final_byte
+        {
+            b'u' => private.parse().ok().map(InputEvent::KeyboardFlags),
+            // Attribute 4 means sixel graphics.
+            b'c' => Some(InputEvent::DeviceAttributes {
+                sixel: private.split(';').skip(1).any(|attribute| attribute == "4"),
+            }),
+            _ => None,
+        };
+        return Parsed::Event(event, len);
+    }
+
+    // Parameters look like `code[:alternates][;modifiers[:event]][;text]`.
+    let fields: Vec<Vec<u32>> = params
+        .split(';')
+        .map(|field| field.split(':').map(|n| n.parse().unwrap_or(0)).collect())
+        .collect();
+    let field = |index: usize, sub: usize| fields.get(index).and_then(|f| f.get(sub)).copied();
+    let action = match field(1, 1)
+    {
+        Some(2) => KeyAction::Repeat,
+        Some(3) => KeyAction::Release,
+        _ => KeyAction::Press,
+    };
+
+    // Modifiers are sent as one plus a bitmask in which Ctrl is 4.
+    let ctrl = field(1, 0).is_some_and(|m| m.saturating_sub(1) & 4 != 0);
+
+    let key = match final_byte
+    {
+        b'u' => field(0, 0).and_then(|code| codepoint_key(code, ctrl)),
+        b'~' => match field(0, 0)
+        {
+            // xterm modifyOtherKeys: `CSI 27 ; modifiers ; code ~`.
+            Some(27) => field(2, 0).and_then(|code| codepoint_key(code, ctrl)),
+            Some(n) => tilde_key(n),
+            None => None,
+        },
+        _ => legacy_key(final_byte),
+    };
+    let event = key.map(|key| InputEvent::Key(KeyEvent { key, action }));
+    Parsed::Event(event, len)
+}
+
+/// Parses `ESC _ <body> ESC \`, which kitty uses for graphics replies.
+fn parse_apc(bytes: &[u8]) -> Parsed
+{
+    let Some(end) = bytes.windows(2).position(|pair| pair == b"\x1b\\")
+    else
+    {
+        return Parsed::Incomplete;
+    };
+    let body = &bytes[2..end];
+    let event = body.strip_prefix(b"G").map(|reply| InputEvent::GraphicsReply {
+        ok: reply.ends_with(b";OK"),
+    });
+    Parsed::Event(event, end + 2)
+}
+
+fn codepoint_key(code: u32, ctrl: bool) -> Option<Key>
+{
+    match code
+    {
+        27 => Some(Key::Escape),
+        13 => Some(Key::Enter),
+        9 => Some(Key::Tab),
+        127 | 8 => Some(Key::Backspace),
+        _ =>
+        {
+            let c = char::from_u32(code).filter(|c| !c.is_control())?;
+            Some(if ctrl { Key::Ctrl(c) } else { Key::Char(c) })
+        }
+    }
+}
+
+/// Keys sent as `CSI <n> ~`.
+fn tilde_key(n: u32) -> Option<Key>
+{
+    let function = match n
+    {
+        15 => 5,
+        17..=21 => n - 11,
+        23 | 24 => n - 12,
+        _ => return None,
+    };
+    Some(Key::Function(function as u8))
+}
+
+/// Keys sent as `CSI <final>` or `SS3 <final>`.
+fn legacy_key(final_byte: u8) -> Option<Key>
+{
+    match final_byte
+    {
+        b'A' => Some(Key::Up),
+        b'B' => Some(Key::Down),
+        b'C' => Some(Key::Right),
+        b'D' => Some(Key::Left),
+        b'P'..=b'S' => Some(Key::Function(final_byte - b'P' + 1)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn key(key: Key, action: KeyAction) -> InputEvent
+    {
+        InputEvent::Key(KeyEvent { key, action })
+    }
+
+    #[test]
+    fn legacy_keys_test()
+    {
+        let mut parser = EventParser::new();
+        assert_eq!(
+            parser.feed(b"q\x1b[A\x1bOQ\x1b[15~"),
+            [
+                key(Key::Char('q'), KeyAction::Press),
+                key(Key::Up, KeyAction::Press),
+                key(Key::Function(2), KeyAction::Press),
+                key(Key::Function(5), KeyAction::Press),
+            ]
+        );
+    }
+
+    #[test]
+    fn kitty_keys_test()
+    {
+        // A split press, a repeat, a release and Ctrl+C.
+        let mut parser = EventParser::new();
+        assert_eq!(parser.feed(b"\x1b[11"), []);
+        assert_eq!(
+            parser.feed(b"3u\x1b[113;1:2u\x1b[113;1:3u\x1b[99;5u"),
+            [
+                key(Key::Char('q'), KeyAction::Press),
+                key(Key::Char('q'), KeyAction::Repeat),
+                key(Key::Char('q'), KeyAction::Release),
+                key(Key::Ctrl('c'), KeyAction::Press),
+            ]
+        );
+    }
+
+    #[test]
+    fn query_replies_test()
+    {
+        let mut parser = EventParser::new();
+        let replies = parser.feed(b"\x1b[?11u\x1b[?62;22c\x1b");
+        assert!(replies[0].reports_releases());
+        assert_eq!(
+            replies[1..],
+            [
+                InputEvent::DeviceAttributes { sixel: false },
+                key(Key::Escape, KeyAction::Press)
+            ]
+        );
+    }
+
+    #[test]
+    fn capabilities_test()
+    {
+        let mut capabilities = Capabilities::default();
+        let mut parser = EventParser::new();
+        let replies = parser.feed(b"\x1b[?11u\x1b_Gi=31;OK\x1b\\\x1b[?62;4;22c");
+        let last = replies.iter().position(|reply| capabilities.update(reply));
+        assert_eq!(last, Some(2));
+        assert_eq!(
+            capabilities,
+            Capabilities {
+                key_releases: true,
+                sixel: true,
+                kitty_graphics: true
+            }
+        );
+    }
+
+    #[test]
+    fn graphics_error_reply_test()
+    {
+        let mut parser = EventParser::new();
+        assert_eq!(
+            parser.feed(b"\x1b_Gi=31;ENOENT:bad format\x1b\\"),
+            [InputEvent::GraphicsReply { ok: false }]
+        );
+    }
+}
@@ -0,0 +1,481 @@
+use std::fmt;
+
+use crate::machine::{ChipRam, ChipRegisters};
+
+/// A fully decoded instruction together with its operands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpCode
+{
+    Cls,
+    Ret,
+    Jmp
+    {
+        nnn: u16,
+    },
+    Call
+    {
+        nnn: u16,
+    },
+    SeVxBy
+    {
+        x: u8,
+        kk: u8,
+    },
+    SneVxBy
+    {
+        x: u8,
+        kk: u8,
+    },
+    SeVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    LdVxBy
+    {
+        x: u8,
+        kk: u8,
+    },
+    Add
+    {
+        x: u8,
+        kk: u8,
+    },
+    LdVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    OrVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    AndVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    XorVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    AddVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    SubVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    ShrVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    SubnVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    ShlVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    SneVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    LdI
+    {
+        nnn: u16,
+    },
+    JpV0Addr
+    {
+        nnn: u16,
+    },
+    RndVxBy
+    {
+        x: u8,
+        kk: u8,
+    },
+    Display
+    {
+        x: u8,
+        y: u8,
+        n: u8,
+    },
+    SkpVx
+    {
+        x: u8,
+    },
+    SknpVx
+    {
+        x: u8,
+    },
+    LdVxDt
+    {
+        x: u8,
+    },
+    LdVxK
+    {
+        x: u8,
+    },
+    LdDtVx
+    {
+        x: u8,
+    },
+    LdStVx
+    {
+        x: u8,
+    },
+    AddIVx
+    {
+        x: u8,
+    },
+    LdFVx
+    {
+        x: u8,
+    },
+    LdBVx
+    {
+        x: u8,
+    },
+    LdIVx
+    {
+        x: u8,
+    },
+    LdVxI
+    {
+        x: u8,
+    },
+    /// `00CN`: scroll the display down N rows (SUPER-CHIP).
+    ScrollDown
+    {
+        n: u8,
+    },
+    /// `00FB`: scroll the display right 4 pixels (SUPER-CHIP).
+    ScrollRight,
+    /// `00FC`: scroll the display left 4 pixels (SUPER-CHIP).
+    ScrollLeft,
+    /// `00FD`: halt the interpreter (SUPER-CHIP).
+    Exit,
+    /// `00FE`: switch to 64x32 mode (SUPER-CHIP).
+    Lores,
+    /// `00FF`: switch to 128x64 mode (SUPER-CHIP).
+    Hires,
+    /// `FX30`: point I at the large font glyph for Vx (SUPER-CHIP).
+    LdHfVx
+    {
+        x: u8,
+    },
+    /// `FX75`: store V0..=Vx in the RPL user flags (SUPER-CHIP).
+    LdRVx
+    {
+        x: u8,
+    },
+    /// `FX85`: load V0..=Vx from the RPL user flags (SUPER-CHIP).
+    LdVxR
+    {
+        x: u8,
+    },
+    /// `00DN`: scroll the display up N rows (XO-CHIP).
+    ScrollUp
+    {
+        n: u8,
+    },
+    /// `5XY2`: store Vx..=Vy at I without changing I (XO-CHIP).
+    SaveVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    /// `5XY3`: load Vx..=Vy from I without changing I (XO-CHIP).
+    LoadVxVy
+    {
+        x: u8,
+        y: u8,
+    },
+    /// `FN01`: select the bit planes used for drawing (XO-CHIP).
+    Plane
+    {
+        n: u8,
+    },
+    /// `F000 NNNN`: load a 16-bit address into I (XO-CHIP). This is the only
+    /// four-byte instruction; see [`Instruction::is_long_load`].
+    LdILong
+    {
+        nnnn: u16,
+    },
+    /// `F002`: load the 16-byte audio pattern from I (XO-CHIP).
+    Audio,
+    /// `FX3A`: set the audio pitch register to Vx (XO-CHIP).
+    LdPitchVx
+    {
+        x: u8,
+    },
+}
+
+impl OpCode
+{
+    /// The bytes of the instruction; the inverse of [`Instruction::decode`].
+    /// Only `LdILong` takes four bytes.
+    pub fn encode(self) -> Vec<u8>
+    {
+        let xy = |high: u8, x: u8, y: u8, low: u8| {
+            (high as u16) << 12 | (x as u16) << 8 | (y as u16) << 4 | low as u16
+        };
+        let xkk = |high: u8, x: u8, kk: u8| (high as u16) << 12 | (x as u16) << 8 | kk as u16;
+        let addr = |high: u8, nnn: u16| (high as u16) << 12 | nnn & 0xFFF;
+        let word = match self
+        {
+            OpCode::Cls => 0x00E0,
+            OpCode::Ret => 0x00EE,
+            OpCode::Jmp { nnn } => addr(0x1, nnn),
+            OpCode::Call { nnn } => addr(0x2, nnn),
+            OpCode::SeVxBy { x, kk } => xkk(0x3, x, kk),
+            OpCode::SneVxBy { x, kk } => xkk(0x4, x, kk),
+            OpCode::SeVxVy { x, y } => xy(0x5, x, y, 0x0),
+            OpCode::LdVxBy { x, kk } => xkk(0x6, x, kk),
+            OpCode::Add { x, kk } => xkk(0x7, x, kk),
+            OpCode::LdVxVy { x, y } => xy(0x8, x, y, 0x0),
+            OpCode::OrVxVy { x, y } => xy(0x8, x, y, 0x1),
+            OpCode::AndVxVy { x, y } => xy(0x8, x, y, 0x2),
+            OpCode::XorVxVy { x, y } => xy(0x8, x, y, 0x3),
+            OpCode::AddVxVy { x, y } => xy(0x8, x, y, 0x4),
+            OpCode::SubVxVy { x, y } => xy(0x8, x, y, 0x5),
+            OpCode::ShrVxVy { x, y } => xy(0x8, x, y, 0x6),
+            OpCode::SubnVxVy { x, y } => xy(0x8, x, y, 0x7),
+            OpCode::ShlVxVy { x, y } => xy(0x8, x, y, 0xE),
+            OpCode::SneVxVy { x, y } => xy(0x9, x, y, 0x0),
+            OpCode::LdI { nnn } => addr(0xA, nnn),
+            OpCode::JpV0Addr { nnn } => addr(0xB, nnn),
+            OpCode::RndVxBy { x, kk } => xkk(0xC, x, kk),
+            OpCode::Display { x, y, n } => xy(0xD, x, y, n),
+            OpCode::SkpVx { x } => xkk(0xE, x, 0x9E),
+            OpCode::SknpVx { x } => xkk(0xE, x, 0xA1),
+            OpCode::LdVxDt { x } => xkk(0xF, x, 0x07),
+            OpCode::LdVxK { x } => xkk(0xF, x, 0x0A),
+            OpCode::LdDtVx { x } => xkk(0xF, x, 0x15),
+            OpCode::LdStVx { x } => xkk(0xF, x, 0x18),
+            OpCode::AddIVx { x } => xkk(0xF, x, 0x1E),
+            OpCode::LdFVx { x } => xkk(0xF, x, 0x29),
+            OpCode::LdBVx { x } => xkk(0xF, x, 0x33),
+            OpCode::LdIVx { x } => xkk(0xF, x, 0x55),
+            OpCode::LdVxI { x } => xkk(0xF, x, 0x65),
+            OpCode::ScrollDown { n } => 0x00C0 | n as u16,
+            OpCode::ScrollRight => 0x00FB,
+            OpCode::ScrollLeft => 0x00FC,
+            OpCode::Exit => 0x00FD,
+            OpCode::Lores => 0x00FE,
+            OpCode::Hires => 0x00FF,
+            OpCode::LdHfVx { x } => xkk(0xF, x, 0x30),
+            OpCode::LdRVx { x } => xkk(0xF, x, 0x75),
+            OpCode::LdVxR { x } => xkk(0xF, x, 0x85),
+            OpCode::ScrollUp { n } => 0x00D0 | n as u16,
+            OpCode::SaveVxVy { x, y } => xy(0x5, x, y, 0x2),
+            OpCode::LoadVxVy { x, y } => xy(0x5, x, y, 0x3),
+            OpCode::Plane { n } => xkk(0xF, n, 0x01),
+            OpCode::LdILong { nnnn } =>
+            {
+                let [high, low] = nnnn.to_be_bytes();
+                return vec![0xF0, 0x00, high, low];
+            }
+            OpCode::Audio => 0xF002,
+            OpCode::LdPitchVx { x } => xkk(0xF, x, 0x3A),
+        };
+        word.to_be_bytes().to_vec()
+    }
+}
+
+#[derive(Debug)]
+pub struct Instruction
+{
+    pub data: [u8; 2],
+}
+
+impl fmt::Display for Instruction
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "\r\n Instruction display:").unwrap();
+        for i in self.data
+        {
+            write!(f, "\r\n {:x?}", i).unwrap();
+        }
+        Ok(())
+    }
+}
+
+impl Instruction
+{
+    /// Fetches the instruction at PC and advances PC past it, or returns
+    /// `None` if the instruction would be read from outside of RAM.
+    pub fn get_next_instruction(ram: &ChipRam, registers: &mut ChipRegisters)
+        -> Option<Instruction>
+    {
+        let pc = registers.pc as usize;
+        let next_instruction = [*ram.get(pc)?, *ram.get(pc + 1)?];
+        registers.pc = registers.pc.wrapping_add(2);
+        Some(Instruction::new(next_instruction))
+    }
+
+    /// Whether this is the first half of the XO-CHIP `F000 NNNN` long load,
+    /// whose operand is the following instruction word. [`Instruction::decode`]
+    /// returns `None` for it since it cannot see the operand.
+    pub fn is_long_load(&self) -> bool
+    {
+        self.data == [0xF0, 0x00]
+    }
+
+    /// Decodes the instruction by switching on its nibbles; this never
+    /// allocates and is called once per executed instruction.
+    pub fn decode(&self) -> Option<OpCode>
+    {
+        let x = self.get_x();
+        let y = self.get_y();
+        let n = self.get_n();
+        let kk = self.get_kk();
+        let nnn = self.get_nnn();
+        let op = match self.get_bits()
+        {
+            [0x0, 0x0, 0xE, 0x0] => OpCode::Cls,
+            [0x0, 0x0, 0xE, 0xE] => OpCode::Ret,
+            [0x0, 0x0, 0xC, _] => OpCode::ScrollDown { n },
+            [0x0, 0x0, 0xD, _] => OpCode::ScrollUp { n },
+            [0x0, 0x0, 0xF, 0xB] => OpCode::ScrollRight,
+            [0x0, 0x0, 0xF, 0xC] => OpCode::ScrollLeft,
+            [0x0, 0x0, 0xF, 0xD] => OpCode::Exit,
+            [0x0, 0x0, 0xF, 0xE] => OpCode::Lores,
+            [0x0, 0x0, 0xF, 0xF] => OpCode::Hires,
+            [0x1, ..] => OpCode::Jmp { nnn },
+            [0x2, ..] => OpCode::Call { nnn },
+            [0x3, ..] => OpCode::SeVxBy { x, kk },
+            [0x4, ..] => OpCode::SneVxBy { x, kk },
+            [0x5, _, _, 0x0] => OpCode::SeVxVy { x, y },
+            [0x5, _, _, 0x2] => OpCode::SaveVxVy { x, y },
+            [0x5, _, _, 0x3] => OpCode::LoadVxVy { x, y },
+            [0x6, ..] => OpCode::LdVxBy { x, kk },
+            [0x7, ..] => OpCode::Add { x, kk },
+            [0x8, _, _, 0x0] => OpCode::LdVxVy { x, y },
+            [0x8, _, _, 0x1] => OpCode::OrVxVy { x, y },
+            [0x8, _, _, 0x2] => OpCode::AndVxVy { x, y },
+            [0x8, _, _, 0x3] => OpCode::XorVxVy { x, y },
+            [0x8, _, _, 0x4] => OpCode::AddVxVy { x, y },
+            [0x8, _, _, 0x5] => OpCode::SubVxVy { x, y },
+            [0x8, _, _, 0x6] => OpCode::ShrVxVy { x, y },
+            [0x8, _, _, 0x7] => OpCode::SubnVxVy { x, y },
+            [0x8, _, _, 0xE] => OpCode::ShlVxVy { x, y },
+            [0x9, _, _, 0x0] => OpCode::SneVxVy { x, y },
+            [0xA, ..] => OpCode::LdI { nnn },
+            [0xB, ..] => OpCode::JpV0Addr { nnn },
+            [0xC, ..] => OpCode::RndVxBy { x, kk },
+            [0xD, ..] => OpCode::Display { x, y, n },
+            [0xE, _, 0x9, 0xE] => OpCode::SkpVx { x },
+            [0xE, _, 0xA, 0x1] => OpCode::SknpVx { x },
+            [0xF, _, 0x0, 0x1] => OpCode::Plane { n: x },
+            [0xF, 0x0, 0x0, 0x2] => OpCode::Audio,
+            [0xF, _, 0x0, 0x7] => OpCode::LdVxDt { x },
+            [0xF, _, 0x0, 0xA] => OpCode::LdVxK { x },
+            [0xF, _, 0x1, 0x5] => OpCode::LdDtVx { x },
+            [0xF, _, 0x1, 0x8] => OpCode::LdStVx { x },
+            [0xF, _, 0x1, 0xE] => OpCode::AddIVx { x },
+            [0xF, _, 0x2, 0x9] => OpCode::LdFVx { x },
+            [0xF, _, 0x3, 0x0] => OpCode::LdHfVx { x },
+            [0xF, _, 0x3, 0x3] => OpCode::LdBVx { x },
+            [0xF, _, 0x3, 0xA] => OpCode::LdPitchVx { x },
+            [0xF, _, 0x5, 0x5] => OpCode::LdIVx { x },
+            [0xF, _, 0x6, 0x5] => OpCode::LdVxI { x },
+            [0xF, _, 0x7, 0x5] => OpCode::LdRVx { x },
+            [0xF, _, 0x8, 0x5] => OpCode::LdVxR { x },
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    pub fn get_nnn(&self) -> u16
+    {
+        let mut k = self.data[1] as u16;
+        let mut j = (self.data[0] & 0b1111) as u16;
+        j <<= 8;
+        k |= j;
+        k
+    }
+    pub fn get_n(&self) -> u8
+    {
+        self.data[1] & 0b1111
+    }
+    pub fn get_x(&self) -> u8
+    {
+        self.data[0] & 0b1111
+    }
+    pub fn get_y(&self) -> u8
+    {
+        self.data[1] >> 4
+    }
+    pub fn get_kk(&self) -> u8
+    {
+        self.data[1]
+    }
+    pub fn get_bits(&self) -> [u8; 4]
+    {
+        [
+            self.data[0] >> 4,
+            self.data[0] & 0b1111,
+            self.data[1] >> 4,
+            self.data[1] & 0b1111,
+        ]
+    }
+
+    pub fn new(next_instruction: [u8; 2]) -> Instruction
+    {
+        Self {
+            data: next_instruction,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn instruction_reading_test()
+    {
+        let ins = Instruction::new([0xF1, 0x2B]);
+        assert_eq!(ins.get_nnn(), 0x12B);
+        assert_eq!(ins.get_n(), 0xB);
+        assert_eq!(ins.get_x(), 0x1);
+        assert_eq!(ins.get_y(), 0x2);
+        assert_eq!(ins.get_kk(), 0x2B);
+    }
+
+    #[test]
+    fn instruction_decode_test()
+    {
+        let decode = |hi, lo| Instruction::new([hi, lo]).decode();
+        assert_eq!(decode(0x00, 0xE0), Some(OpCode::Cls));
+        assert_eq!(decode(0x12, 0xA0), Some(OpCode::Jmp { nnn: 0x2A0 }));
+        assert_eq!(decode(0x63, 0x1F), Some(OpCode::LdVxBy { x: 3, kk: 0x1F }));
+        assert_eq!(decode(0x8A, 0xB4), Some(OpCode::AddVxVy { x: 0xA, y: 0xB }));
+        assert_eq!(
+            decode(0xD0, 0x15),
+            Some(OpCode::Display { x: 0, y: 1, n: 5 })
+        );
+        assert_eq!(decode(0xF2, 0x65), Some(OpCode::LdVxI { x: 2 }));
+    }
+
+    #[test]
+    fn instruction_decode_unknown_test()
+    {
+        let decode = |hi, lo| Instruction::new([hi, lo]).decode();
+        assert_eq!(decode(0x51, 0x21), None);
+        assert_eq!(decode(0xFF, 0xFF), None);
+    }
+}
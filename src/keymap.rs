@@ -0,0 +1,490 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crate::config::{parse_string, parse_string_list, ConfigError, Entry};
+use crate::host_input::Key;
+use crate::keypad::KEY_COUNT;
+
+/// Things the emulator itself does in response to a key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action
+{
+    Quit,
+    Pause,
+    Reset,
+    SaveState,
+    LoadState,
+    SpeedUp,
+    SpeedDown,
+}
+
+impl Action
+{
+    pub const ALL: [Action; 7] = [
+        Action::Quit,
+        Action::Pause,
+        Action::Reset,
+        Action::SaveState,
+        Action::LoadState,
+        Action::SpeedUp,
+        Action::SpeedDown,
+    ];
+
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Action::Quit => "quit",
+            Action::Pause => "pause",
+            Action::Reset => "reset",
+            Action::SaveState => "save-state",
+            Action::LoadState => "load-state",
+            Action::SpeedUp => "speed-up",
+            Action::SpeedDown => "speed-down",
+        }
+    }
+
+    fn default_key(self) -> Key
+    {
+        match self
+        {
+            Action::Quit => Key::Char('m'),
+            Action::Pause => Key::Char(' '),
+            Action::Reset => Key::Function(2),
+            Action::SaveState => Key::Function(5),
+            Action::LoadState => Key::Function(9),
+            Action::SpeedUp => Key::Char('='),
+            Action::SpeedDown => Key::Char('-'),
+        }
+    }
+}
+
+/// What a host key does when pressed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Binding
+{
+    /// One of the 16 hex keys.
+    Keypad(u8),
+    Action(Action),
+}
+
+/// Where the 4x4 hex keypad sits on the host keyboard.
+///
+/// Every layout uses the same physical block of keys: the four keys under
+/// 1 to 4 on the number row and the three rows below them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Layout
+{
+    #[default]
+    Qwerty,
+    Azerty,
+    Dvorak,
+}
+
+impl Layout
+{
+    pub const NAMES: &'static [&'static str] = &["qwerty", "azerty", "dvorak"];
+
+    /// The host key for each hex key, indexed by the hex key.
+    pub fn keys(self) -> [char; KEY_COUNT]
+    {
+        let block: [[char; 4]; 4] = match self
+        {
+            Layout::Qwerty => [
+                ['1', '2', '3', '4'],
+                ['q', 'w', 'e', 'r'],
+                ['a', 's', 'd', 'f'],
+                ['z', 'x', 'c', 'v'],
+            ],
+            Layout::Azerty => [
+                ['&', 'é', '"', '\''],
+                ['a', 'z', 'e', 'r'],
+                ['q', 's', 'd', 'f'],
+                ['w', 'x', 'c', 'v'],
+            ],
+            Layout::Dvorak => [
+                ['1', '2', '3', '4'],
+                ['\'', ',', '.', 'p'],
+                ['a', 'o', 'e', 'u'],
+                [';', 'q', 'j', 'k'],
+            ],
+        };
+        // The COSMAC VIP keypad, row by row.
+        const HEX_KEYPAD: [[usize; 4]; 4] = [
+            [0x1, 0x2, 0x3, 0xC],
+            [0x4, 0x5, 0x6, 0xD],
+            [0x7, 0x8, 0x9, 0xE],
+            [0xA, 0x0, 0xB, 0xF],
+        ];
+        let mut keys = [' '; KEY_COUNT];
+        for (row, hex_row) in block.iter().zip(HEX_KEYPAD)
+        {
+            for (c, hex) in row.iter().zip(hex_row)
+            {
+                keys[hex] = *c;
+            }
+        }
+        keys
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownLayout(pub String);
+
+impl fmt::Display for UnknownLayout
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(
+            f,
+            "unknown keyboard layout '{}', expected one of: {}",
+            self.0,
+            Layout::NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownLayout {}
+
+impl FromStr for Layout
+{
+    type Err = UnknownLayout;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_ascii_lowercase().as_str()
+        {
+            "qwerty" => Ok(Layout::Qwerty),
+            "azerty" => Ok(Layout::Azerty),
+            "dvorak" => Ok(Layout::Dvorak),
+            _ => Err(UnknownLayout(s.to_string())),
+        }
+    }
+}
+
+/// The binding of every host key that does something.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap
+{
+    bindings: HashMap<Key, Binding>,
+}
+
+impl Default for Keymap
+{
+    fn default() -> Self
+    {
+        Self::new(Layout::default())
+    }
+}
+
+impl Keymap
+{
+    /// The keypad laid out as `layout`, with the default action keys.
+    pub fn new(layout: Layout) -> Self
+    {
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+        };
+        for (hex, c) in layout.keys().into_iter().enumerate()
+        {
+            keymap.bind(Key::Char(c), Binding::Keypad(hex as u8));
+        }
+        for action in Action::ALL
+        {
+            keymap.bind(action.default_key(), Binding::Action(action));
+        }
+        keymap
+    }
+
+    pub fn bind(&mut self, key: Key, binding: Binding)
+    {
+        self.bindings.insert(normalize(key), binding);
+    }
+
+    /// Binds `action` to `keys` only, dropping the keys it had before.
+    pub fn bind_action(&mut self, action: Action, keys: &[Key])
+    {
+        self.bindings.retain(|_, binding| *binding != Binding::Action(action));
+        for key in keys
+        {
+            self.bind(*key, Binding::Action(action));
+        }
+    }
+
+    pub fn lookup(&self, key: Key) -> Option<Binding>
+    {
+        self.bindings.get(&normalize(key)).copied()
+    }
+}
+
+/// Bindings are case-insensitive.
+fn normalize(key: Key) -> Key
+{
+    match key
+    {
+        Key::Char(c) => Key::Char(c.to_lowercase().next().unwrap_or(c)),
+        key => key,
+    }
+}
+
+/// The FNV-1a hash of a ROM, which names its section in a keymap file.
+pub fn rom_hash(rom: &[u8]) -> u64
+{
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Overrides
+{
+    layout: Option<Layout>,
+    keys: Vec<(Key, u8)>,
+    actions: Vec<(Action, Vec<Key>)>,
+}
+
+/// The key bindings from the config file: a layout, extra bindings, and
+/// overrides per ROM. See [`crate::config::Config`] for the format.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeymapConfig
+{
+    global: Overrides,
+    roms: HashMap<u64, Overrides>,
+}
+
+impl KeymapConfig
+{
+    /// Takes in one entry of the config file.
+    pub fn apply(&mut self, entry: &Entry) -> Result<(), ConfigError>
+    {
+        let error = |message: String| entry.error(message);
+        let section: Vec<&str> = entry.section.iter().map(String::as_str).collect();
+        let (rom, table) = match section.as_slice()
+        {
+            ["rom", hash, table @ ..] =>
+            {
+                let hash = u64::from_str_radix(hash, 16)
+                    .map_err(|_| error(format!("'{}' is not a ROM hash", hash)))?;
+                (Some(hash), table)
+            }
+            table => (None, table),
+        };
+        let overrides = self.overrides(rom);
+        let name = entry.name.as_str();
+        match table
+        {
+            [] if name == "layout" =>
+            {
+                let layout = parse_string(&entry.value).map_err(error)?;
+                overrides.layout =
+                    Some(layout.parse().map_err(|e: UnknownLayout| error(e.to_string()))?);
+            }
+            [] =>
+            {
+                let place = rom.map_or("the top level".to_string(), |hash| {
+                    format!("rom.{:016x}", hash)
+                });
+                return Err(error(format!("unknown setting '{}' in {}", name, place)));
+            }
+            ["keys"] =>
+            {
+                let key = parse_key(name).map_err(error)?;
+                let hex = parse_hex_key(&entry.value).map_err(error)?;
+                overrides.keys.push((key, hex));
+            }
+            ["actions"] =>
+            {
+                let action = Action::ALL
+                    .into_iter()
+                    .find(|action| action.name() == name)
+                    .ok_or_else(|| error(format!("unknown action '{}'", name)))?;
+                let keys = parse_string_list(&entry.value)
+                    .map_err(error)?
+                    .iter()
+                    .map(|key| parse_key(key))
+                    .collect::<Result<_, _>>()
+                    .map_err(error)?;
+                overrides.actions.push((action, keys));
+            }
+            _ =>
+            {
+                return Err(error(format!(
+                    "unknown section [{}]",
+                    entry.section.join(".")
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn overrides(&mut self, rom: Option<u64>) -> &mut Overrides
+    {
+        match rom
+        {
+            Some(hash) => self.roms.entry(hash).or_default(),
+            None => &mut self.global,
+        }
+    }
+
+    /// The keymap for `rom`: its layout and bindings, falling back to the
+    /// file-wide ones, and `layout` if the file does not pick one.
+    pub fn keymap_for(&self, rom: &[u8], layout: Layout) -> Keymap
+    {
+        let rom_overrides = self.roms.get(&rom_hash(rom));
+        let layout = rom_overrides.and_then(|o| o.layout).or(self.global.layout).unwrap_or(layout);
+        let mut keymap = Keymap::new(layout);
+        for overrides in std::iter::once(&self.global).chain(rom_overrides)
+        {
+            for (key, hex) in &overrides.keys
+            {
+                keymap.bind(*key, Binding::Keypad(*hex));
+            }
+            for (action, keys) in &overrides.actions
+            {
+                keymap.bind_action(*action, keys);
+            }
+        }
+        keymap
+    }
+}
+
+/// A hex key is an integer (`5`, `0xA`) or a hex digit string (`"A"`).
+fn parse_hex_key(value: &str) -> Result<u8, String>
+{
+    let parsed = if value.starts_with('"')
+    {
+        u8::from_str_radix(&parse_string(value)?, 16).ok()
+    }
+    else if let Some(hex) = value.strip_prefix("0x")
+    {
+        u8::from_str_radix(hex, 16).ok()
+    }
+    else
+    {
+        value.parse().ok()
+    };
+    parsed
+        .filter(|hex| (*hex as usize) < KEY_COUNT)
+        .ok_or_else(|| format!("'{}' is not a hex key between 0 and F", value))
+}
+
+/// Parses a host key name: a single character, or one of `space`, `enter`,
+/// `tab`, `escape`, `backspace`, the arrow keys and `f1` to `f12`.
+pub fn parse_key(name: &str) -> Result<Key, String>
+{
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next())
+    {
+        return Ok(normalize(Key::Char(c)));
+    }
+    let lower = name.to_ascii_lowercase();
+    let key = match lower.as_str()
+    {
+        "space" => Key::Char(' '),
+        "enter" | "return" => Key::Enter,
+        "tab" => Key::Tab,
+        "escape" | "esc" => Key::Escape,
+        "backspace" => Key::Backspace,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        _ => match lower.strip_prefix('f').and_then(|n| n.parse().ok())
+        {
+            Some(n @ 1..=12) => Key::Function(n),
+            _ => return Err(format!("unknown key '{}'", name)),
+        },
+    };
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn default_layout_test()
+    {
+        let qwerty = Keymap::default();
+        assert_eq!(qwerty.lookup(Key::Char('x')), Some(Binding::Keypad(0x0)));
+        assert_eq!(qwerty.lookup(Key::Char('V')), Some(Binding::Keypad(0xF)));
+        assert_eq!(
+            qwerty.lookup(Key::Char('m')),
+            Some(Binding::Action(Action::Quit))
+        );
+    }
+
+    #[test]
+    fn other_layouts_test()
+    {
+        let azerty = Keymap::new(Layout::Azerty);
+        assert_eq!(azerty.lookup(Key::Char('&')), Some(Binding::Keypad(0x1)));
+        assert_eq!(azerty.lookup(Key::Char('a')), Some(Binding::Keypad(0x4)));
+        let dvorak = Keymap::new(Layout::Dvorak);
+        assert_eq!(dvorak.lookup(Key::Char(';')), Some(Binding::Keypad(0xA)));
+    }
+
+    const ROM: [u8; 2] = [0x12, 0x00];
+
+    fn config() -> KeymapConfig
+    {
+        let text = format!(
+            r##"
+            layout = "dvorak" # applies to every ROM
+            [keys]
+            up = 5
+            "#" = "c"
+
+            [actions]
+            quit = ["escape", "Q"]
+            save-state = "f6"
+
+            [rom.{:016x}]
+            layout = "qwerty"
+            [rom.{:016x}.keys]
+            i = 0x5
+            "##,
+            rom_hash(&ROM),
+            rom_hash(&ROM)
+        );
+        Config::parse(&text).unwrap().keymap
+    }
+
+    #[test]
+    fn global_bindings_test()
+    {
+        let other = config().keymap_for(&[0x00, 0xE0], Layout::Qwerty);
+        assert_eq!(other.lookup(Key::Char('o')), Some(Binding::Keypad(0x8)));
+        assert_eq!(other.lookup(Key::Up), Some(Binding::Keypad(0x5)));
+        assert_eq!(other.lookup(Key::Char('#')), Some(Binding::Keypad(0xC)));
+    }
+
+    #[test]
+    fn action_bindings_replace_defaults_test()
+    {
+        let other = config().keymap_for(&[0x00, 0xE0], Layout::Qwerty);
+        assert_eq!(
+            other.lookup(Key::Escape),
+            Some(Binding::Action(Action::Quit))
+        );
+        assert_eq!(
+            other.lookup(Key::Char('q')),
+            Some(Binding::Action(Action::Quit))
+        );
+        assert_eq!(other.lookup(Key::Char('m')), None);
+        assert_eq!(other.lookup(Key::Function(5)), None);
+    }
+
+    #[test]
+    fn rom_overrides_test()
+    {
+        let game = config().keymap_for(&ROM, Layout::Dvorak);
+        assert_eq!(game.lookup(Key::Char('w')), Some(Binding::Keypad(0x5)));
+        assert_eq!(game.lookup(Key::Char('i')), Some(Binding::Keypad(0x5)));
+        assert_eq!(
+            game.lookup(Key::Function(6)),
+            Some(Binding::Action(Action::SaveState))
+        );
+    }
+}
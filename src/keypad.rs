@@ -0,0 +1,71 @@
+use std::fmt;
+
+pub const KEY_COUNT: usize = 16;
+
+/// The 16 keys of the hex keypad as a bitmask, bit `n` being key `n`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct KeypadState(pub u16);
+
+impl KeypadState
+{
+    pub fn new() -> Self
+    {
+        Self(0)
+    }
+
+    pub fn is_down(self, key: usize) -> bool
+    {
+        self.0 & (1 << (key & 0xF)) != 0
+    }
+
+    pub fn set(&mut self, key: usize, down: bool)
+    {
+        let bit = 1 << (key & 0xF);
+        if down
+        {
+            self.0 |= bit;
+        }
+        else
+        {
+            self.0 &= !bit;
+        }
+    }
+
+    /// The keys that are down, lowest first.
+    pub fn keys_down(self) -> impl Iterator<Item = usize>
+    {
+        (0..KEY_COUNT).filter(move |key| self.is_down(*key))
+    }
+}
+
+impl From<[bool; KEY_COUNT]> for KeypadState
+{
+    fn from(keys: [bool; KEY_COUNT]) -> Self
+    {
+        let mut state = Self::new();
+        for (key, down) in keys.into_iter().enumerate()
+        {
+            state.set(key, down);
+        }
+        state
+    }
+}
+
+impl fmt::Display for KeypadState
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        for key in 0..KEY_COUNT
+        {
+            if self.is_down(key)
+            {
+                write!(f, "{:X}", key)?;
+            }
+            else
+            {
+                f.write_str("-")?;
+            }
+        }
+        Ok(())
+    }
+}
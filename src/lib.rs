@@ -0,0 +1,31 @@
+pub mod asm;
+pub mod audio;
+pub mod bitmap;
+pub mod cli;
+pub mod config;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod guest_graphics;
+pub mod headless;
+pub mod host_graphics;
+pub mod host_input;
+pub mod instruction;
+pub mod keymap;
+pub mod keypad;
+pub mod machine;
+pub mod octo;
+pub mod palette;
+pub mod presenter;
+pub mod quirks;
+pub mod renderer;
+pub mod rng;
+pub mod scheduler;
+pub mod shutdown;
+
+pub use crate::error::Chip8Error;
+pub use crate::guest_graphics::{get_fonts, ChipDisplay, Sprite};
+pub use crate::instruction::{Instruction, OpCode};
+pub use crate::keypad::KeypadState;
+pub use crate::machine::{Chip8, ChipRam, ChipRegisters, MachineState, SaveState};
+pub use crate::quirks::{QuirkProfile, Quirks};
@@ -0,0 +1,1306 @@
+use std::{fmt, fs, ops::BitAnd, ops::Range, path::Path};
+
+use crate::audio::{AudioState, DEFAULT_PATTERN, DEFAULT_PITCH, PATTERN_SIZE};
+use crate::error::Chip8Error;
+
+use crate::guest_graphics::{get_big_fonts, get_fonts, ChipDisplay, Sprite, ALL_PLANES};
+use crate::instruction::{Instruction, OpCode};
+use crate::keypad::{KeypadState, KEY_COUNT};
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rng::Rng;
+
+pub const FONT_RAM_OFFSET: usize = 0x0;
+pub const BIG_FONT_RAM_OFFSET: usize = 0x50;
+pub const ROM_RAM_OFFSET: usize = 0x200;
+
+/// Memory size of CHIP-8 and SUPER-CHIP machines.
+pub const RAM_SIZE: usize = 0x1000;
+/// Memory size of XO-CHIP machines.
+pub const XO_RAM_SIZE: usize = 0x10000;
+
+pub type ChipRam = Vec<u8>;
+
+/// Whether the machine is still executing instructions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MachineState
+{
+    Running,
+    /// `FX0A` is blocked until a key is pressed (and, with the
+    /// `key_wait_release` quirk, released) and then stored in V`register`.
+    WaitingForKey
+    {
+        register: u8,
+    },
+    /// The program executed `00FD`; [`Chip8::step`] does nothing from now on.
+    Halted,
+}
+
+/// Whether an instruction read or wrote memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessKind
+{
+    Read,
+    Write,
+}
+
+/// Bytes of RAM an instruction read or wrote as data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryAccess
+{
+    pub kind: AccessKind,
+    pub range: Range<usize>,
+}
+
+/// A register of [`ChipRegisters`] that instructions read and write as data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterName
+{
+    V(u8),
+    I,
+    Sp,
+    Delay,
+    Sound,
+}
+
+/// A register an instruction read or wrote. Writes count even when the value
+/// stays the same.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RegisterAccess
+{
+    pub kind: AccessKind,
+    pub register: RegisterName,
+}
+
+/// A complete CHIP-8 machine: memory, registers, framebuffer and keypad.
+///
+/// The machine does no I/O of its own; front-ends feed it key state through
+/// [`Chip8::set_keypad`], call [`Chip8::step`] or [`Chip8::run_cycles`] to execute
+/// instructions and [`Chip8::tick_timers`] at 60 Hz, and read the framebuffer
+/// back out of [`Chip8::display`].
+#[derive(Clone)]
+pub struct Chip8
+{
+    pub ram: ChipRam,
+    pub registers: ChipRegisters,
+    pub display: ChipDisplay,
+    pub keypad: KeypadState,
+    pub quirks: Quirks,
+    /// The SUPER-CHIP RPL user flags saved and restored by `FX75`/`FX85`.
+    pub rpl_flags: [u8; 16],
+    /// The XO-CHIP audio pattern loaded by `F002`.
+    pub audio_pattern: [u8; PATTERN_SIZE],
+    pub state: MachineState,
+    pub rng: Rng,
+    /// Set after a draw when [`Quirks::display_wait`] is on; execution stalls
+    /// until the next frame.
+    waiting_for_vblank: bool,
+    /// The key pressed during `FX0A` that has not been released yet.
+    pending_key: Option<u8>,
+    /// What the last instruction did to memory.
+    accesses: Vec<MemoryAccess>,
+    /// What the last instruction did to the registers.
+    register_accesses: Vec<RegisterAccess>,
+}
+
+impl Chip8
+{
+    pub fn new() -> Self
+    {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self
+    {
+        let mut machine = Self {
+            ram: vec![0; quirks.memory_size],
+            registers: ChipRegisters::new(),
+            display: ChipDisplay::new(),
+            keypad: KeypadState::new(),
+            quirks,
+            rpl_flags: [0; 16],
+            audio_pattern: DEFAULT_PATTERN,
+            state: MachineState::Running,
+            rng: Rng::default(),
+            waiting_for_vblank: false,
+            pending_key: None,
+            accesses: Vec::new(),
+            register_accesses: Vec::new(),
+        };
+        for (i, val) in get_fonts().iter().enumerate()
+        {
+            load_into_ram(
+                &val.sprite_data[..val.height as usize],
+                &mut machine.ram,
+                FONT_RAM_OFFSET + i * 5,
+            );
+        }
+        for (i, glyph) in get_big_fonts().iter().enumerate()
+        {
+            load_into_ram(
+                glyph,
+                &mut machine.ram,
+                BIG_FONT_RAM_OFFSET + i * glyph.len(),
+            );
+        }
+        machine.registers.pc = ROM_RAM_OFFSET as u16;
+        machine
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error>
+    {
+        let max = self.ram.len() - ROM_RAM_OFFSET;
+        if rom.len() > max
+        {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+        load_into_ram(rom, &mut self.ram, ROM_RAM_OFFSET);
+        Ok(())
+    }
+
+    pub fn load_rom_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Chip8Error>
+    {
+        let rom = fs::read(path)?;
+        self.load_rom(&rom)
+    }
+
+    /// Restarts the random number generator from `seed`.
+    pub fn set_seed(&mut self, seed: u64)
+    {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Snapshots the machine, including the random number generator.
+    pub fn save_state(&self) -> SaveState
+    {
+        SaveState {
+            machine: self.clone(),
+        }
+    }
+
+    /// Restores a snapshot taken by [`Chip8::save_state`]. The keypad is left
+    /// alone since it mirrors the host's keyboard.
+    pub fn load_state(&mut self, save_state: &SaveState)
+    {
+        let keypad = self.keypad;
+        *self = save_state.machine.clone();
+        self.keypad = keypad;
+    }
+
+    /// Updates one key of the keypad, completing a pending `FX0A` when the
+    /// key goes down or comes back up, depending on the quirks.
+    pub fn set_key(&mut self, key: usize, down: bool)
+    {
+        let was_down = self.keypad.is_down(key);
+        self.keypad.set(key, down);
+        let MachineState::WaitingForKey { register } = self.state
+        else
+        {
+            return;
+        };
+        match self.pending_key
+        {
+            None if down && !was_down =>
+            {
+                if self.quirks.key_wait_release
+                {
+                    self.pending_key = Some(key as u8);
+                }
+                else
+                {
+                    self.finish_key_wait(register, key as u8);
+                }
+            }
+            Some(pending) if pending as usize == key && !down =>
+            {
+                self.finish_key_wait(register, pending);
+            }
+            _ => (),
+        }
+    }
+
+    /// Replaces the whole keypad, as [`Chip8::set_key`] would key by key.
+    pub fn set_keypad(&mut self, keypad: KeypadState)
+    {
+        for key in 0..KEY_COUNT
+        {
+            self.set_key(key, keypad.is_down(key));
+        }
+    }
+
+    fn finish_key_wait(&mut self, register: u8, key: u8)
+    {
+        self.registers.v[register as usize] = key;
+        self.pending_key = None;
+        self.state = MachineState::Running;
+    }
+
+    /// Decrements the delay and sound timers; call this once per 60 Hz frame.
+    pub fn tick_timers(&mut self)
+    {
+        self.waiting_for_vblank = false;
+        if self.registers.delay > 0
+        {
+            self.registers.delay -= 1;
+        }
+        if self.registers.sound > 0
+        {
+            self.registers.sound -= 1;
+        }
+    }
+
+    pub fn audio_state(&self) -> AudioState
+    {
+        AudioState {
+            pattern: self.audio_pattern,
+            pitch: self.registers.pitch,
+            playing: self.registers.sound > 0,
+        }
+    }
+
+    pub fn run_cycles(&mut self, n: usize) -> Result<(), Chip8Error>
+    {
+        for _ in 0..n
+        {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    ///
+    /// On a fault the program counter is left pointing at the offending
+    /// instruction so the state can be inspected. Nothing happens unless the
+    /// machine is [`MachineState::Running`].
+    pub fn step(&mut self) -> Result<(), Chip8Error>
+    {
+        self.accesses.clear();
+        self.register_accesses.clear();
+        if self.waiting_for_vblank || self.state != MachineState::Running
+        {
+            return Ok(());
+        }
+        let pc = self.registers.pc;
+        let result = self.execute();
+        if result.is_err()
+        {
+            self.registers.pc = pc;
+        }
+        result
+    }
+
+    /// Whether a draw with [`Quirks::display_wait`] on has stalled execution
+    /// until the timers next tick.
+    pub fn is_waiting_for_vblank(&self) -> bool
+    {
+        self.waiting_for_vblank
+    }
+
+    /// The memory the last [`Chip8::step`] read or wrote as data, not
+    /// counting the instruction fetch. A faulting access is not included.
+    pub fn last_accesses(&self) -> &[MemoryAccess]
+    {
+        &self.accesses
+    }
+
+    /// The registers the last [`Chip8::step`] read or wrote, in order, not
+    /// counting the program counter. A faulting instruction keeps what it did
+    /// before the fault, and `FX0A` records nothing because VX is only
+    /// written once a key arrives.
+    pub fn last_register_accesses(&self) -> &[RegisterAccess]
+    {
+        &self.register_accesses
+    }
+
+    fn execute(&mut self) -> Result<(), Chip8Error>
+    {
+        use RegisterName::{Delay, Sound, Sp, I, V};
+        let ram = &mut self.ram;
+        let pc = self.registers.pc;
+        let fetch_fault = Chip8Error::MemoryOutOfBounds {
+            pc,
+            address: pc as usize + 1,
+        };
+        let next_instruction =
+            Instruction::get_next_instruction(ram, &mut self.registers).ok_or(fetch_fault)?;
+        let decoded = if next_instruction.is_long_load()
+        {
+            let operand = Instruction::get_next_instruction(ram, &mut self.registers).ok_or(
+                Chip8Error::MemoryOutOfBounds {
+                    pc,
+                    address: pc as usize + 3,
+                },
+            )?;
+            Some(OpCode::LdILong {
+                nnnn: u16::from_be_bytes(operand.data),
+            })
+        }
+        else
+        {
+            next_instruction.decode()
+        };
+        let Some(op) = decoded
+        else
+        {
+            return Err(Chip8Error::UnknownOpcode {
+                pc,
+                opcode: u16::from_be_bytes(next_instruction.data),
+            });
+        };
+        let mut memory = Memory {
+            ram,
+            pc,
+            accesses: &mut self.accesses,
+        };
+        let mut registers = Registers {
+            file: &mut self.registers,
+            accesses: &mut self.register_accesses,
+        };
+        match op
+        {
+            OpCode::Cls => self.display.clear(),
+            OpCode::Jmp { nnn } => registers.file.pc = nnn,
+            OpCode::LdVxBy { x, kk } => registers.set_v(x, kk),
+            OpCode::Add { x, kk } =>
+            {
+                let vx = registers.v(x);
+                registers.set_v(x, vx.wrapping_add(kk));
+            }
+            OpCode::LdI { nnn } => registers.set(I, nnn),
+            OpCode::Display { x, y, n } =>
+            {
+                let xpos = registers.v(x);
+                let ypos = registers.v(y);
+                let clip = self.quirks.clip_sprites;
+                let sprite_len = if n == 0 { 32 } else { n as usize };
+                let mut address = registers.get(I);
+                let mut xor = false;
+                // With several planes selected the sprite data for each plane
+                // follows the previous one in memory.
+                for plane in [0b01, 0b10]
+                {
+                    if self.display.selected_planes & plane == 0
+                    {
+                        continue;
+                    }
+                    let data = memory.read(address, sprite_len)?;
+                    xor |= if n == 0
+                    {
+                        self.display.draw_large_sprite(xpos, ypos, data, plane, clip)
+                    }
+                    else
+                    {
+                        let sprite = Sprite::new_from_bytes(data);
+                        self.display.draw_sprite(xpos, ypos, sprite, plane, clip)
+                    };
+                    address = address.wrapping_add(sprite_len as u16);
+                }
+                registers.set_v(0xF, if xor { 1 } else { 0 });
+                self.waiting_for_vblank = self.quirks.display_wait;
+            }
+            OpCode::Ret =>
+            {
+                let sp = registers.get(Sp);
+                if sp == 0
+                {
+                    return Err(Chip8Error::StackUnderflow { pc });
+                }
+                registers.set(Sp, sp - 1);
+                registers.file.pc = registers.file.stack[sp as usize - 1];
+            }
+            OpCode::Call { nnn } =>
+            {
+                let sp = registers.get(Sp) as usize;
+                if sp >= registers.file.stack.len()
+                {
+                    return Err(Chip8Error::StackOverflow { pc });
+                }
+                registers.file.stack[sp] = registers.file.pc;
+                registers.set(Sp, sp as u16 + 1);
+                registers.file.pc = nnn;
+            }
+            OpCode::SeVxBy { x, kk } =>
+            {
+                if registers.v(x) == kk
+                {
+                    skip_next_instruction(ram, registers.file);
+                }
+            }
+            OpCode::SneVxBy { x, kk } =>
+            {
+                if registers.v(x) != kk
+                {
+                    skip_next_instruction(ram, registers.file);
+                }
+            }
+            OpCode::SeVxVy { x, y } =>
+            {
+                if registers.v(x) == registers.v(y)
+                {
+                    skip_next_instruction(ram, registers.file);
+                }
+            }
+            OpCode::LdVxVy { x, y } =>
+            {
+                let vy = registers.v(y);
+                registers.set_v(x, vy);
+            }
+            OpCode::OrVxVy { x, y } =>
+            {
+                let value = registers.v(x) | registers.v(y);
+                registers.set_v(x, value);
+                reset_vf(&mut registers, &self.quirks);
+            }
+            OpCode::AndVxVy { x, y } =>
+            {
+                let value = registers.v(x).bitand(registers.v(y));
+                registers.set_v(x, value);
+                reset_vf(&mut registers, &self.quirks);
+            }
+            OpCode::XorVxVy { x, y } =>
+            {
+                let value = registers.v(x) ^ registers.v(y);
+                registers.set_v(x, value);
+                reset_vf(&mut registers, &self.quirks);
+            }
+            OpCode::AddVxVy { x, y } =>
+            {
+                let (val, carry) = registers.v(x).overflowing_add(registers.v(y));
+                registers.set_v(x, val);
+                registers.set_v(0xF, if carry { 1 } else { 0 });
+            }
+            OpCode::SubVxVy { x, y } =>
+            {
+                let (val, borrow) = registers.v(x).overflowing_sub(registers.v(y));
+                registers.set_v(x, val);
+                registers.set_v(0xF, if borrow { 0 } else { 1 });
+            }
+            OpCode::ShrVxVy { x, y } =>
+            {
+                let val = registers.v(if self.quirks.shift_uses_vy { y } else { x });
+                registers.set_v(x, val >> 1);
+                registers.set_v(0xF, val & 0b1);
+            }
+            OpCode::SubnVxVy { x, y } =>
+            {
+                let (val, borrow) = registers.v(y).overflowing_sub(registers.v(x));
+                registers.set_v(x, val);
+                registers.set_v(0xF, if borrow { 0 } else { 1 });
+            }
+            OpCode::ShlVxVy { x, y } =>
+            {
+                let val = registers.v(if self.quirks.shift_uses_vy { y } else { x });
+                registers.set_v(x, val << 1);
+                registers.set_v(0xF, val >> 7);
+            }
+            OpCode::SneVxVy { x, y } =>
+            {
+                if registers.v(x) != registers.v(y)
+                {
+                    skip_next_instruction(ram, registers.file);
+                }
+            }
+            OpCode::JpV0Addr { nnn } =>
+            {
+                let offset_register = if self.quirks.jump_uses_vx
+                {
+                    (nnn >> 8) as u8
+                }
+                else
+                {
+                    0
+                };
+                registers.file.pc = nnn + registers.v(offset_register) as u16;
+            }
+            OpCode::RndVxBy { x, kk } => registers.set_v(x, self.rng.next_u8() & kk),
+            OpCode::SkpVx { x } =>
+            {
+                if self.keypad.is_down(registers.v(x) as usize)
+                {
+                    skip_next_instruction(ram, registers.file);
+                }
+            }
+            OpCode::SknpVx { x } =>
+            {
+                if !self.keypad.is_down(registers.v(x) as usize)
+                {
+                    skip_next_instruction(ram, registers.file);
+                }
+            }
+            OpCode::LdVxDt { x } =>
+            {
+                let delay = registers.get(Delay);
+                registers.set(V(x), delay);
+            }
+            OpCode::LdVxK { x } => self.state = MachineState::WaitingForKey { register: x },
+            OpCode::LdDtVx { x } =>
+            {
+                let vx = registers.get(V(x));
+                registers.set(Delay, vx);
+            }
+            OpCode::LdStVx { x } =>
+            {
+                let vx = registers.get(V(x));
+                registers.set(Sound, vx);
+            }
+            OpCode::AddIVx { x } =>
+            {
+                let i = registers.get(I).wrapping_add(registers.get(V(x)));
+                registers.set(I, i);
+            }
+            OpCode::LdFVx { x } =>
+            {
+                let i = registers.get(V(x)) * 5 + FONT_RAM_OFFSET as u16;
+                registers.set(I, i);
+            }
+            OpCode::LdBVx { x } =>
+            {
+                let x = registers.v(x);
+                let hundreds = x / 100;
+                let tens = (x - (hundreds * 100)) / 10;
+                let ones = x - (hundreds * 100) - (tens * 10);
+
+                memory.write(registers.get(I), &[hundreds, tens, ones])?;
+            }
+            OpCode::LdIVx { x } =>
+            {
+                let values: Vec<u8> = (0..=x).map(|register| registers.v(register)).collect();
+                let i = registers.get(I);
+                memory.write(i, &values)?;
+                advance_i(&mut registers, i, x, &self.quirks);
+            }
+            OpCode::LdVxI { x } =>
+            {
+                let i = registers.get(I);
+                let values = memory.read(i, x as usize + 1)?;
+                for (register, value) in (0..=x).zip(values)
+                {
+                    registers.set_v(register, *value);
+                }
+                advance_i(&mut registers, i, x, &self.quirks);
+            }
+            OpCode::ScrollDown { n } => self.display.scroll_down(n as usize),
+            OpCode::ScrollRight => self.display.scroll_right(),
+            OpCode::ScrollLeft => self.display.scroll_left(),
+            OpCode::Exit => self.state = MachineState::Halted,
+            OpCode::Lores => self.display.set_hires(false),
+            OpCode::Hires => self.display.set_hires(true),
+            OpCode::LdHfVx { x } =>
+            {
+                let digit = registers.get(V(x)) & 0xF;
+                registers.set(I, digit * 10 + BIG_FONT_RAM_OFFSET as u16);
+            }
+            OpCode::LdRVx { x } =>
+            {
+                for register in 0..=x
+                {
+                    self.rpl_flags[register as usize] = registers.v(register);
+                }
+            }
+            OpCode::LdVxR { x } =>
+            {
+                for register in 0..=x
+                {
+                    registers.set_v(register, self.rpl_flags[register as usize]);
+                }
+            }
+            OpCode::ScrollUp { n } => self.display.scroll_up(n as usize),
+            OpCode::SaveVxVy { x, y } =>
+            {
+                let values: Vec<u8> =
+                    register_range(x, y).map(|register| registers.v(register as u8)).collect();
+                memory.write(registers.get(I), &values)?;
+            }
+            OpCode::LoadVxVy { x, y } =>
+            {
+                let registers_range = register_range(x, y);
+                let values = memory.read(registers.get(I), registers_range.len())?;
+                for (value, register) in values.iter().zip(registers_range)
+                {
+                    registers.set_v(register as u8, *value);
+                }
+            }
+            OpCode::Plane { n } => self.display.selected_planes = n & ALL_PLANES,
+            OpCode::LdILong { nnnn } => registers.set(I, nnnn),
+            OpCode::Audio =>
+            {
+                self.audio_pattern.copy_from_slice(memory.read(registers.get(I), PATTERN_SIZE)?);
+            }
+            OpCode::LdPitchVx { x } => registers.file.pitch = registers.v(x),
+        }
+        Ok(())
+    }
+}
+
+/// A snapshot of a [`Chip8`] taken by [`Chip8::save_state`].
+#[derive(Clone)]
+pub struct SaveState
+{
+    machine: Chip8,
+}
+
+impl SaveState
+{
+    /// The seed of the random number generator at the time of the snapshot.
+    pub fn seed(&self) -> u64
+    {
+        self.machine.rng.seed()
+    }
+}
+
+impl Default for Chip8
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+fn reset_vf(registers: &mut Registers, quirks: &Quirks)
+{
+    if quirks.vf_reset
+    {
+        registers.set_v(0xF, 0);
+    }
+}
+
+/// Moves I past the registers stored or loaded by `FX55`/`FX65`, unless the
+/// quirks leave it alone.
+fn advance_i(registers: &mut Registers, i: u16, x: u8, quirks: &Quirks)
+{
+    let increment = match quirks.memory_increment
+    {
+        MemoryIncrement::XPlusOne => x as u16 + 1,
+        MemoryIncrement::X => x as u16,
+        MemoryIncrement::Unchanged => return,
+    };
+    registers.set(RegisterName::I, i.wrapping_add(increment));
+}
+
+/// Advances PC past the next instruction, which is four bytes long if it is
+/// the XO-CHIP `F000 NNNN` long load.
+fn skip_next_instruction(ram: &ChipRam, registers: &mut ChipRegisters)
+{
+    let pc = registers.pc as usize;
+    let long = ram.get(pc..pc + 2) == Some(&[0xF0, 0x00]);
+    registers.pc = registers.pc.wrapping_add(if long { 4 } else { 2 });
+}
+
+/// The registers touched by `5XY2`/`5XY3`, in the order they are stored;
+/// the range runs backwards when x is greater than y.
+fn register_range(x: u8, y: u8) -> impl ExactSizeIterator<Item = usize>
+{
+    let (x, y) = (x as usize, y as usize);
+    (0..x.abs_diff(y) + 1).map(move |k| {
+        if x <= y
+        {
+            x + k
+        }
+        else
+        {
+            x - k
+        }
+    })
+}
+
+/// RAM as seen by instructions that use it for data. Every access goes
+/// through here so that it can be recorded for watchpoints.
+struct Memory<'a>
+{
+    ram: &'a mut ChipRam,
+    /// The instruction doing the accessing, for faults.
+    pc: u16,
+    accesses: &'a mut Vec<MemoryAccess>,
+}
+
+impl Memory<'_>
+{
+    fn read(&mut self, start: u16, len: usize) -> Result<&[u8], Chip8Error>
+    {
+        let range = ram_range(self.ram, self.pc, start, len)?;
+        self.accesses.push(MemoryAccess {
+            kind: AccessKind::Read,
+            range: range.clone(),
+        });
+        Ok(&self.ram[range])
+    }
+
+    fn write(&mut self, start: u16, bytes: &[u8]) -> Result<(), Chip8Error>
+    {
+        let range = ram_range(self.ram, self.pc, start, bytes.len())?;
+        self.accesses.push(MemoryAccess {
+            kind: AccessKind::Write,
+            range: range.clone(),
+        });
+        self.ram[range].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// The registers as seen by instructions. Reads and writes of the data
+/// registers go through here so that they can be recorded for watchpoints;
+/// the program counter, stack and pitch are used through `file` directly.
+struct Registers<'a>
+{
+    file: &'a mut ChipRegisters,
+    accesses: &'a mut Vec<RegisterAccess>,
+}
+
+impl Registers<'_>
+{
+    fn get(&mut self, register: RegisterName) -> u16
+    {
+        self.accesses.push(RegisterAccess {
+            kind: AccessKind::Read,
+            register,
+        });
+        match register
+        {
+            RegisterName::V(x) => self.file.v[x as usize] as u16,
+            RegisterName::I => self.file.i,
+            RegisterName::Sp => self.file.sp as u16,
+            RegisterName::Delay => self.file.delay as u16,
+            RegisterName::Sound => self.file.sound as u16,
+        }
+    }
+
+    /// Stores `value`, truncated to the width of the register.
+    fn set(&mut self, register: RegisterName, value: u16)
+    {
+        self.accesses.push(RegisterAccess {
+            kind: AccessKind::Write,
+            register,
+        });
+        match register
+        {
+            RegisterName::V(x) => self.file.v[x as usize] = value as u8,
+            RegisterName::I => self.file.i = value,
+            RegisterName::Sp => self.file.sp = value as u8,
+            RegisterName::Delay => self.file.delay = value as u8,
+            RegisterName::Sound => self.file.sound = value as u8,
+        }
+    }
+
+    fn v(&mut self, x: u8) -> u8
+    {
+        self.get(RegisterName::V(x)) as u8
+    }
+
+    fn set_v(&mut self, x: u8, value: u8)
+    {
+        self.set(RegisterName::V(x), value as u16);
+    }
+}
+
+/// Returns the RAM range `start..start + len`, or a fault if any of it lies
+/// outside of memory.
+fn ram_range(ram: &ChipRam, pc: u16, start: u16, len: usize) -> Result<Range<usize>, Chip8Error>
+{
+    let start = start as usize;
+    let end = start + len;
+    if end > ram.len()
+    {
+        return Err(Chip8Error::MemoryOutOfBounds {
+            pc,
+            address: start.max(ram.len()),
+        });
+    }
+    Ok(start..end)
+}
+
+fn load_into_ram(buf: &[u8], ram: &mut ChipRam, base_ram_position: usize)
+{
+    for (i, val) in buf.iter().enumerate()
+    {
+        ram[i + base_ram_position] = *val;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChipRegisters
+{
+    pub v: [u8; 16],
+    pub i: u16,
+    pub delay: u8,
+    pub sound: u8,
+    /// The XO-CHIP audio pitch register set by `FX3A`.
+    pub pitch: u8,
+    pub pc: u16,
+    pub sp: u8,
+    pub stack: [u16; 16],
+}
+impl ChipRegisters
+{
+    pub fn new() -> ChipRegisters
+    {
+        Self {
+            v: [0u8; 16],
+            i: 0u16,
+            delay: 0u8,
+            sound: 0u8,
+            pitch: DEFAULT_PITCH,
+            pc: 0u16,
+            sp: 0u8,
+            stack: [0u16; 16],
+        }
+    }
+}
+
+impl Default for ChipRegisters
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl fmt::Display for ChipRegisters
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        for (i, val) in self.v.iter().enumerate()
+        {
+            write!(f, "V{:X}={:02X} ", i, val)?;
+        }
+        write!(
+            f,
+            "\nI={:03X} PC={:03X} SP={} DT={:02X} ST={:02X}",
+            self.i, self.pc, self.sp, self.delay, self.sound
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::quirks::QuirkProfile;
+    use crate::scheduler::Scheduler;
+
+    /// A machine with the default quirks and `rom` loaded.
+    fn machine_with(rom: &[u8]) -> Chip8
+    {
+        let mut machine = Chip8::new();
+        machine.load_rom(rom).unwrap();
+        machine
+    }
+
+    fn register_accesses(machine: &Chip8) -> Vec<(AccessKind, RegisterName)>
+    {
+        machine
+            .last_register_accesses()
+            .iter()
+            .map(|access| (access.kind, access.register))
+            .collect()
+    }
+
+    #[test]
+    fn machine_step_test()
+    {
+        // LD V3, 0x1F; ADD V3, 0x01; LD I, 0x300
+        let mut machine = machine_with(&[0x63, 0x1F, 0x73, 0x01, 0xA3, 0x00]);
+        machine.run_cycles(3).unwrap();
+        assert_eq!(machine.registers.v[3], 0x20);
+        assert_eq!(machine.registers.i, 0x300);
+        assert_eq!(machine.registers.pc, 0x206);
+    }
+
+    #[test]
+    fn subtraction_flag_test()
+    {
+        // VF is 1 when there is no borrow and 0 when there is, for both 8XY5
+        // (VX = VX - VY) and 8XY7 (VX = VY - VX).
+        let run = |x: u8, y: u8, opcode: u8| {
+            let mut machine = machine_with(&[0x60, x, 0x61, y, 0x80, 0x10 | opcode]);
+            machine.run_cycles(3).unwrap();
+            (machine.registers.v[0], machine.registers.v[0xF])
+        };
+        assert_eq!(run(5, 3, 0x5), (2, 1));
+        assert_eq!(run(3, 3, 0x5), (0, 1));
+        assert_eq!(run(3, 5, 0x5), (0xFE, 0));
+        assert_eq!(run(3, 5, 0x7), (2, 1));
+        assert_eq!(run(5, 5, 0x7), (0, 1));
+        assert_eq!(run(5, 3, 0x7), (0xFE, 0));
+    }
+
+    #[test]
+    fn stack_underflow_fault_test()
+    {
+        // LD V0, 0 then RET with an empty stack.
+        let mut machine = machine_with(&[0x60, 0x00, 0x00, 0xEE]);
+        machine.step().unwrap();
+        let fault = machine.step().unwrap_err();
+        assert!(matches!(fault, Chip8Error::StackUnderflow { pc: 0x202 }));
+        assert_eq!(machine.registers.pc, 0x202);
+    }
+
+    #[test]
+    fn unknown_opcode_fault_test()
+    {
+        let fault = machine_with(&[0xFF, 0xFF]).step().unwrap_err();
+        assert!(matches!(
+            fault,
+            Chip8Error::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0xFFFF
+            }
+        ));
+    }
+
+    #[test]
+    fn memory_out_of_bounds_fault_test()
+    {
+        // LD I, 0xFFF; LD V5, [I] reads past the end of RAM.
+        let mut machine = machine_with(&[0xAF, 0xFF, 0xF5, 0x65]);
+        machine.step().unwrap();
+        let fault = machine.step().unwrap_err();
+        assert!(matches!(
+            fault,
+            Chip8Error::MemoryOutOfBounds { pc: 0x202, .. }
+        ));
+    }
+
+    #[test]
+    fn rom_too_large_test()
+    {
+        let rom = vec![0u8; 4096];
+        let fault = Chip8::new().load_rom(&rom).unwrap_err();
+        assert!(matches!(
+            fault,
+            Chip8Error::RomTooLarge {
+                size: 4096,
+                max: 3584
+            }
+        ));
+    }
+
+    /// LD V1, 0x81; LD V0, 0x00; SHR V0, V1; LD I, 0x300; LD [I], V1
+    const QUIRKS_ROM: [u8; 10] = [0x61, 0x81, 0x60, 0x00, 0x80, 0x16, 0xA3, 0x00, 0xF1, 0x55];
+
+    #[test]
+    fn vip_quirks_test()
+    {
+        let mut vip = Chip8::with_quirks(QuirkProfile::CosmacVip.quirks());
+        vip.load_rom(&QUIRKS_ROM).unwrap();
+        vip.run_cycles(5).unwrap();
+        assert_eq!(vip.registers.v[0], 0x40);
+        assert_eq!(vip.registers.v[0xF], 1);
+        assert_eq!(vip.registers.i, 0x302);
+    }
+
+    #[test]
+    fn superchip_quirks_test()
+    {
+        let mut schip = Chip8::with_quirks(QuirkProfile::SuperChip.quirks());
+        schip.load_rom(&QUIRKS_ROM).unwrap();
+        schip.run_cycles(5).unwrap();
+        assert_eq!(schip.registers.v[0], 0x00);
+        assert_eq!(schip.registers.v[0xF], 0);
+        assert_eq!(schip.registers.i, 0x300);
+    }
+
+    #[test]
+    fn superchip_test()
+    {
+        let rom = [
+            0x00, 0xFF, // HIGH
+            0x60, 0x78, // LD V0, 120
+            0x61, 0x00, // LD V1, 0
+            0xA3, 0x00, // LD I, 0x300
+            0xD0, 0x10, // DRW V0, V1, 0 (16x16, clipped at the right edge)
+            0x00, 0xC2, // SCD 2
+            0x00, 0xFC, // SCL
+            0xF1, 0x75, // LD R, V1
+            0x60, 0x09, // LD V0, 9
+            0xF0, 0x30, // LD HF, V0
+            0xF1, 0x85, // LD V1, R
+            0x00, 0xFD, // EXIT
+        ];
+        let mut machine = Chip8::with_quirks(QuirkProfile::SuperChip.quirks());
+        machine.load_rom(&rom).unwrap();
+        machine.ram[0x300..0x320].fill(0xFF);
+        machine.run_cycles(20).unwrap();
+        machine.display.debuff();
+
+        assert_eq!(machine.state, MachineState::Halted);
+        assert_eq!(machine.registers.pc, 0x218);
+        assert_eq!(machine.display.width(), 128);
+        assert_eq!(machine.registers.i, 0x50 + 9 * 10);
+        assert_eq!(machine.registers.v[0], 120);
+        assert_eq!(machine.registers.v[1], 0);
+        assert_eq!(machine.rpl_flags[0], 120);
+        // The sprite covered columns 120..128 and rows 0..16 before scrolling.
+        assert_eq!(machine.display.get_pixel(115, 1), Some(0));
+        assert_eq!(machine.display.get_pixel(116, 2), Some(1));
+        assert_eq!(machine.display.get_pixel(123, 17), Some(1));
+        assert_eq!(machine.display.get_pixel(124, 2), Some(0));
+        assert_eq!(machine.display.get_pixel(116, 18), Some(0));
+    }
+
+    fn xochip_with(rom: &[u8]) -> Chip8
+    {
+        let mut machine = Chip8::with_quirks(QuirkProfile::XoChip.quirks());
+        machine.load_rom(rom).unwrap();
+        machine
+    }
+
+    #[test]
+    fn xochip_memory_size_test()
+    {
+        assert_eq!(xochip_with(&[]).ram.len(), 0x10000);
+    }
+
+    #[test]
+    fn skip_over_long_load_test()
+    {
+        let mut machine = xochip_with(&[
+            0x60, 0x05, // LD V0, 5
+            0x30, 0x05, // SE V0, 5 (skips the whole long load below)
+            0xF0, 0x00, 0x12, 0x34, // LD I, long 0x1234
+            0xF0, 0x00, 0xE0, 0x00, // LD I, long 0xE000
+        ]);
+        machine.run_cycles(3).unwrap();
+        assert_eq!(machine.registers.i, 0xE000);
+    }
+
+    #[test]
+    fn save_and_load_register_range_test()
+    {
+        let mut machine = xochip_with(&[
+            0xAE, 0x00, // LD I, 0xE00
+            0x61, 0x07, // LD V1, 7
+            0x52, 0x12, // SAVE V2 - V1 (stored backwards: V2, V1)
+            0x53, 0x13, // LOAD V3 - V1
+        ]);
+        machine.run_cycles(3).unwrap();
+        assert_eq!(machine.ram[0xE00..0xE02], [0x00, 0x07]);
+        machine.ram[0xE02] = 0x42;
+        machine.step().unwrap();
+        assert_eq!(machine.registers.v[1..4], [0x42, 0x07, 0x00]);
+    }
+
+    #[test]
+    fn draw_on_both_planes_test()
+    {
+        let mut machine = xochip_with(&[
+            0xA3, 0x00, // LD I, 0x300
+            0xF3, 0x01, // PLANE 3
+            0xD0, 0x01, // DRW V0, V0, 1 with one byte per plane
+        ]);
+        machine.ram[0x300..0x302].copy_from_slice(&[0xC0, 0x80]);
+        machine.run_cycles(3).unwrap();
+        machine.display.debuff();
+        assert_eq!(machine.display.get_pixel(0, 0), Some(0b11));
+        assert_eq!(machine.display.get_pixel(1, 0), Some(0b01));
+        assert_eq!(machine.display.get_pixel(2, 0), Some(0));
+    }
+
+    #[test]
+    fn scroll_up_test()
+    {
+        // Scrolling up moves row 0 off the top of the screen.
+        let mut machine = xochip_with(&[0xD0, 0x01, 0x00, 0xD1]);
+        machine.run_cycles(2).unwrap();
+        machine.display.debuff();
+        assert_eq!(machine.display.get_pixel(0, 0), Some(0));
+        assert_eq!(machine.display.get_pixel(0, 31), Some(0));
+    }
+
+    #[test]
+    fn audio_state_test()
+    {
+        // LD I, 0x300; AUDIO; LD V0, 112; PITCH := V0; LD V1, 2; LD ST, V1
+        let mut machine = xochip_with(&[
+            0xA3, 0x00, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A, 0x61, 0x02, 0xF1, 0x18,
+        ]);
+        machine.ram[0x300] = 0xF0;
+        machine.run_cycles(6).unwrap();
+        let state = machine.audio_state();
+        assert!(state.playing);
+        assert_eq!(state.pitch, 112);
+        assert_eq!(state.pattern[0], 0xF0);
+
+        machine.tick_timers();
+        machine.tick_timers();
+        assert!(!machine.audio_state().playing);
+    }
+
+    #[test]
+    fn draw_clipping_quirk_test()
+    {
+        // LD V0, 62; LD V1, 31; LD I, 0x20A; DRW V0, V1, 1; sprite 0xF0
+        let rom = [
+            0x60, 0x3E, 0x61, 0x1F, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0xF0,
+        ];
+        for clip in [true, false]
+        {
+            let mut machine = machine_with(&rom);
+            machine.quirks.clip_sprites = clip;
+            machine.run_cycles(4).unwrap();
+            machine.display.debuff();
+            let row: Vec<_> =
+                [60, 61, 62, 63, 0, 1].iter().map(|x| machine.display.get_pixel(*x, 31)).collect();
+            let wrapped = if clip { 0 } else { 1 };
+            assert_eq!(
+                row,
+                [0, 0, 1, 1, wrapped, wrapped].map(Some),
+                "clip_sprites = {}",
+                clip
+            );
+            assert_eq!(machine.registers.v[0xF], 0);
+        }
+    }
+
+    /// LD V0, 10; LD DT, V0; LD V3, K; then spin.
+    const KEY_WAIT_ROM: [u8; 8] = [0x60, 0x0A, 0xF0, 0x15, 0xF3, 0x0A, 0x12, 0x06];
+
+    #[test]
+    fn key_wait_ignores_held_key_test()
+    {
+        let mut machine = machine_with(&KEY_WAIT_ROM);
+        machine.set_key(0x5, true);
+        let mut scheduler = Scheduler::new(8);
+        scheduler.run_frame(&mut machine).unwrap();
+        assert_eq!(machine.state, MachineState::WaitingForKey { register: 3 });
+        let pc = machine.registers.pc;
+
+        // A key held since before FX0A does not count, and timers keep running.
+        for _ in 0..3
+        {
+            scheduler.run_frame(&mut machine).unwrap();
+        }
+        assert_eq!(machine.registers.pc, pc);
+        assert_eq!(machine.registers.delay, 10 - 4);
+        assert_eq!(scheduler.cycles, 8 * 4);
+    }
+
+    #[test]
+    fn key_wait_completes_on_release_test()
+    {
+        let mut machine = machine_with(&KEY_WAIT_ROM);
+        machine.run_cycles(3).unwrap();
+        let pc = machine.registers.pc;
+        machine.set_key(0xB, true);
+        machine.run_cycles(4).unwrap();
+        assert_eq!(machine.registers.pc, pc);
+        machine.set_key(0xB, false);
+        assert_eq!(machine.state, MachineState::Running);
+        assert_eq!(machine.registers.v[3], 0xB);
+    }
+
+    #[test]
+    fn key_wait_completes_on_press_test()
+    {
+        let mut quirks = QuirkProfile::CosmacVip.quirks();
+        quirks.key_wait_release = false;
+        let mut machine = Chip8::with_quirks(quirks);
+        machine.load_rom(&KEY_WAIT_ROM).unwrap();
+        machine.run_cycles(3).unwrap();
+        machine.set_key(0x2, true);
+        assert_eq!(machine.state, MachineState::Running);
+        assert_eq!(machine.registers.v[3], 0x2);
+    }
+
+    /// RND V0, 0xFF; RND V1, 0x0F; jump back to the start.
+    const RANDOM_ROM: [u8; 6] = [0xC0, 0xFF, 0xC1, 0x0F, 0x12, 0x00];
+
+    fn random_values(seed: u64) -> Vec<(u8, u8)>
+    {
+        let mut machine = machine_with(&RANDOM_ROM);
+        machine.set_seed(seed);
+        (0..8)
+            .map(|_| {
+                machine.run_cycles(3).unwrap();
+                (machine.registers.v[0], machine.registers.v[1])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_seed_test()
+    {
+        let first = random_values(1234);
+        assert_eq!(first, random_values(1234));
+        assert_ne!(first, random_values(4321));
+        assert!(first.iter().any(|(value, _)| *value != first[0].0));
+    }
+
+    #[test]
+    fn random_mask_test()
+    {
+        assert!(random_values(1234).iter().all(|(_, masked)| *masked <= 0x0F));
+    }
+
+    #[test]
+    fn save_state_restores_random_generator_test()
+    {
+        let mut machine = machine_with(&RANDOM_ROM);
+        machine.set_seed(99);
+        machine.run_cycles(3).unwrap();
+        let snapshot = machine.save_state();
+        assert_eq!(snapshot.seed(), 99);
+        machine.run_cycles(3).unwrap();
+        let after = machine.registers.clone();
+        machine.run_cycles(9).unwrap();
+        machine.load_state(&snapshot);
+        machine.run_cycles(3).unwrap();
+        assert_eq!(machine.registers, after);
+    }
+
+    #[test]
+    fn register_accesses_in_order_test()
+    {
+        use AccessKind::{Read, Write};
+        use RegisterName::V;
+        // XOR V0, V1 reads both, then writes V0 and resets VF.
+        let mut machine = machine_with(&[0x80, 0x13]);
+        machine.step().unwrap();
+        assert_eq!(
+            register_accesses(&machine),
+            [(Read, V(0)), (Read, V(1)), (Write, V(0)), (Write, V(0xF))]
+        );
+    }
+
+    #[test]
+    fn shift_reads_quirk_source_test()
+    {
+        use AccessKind::{Read, Write};
+        use RegisterName::V;
+        // SHR V0, V1 reads whichever register the quirk shifts.
+        for (shift_uses_vy, source) in [(true, 1), (false, 0)]
+        {
+            let mut machine = machine_with(&[0x80, 0x16]);
+            machine.quirks.shift_uses_vy = shift_uses_vy;
+            machine.step().unwrap();
+            assert_eq!(
+                register_accesses(&machine),
+                [(Read, V(source)), (Write, V(0)), (Write, V(0xF))]
+            );
+        }
+    }
+
+    #[test]
+    fn store_writes_i_only_when_it_moves_test()
+    {
+        use AccessKind::{Read, Write};
+        use RegisterName::{I, V};
+        // LD [I], V1 only writes I when the quirk moves it.
+        for (profile, writes_i) in [
+            (QuirkProfile::CosmacVip, true),
+            (QuirkProfile::SuperChip, false),
+        ]
+        {
+            let mut machine = Chip8::with_quirks(profile.quirks());
+            machine.load_rom(&[0xA3, 0x00, 0xF1, 0x55]).unwrap();
+            machine.run_cycles(2).unwrap();
+            let mut expected = vec![(Read, V(0)), (Read, V(1)), (Read, I)];
+            if writes_i
+            {
+                expected.push((Write, I));
+            }
+            assert_eq!(register_accesses(&machine), expected);
+        }
+    }
+}
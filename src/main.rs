@@ -1,852 +1,422 @@
 use std::{
-    fmt,
-    fs::File,
-    io::Read,
-    ops::{BitAnd, Deref},
-    sync::{mpsc, Arc, Mutex, MutexGuard},
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+    process::ExitCode,
+    sync::mpsc,
     thread,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use host_graphics::Input;
-
-use crate::guest_graphics::*;
-use crate::host_graphics::Terminal;
-
-pub mod guest_graphics;
-mod host_graphics;
-pub mod tests;
-
-const OPS_PER_SECOND: u64 = 1000;
-const FONT_RAM_OFFSET: usize = 0x0;
+use chip_eight::{
+    bitmap::DEFAULT_SCALE,
+    cli::{self, Command, Options},
+    config::{Config, ConfigError},
+    debugger::{DebugCommand, DebugError, Debugger},
+    disasm, headless,
+    headless::HeadlessOptions,
+    host_graphics::Terminal,
+    host_graphics::{Input, TerminalSession, ThreadedInput, DEFAULT_HOLD_TIMEOUT},
+    keymap::{self, Action, Keymap},
+    octo,
+    palette::{ColorMode, Palette},
+    presenter::Presenter,
+    scheduler::{Scheduler, FRAME_DURATION, MAX_CYCLES_PER_FRAME},
+    shutdown::QuitSignal,
+    Chip8, Chip8Error, MachineState,
+};
+use signal_hook::consts::SIGINT;
 
-fn main()
+fn main() -> ExitCode
 {
-    let display: ChipDisplay = ChipDisplay::new();
-    let display_threaded: ThreadedDisplay = Arc::new(Mutex::new(display));
-    let display_threaded_loop_clone: ThreadedDisplay = display_threaded.clone();
-    let _font = guest_graphics::get_fonts();
-    let mut terminal = Terminal::new();
-    let input_threaded = Input::get_threaded_input();
-    let mut input_threaded_clone = input_threaded.clone();
-
-    let (tx, rx) = mpsc::channel();
-
-    let _key_read_handle = thread::spawn(move || {
-        terminal.key_update_loop(tx, &mut input_threaded_clone);
-    });
-    let _rendering_handle = thread::spawn(move || {
-        guest_graphics::display_loop(display_threaded_loop_clone);
-    });
-
-    let mut ram: ChipRam = [0; 4096];
-
-    let registers = ChipRegisters::get_new_threaded();
-    let registers_threaded_clone = registers.clone();
+    let options = match cli::parse_args(env::args().skip(1))
+    {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) =>
+        {
+            println!("{}", cli::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) =>
+        {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            return ExitCode::from(2);
+        }
+    };
 
-    let _timer_handler = thread::spawn(move || {
-        loop
+    let quirks = options.quirks.unwrap_or_default().quirks();
+    let mut machine = Chip8::with_quirks(quirks);
+    let is_octo = options.rom_path.extension().is_some_and(|ext| ext == "8o");
+    let rom = if is_octo
+    {
+        match octo::compile_file(&options.rom_path)
         {
-            thread::sleep(Duration::from_millis(1000 / 60));
-            let mut regs = registers_threaded_clone.lock().unwrap();
-            if regs.delay > 0
-            {
-                regs.delay -= 1;
-            }
-            if regs.sound > 0
+            Ok(rom) => Ok(rom),
+            Err(e) =>
             {
-                regs.sound -= 1;
+                eprintln!("error: {}", e);
+                return ExitCode::FAILURE;
             }
         }
-    });
+    }
+    else
+    {
+        fs::read(&options.rom_path)
+    };
+    let loaded = rom.map_err(Chip8Error::from).and_then(|rom| machine.load_rom(&rom).map(|()| rom));
+    let rom = match loaded
+    {
+        Ok(rom) => rom,
+        Err(e) =>
+        {
+            eprintln!(
+                "error: could not load ROM '{}': {}",
+                options.rom_path.display(),
+                e
+            );
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let rom_name = "a.rom";
-    load_rom_into_ram(rom_name, &mut ram);
-    for (i, val) in _font.iter().enumerate()
+    if options.rom_hash
     {
-        load_into_ram(&val.sprite_data, &mut ram, FONT_RAM_OFFSET + i as usize * 5)
+        println!("{:016x}", keymap::rom_hash(&rom));
+        return ExitCode::SUCCESS;
     }
-    registers.lock().unwrap().pc = 0x200;
 
-    loop
-    {
-        thread::sleep(Duration::from_millis(1));
-        let mut registers = registers.lock().unwrap();
-        let next_instruction = Instruction::get_next_instruction(ram, &mut registers);
-        let next_op = Operation::get_op_code(&next_instruction);
-        if let Some(x) = next_op
+    // Headless runs are reproducible by default; interactive ones are not.
+    let seed = options.seed.unwrap_or_else(|| {
+        if options.headless
         {
-            match x
-            {
-                OpCode::Cls => display_threaded.lock().unwrap().clear(),
-                OpCode::Jmp => registers.pc = next_instruction.get_nnn(),
-                OpCode::LdVxBy =>
-                {
-                    registers.v[next_instruction.get_x() as usize] = next_instruction.get_kk()
-                }
-                OpCode::Add =>
-                {
-                    registers.v[next_instruction.get_x() as usize] = registers.v
-                        [next_instruction.get_x() as usize]
-                        .wrapping_add(next_instruction.get_kk())
-                }
-                OpCode::LdI => registers.i = next_instruction.get_nnn(),
-                OpCode::Display =>
-                {
-                    let mut dis = display_threaded.lock().unwrap();
-                    let sprite_data = &ram[registers.i as usize
-                        ..next_instruction.get_n() as usize + registers.i as usize];
-                    let xpos = registers.v[next_instruction.get_x() as usize];
-                    let ypos = registers.v[next_instruction.get_y() as usize];
-                    let sprite = Sprite::new_from_bytes(sprite_data);
-                    let xor = dis.draw_sprite(xpos, ypos, sprite);
-                    registers.v[0xFusize] = if xor { 1 } else { 0 };
-                }
-                OpCode::Ret =>
-                {
-                    registers.pc = registers.stack[registers.sp as usize];
-                    registers.sp -= 1;
-                }
-                OpCode::Call =>
-                {
-                    registers.sp += 1;
-                    let sp = registers.sp as usize;
-                    registers.stack[sp] = registers.pc;
-                    registers.pc = next_instruction.get_nnn();
-                }
-                OpCode::SeVxBy =>
-                {
-                    if registers.v[next_instruction.get_x() as usize] == next_instruction.get_kk()
-                    {
-                        registers.pc += 2;
-                    }
-                }
-                OpCode::SneVxBy =>
-                {
-                    if registers.v[next_instruction.get_x() as usize] != next_instruction.get_kk()
-                    {
-                        registers.pc += 2;
-                    }
-                }
-                OpCode::SeVxVy =>
-                {
-                    if registers.v[next_instruction.get_x() as usize]
-                        == registers.v[next_instruction.get_y() as usize]
-                    {
-                        registers.pc += 2;
-                    }
-                }
-                OpCode::AddVxBy =>
-                {
-                    let k = registers.v[next_instruction.get_x() as usize];
-                    registers.v[next_instruction.get_x() as usize] = next_instruction.get_kk() + k;
-                }
-                OpCode::LdVxVy =>
-                {
-                    registers.v[next_instruction.get_x() as usize] =
-                        registers.v[next_instruction.get_y() as usize];
-                }
-                OpCode::OrVxVy =>
-                {
-                    registers.v[next_instruction.get_x() as usize] |=
-                        registers.v[next_instruction.get_y() as usize];
-                }
-                OpCode::AndVxVy =>
-                {
-                    registers.v[next_instruction.get_x() as usize] = registers.v
-                        [next_instruction.get_x() as usize]
-                        .bitand(registers.v[next_instruction.get_y() as usize]);
-                }
-                OpCode::XorVxVy =>
-                {
-                    registers.v[next_instruction.get_x() as usize] ^=
-                        registers.v[next_instruction.get_y() as usize];
-                }
-                OpCode::AddVxVy =>
-                {
-                    let x = registers.v[next_instruction.get_x() as usize] as u16;
-                    let y = registers.v[next_instruction.get_y() as usize] as u16;
-                    let mut val = x + y;
-                    let mut flag = false;
-                    if val > 255
-                    {
-                        flag = true;
-                        val &= 0b1111_1111;
-                    }
-                    registers.v[next_instruction.get_x() as usize] = val as u8;
-                    registers.v[0xF] = if flag { 1 } else { 0 };
-                }
-                OpCode::SubVxVy =>
-                {
-                    let x = registers.v[next_instruction.get_x() as usize];
-                    let y = registers.v[next_instruction.get_y() as usize];
-                    let (val, flag) = x.overflowing_sub(y);
-                    registers.v[next_instruction.get_x() as usize] = val;
-                    registers.v[0xF] = if flag { 1 } else { 0 };
-                }
-                OpCode::ShrVxVy =>
-                {
-                    let x = registers.v[next_instruction.get_x() as usize];
-                    let mut flag = false;
-                    let mut val = x;
-                    if x & 0b1 == 1
-                    {
-                        flag = true;
-                    }
-                    val /= 2;
-                    registers.v[next_instruction.get_x() as usize] = val;
-                    registers.v[0xF] = if flag { 1 } else { 0 };
-                }
-                OpCode::SubnVxVy =>
-                {
-                    let x = registers.v[next_instruction.get_x() as usize];
-                    let y = registers.v[next_instruction.get_y() as usize];
-                    let mut flag = false;
-                    let mut val = y;
-                    if y > x
-                    {
-                        flag = true;
-                    }
-                    val -= x;
-                    registers.v[next_instruction.get_x() as usize] = val;
-                    registers.v[0xF] = if flag { 1 } else { 0 };
-                }
-                OpCode::ShlVxVy =>
-                {
-                    let x = registers.v[next_instruction.get_x() as usize];
-                    let mut flag = false;
-                    let mut val = x;
-                    if x & 0b1000_0000 == 0b1000_0000
-                    {
-                        flag = true;
-                    }
-                    val = val.wrapping_mul(2);
-                    registers.v[next_instruction.get_x() as usize] = val;
-                    registers.v[0xF] = if flag { 1 } else { 0 };
-                }
-                OpCode::SneVxVy =>
-                {
-                    if registers.v[next_instruction.get_x() as usize]
-                        != registers.v[next_instruction.get_y() as usize]
-                    {
-                        registers.pc += 2;
-                    }
-                }
-                OpCode::JpV0Addr =>
-                {
-                    let addt = registers.v[0x0];
-                    registers.pc = next_instruction.get_nnn() + addt as u16;
-                }
-                OpCode::RndVxBy =>
-                {
-                    //TODO: Implement real random number generator!
-                    let random_number = 4;
-                    registers.v[next_instruction.get_x() as usize] =
-                        random_number & next_instruction.get_kk();
-                }
-                OpCode::SkpVx =>
-                {
-                    let inp = input_threaded.lock().unwrap();
-                    let key_index = registers.v[next_instruction.get_x() as usize] as usize;
-                    if inp.key_is_down(key_index)
-                    {
-                        registers.pc += 2;
-                    }
-                }
-                OpCode::SknpVx =>
-                {
-                    let inp = input_threaded.lock().unwrap();
-                    let key_index = registers.v[next_instruction.get_x() as usize] as usize;
-                    if !inp.key_is_down(key_index)
-                    {
-                        registers.pc += 2;
-                    }
-                }
-                OpCode::LdVxDt =>
-                {
-                    registers.v[next_instruction.get_x() as usize] = registers.delay;
-                }
-                OpCode::LdVxK =>
-                {
-                    let inp = input_threaded.lock().unwrap();
-                    let key_index = registers.v[next_instruction.get_x() as usize] as usize;
-                    if !inp.key_is_down(key_index)
-                    {
-                        registers.pc -= 2;
-                    }
-                }
-                OpCode::LdDtVx => registers.delay = registers.v[next_instruction.get_x() as usize],
-                OpCode::LdStVx =>
-                {
-                    registers.sound = registers.v[next_instruction.get_x() as usize];
-                }
-                OpCode::AddIVx =>
-                {
-                    registers.i += registers.v[next_instruction.get_x() as usize] as u16;
-                }
-                OpCode::LdFVx =>
-                {
-                    registers.i = registers.v[next_instruction.get_x() as usize] as u16 * 5
-                        + FONT_RAM_OFFSET as u16
-                }
-                OpCode::LdBVx =>
-                {
-                    let x = registers.v[next_instruction.get_x() as usize];
-                    let location = registers.i;
-                    let hundreds = x / 100;
-                    let tens = (x - (hundreds * 100)) / 10;
-                    let ones = x - (hundreds * 100) - (tens * 10);
-
-                    ram[location as usize] = hundreds;
-                    ram[location as usize + 1] = tens;
-                    ram[location as usize + 2] = ones;
-                }
-                OpCode::LdIVx =>
-                {
-                    let i = registers.i as usize;
-                    let maxx = next_instruction.get_x() as usize;
-                    ram[i..=i + maxx].copy_from_slice(&registers.v[..=maxx]);
-                    registers.i += 2;
-                }
-                OpCode::LdVxI =>
-                {
-                    let i = registers.i as usize;
-                    let maxx = next_instruction.get_x() as usize;
-                    registers.v[..=maxx].copy_from_slice(&ram[i..=i + maxx]);
-                    registers.i += 2;
-                }
-            }
+            0
         }
         else
         {
-            panic!("Unknown op! {}", next_instruction);
+            time_seed()
         }
-    }
-}
-
-struct Operation
-{
-    definition: Vec<OperationComponent>,
-    op_code: OpCode,
-}
+    });
+    machine.set_seed(seed);
 
-impl Operation
-{
-    fn new(op_code: OpCode, definition: Vec<OperationComponent>) -> Self
+    if options.debug
     {
-        Self {
-            definition,
-            op_code,
-        }
+        return run_debugger(machine, &options);
     }
 
-    pub fn get_op_code(instruction: &Instruction) -> Option<OpCode>
+    if options.headless
     {
-        let operations = Operation::get_operations();
-        let instruction_bits = instruction.get_bits();
-        let matches = operations
-            .iter()
-            .filter(|cur_op| {
-                let mut i = 0usize;
-                for comp in &cur_op.definition
-                {
-                    match comp
-                    {
-                        OperationComponent::Literal(c) =>
-                        {
-                            if instruction_bits[i] != *c
-                            {
-                                return false;
-                            }
-                            i += 1;
-                        }
-                        OperationComponent::Nnn => i += 3,
-                        OperationComponent::N => i += 1,
-                        OperationComponent::X => i += 1,
-                        OperationComponent::Y => i += 1,
-                        OperationComponent::Kk => i += 2,
-                    }
-                }
-                true
-            })
-            .map(|f| f.op_code)
-            .collect::<Vec<OpCode>>();
-        matches.first().copied()
+        let headless_options = HeadlessOptions {
+            cycles_per_frame: options.cycles_per_frame(),
+            limit: options.run_limit(),
+        };
+        let report = headless::run(machine, &headless_options);
+        print!("{}", report);
+        return if report.fault.is_some()
+        {
+            ExitCode::FAILURE
+        }
+        else
+        {
+            ExitCode::SUCCESS
+        };
     }
 
-    pub fn get_operations() -> Vec<Operation>
+    let config = match load_config(&options)
     {
-        vec![
-            Self {
-                definition: vec![
-                    OperationComponent::Literal(0x0),
-                    OperationComponent::Literal(0x0),
-                    OperationComponent::Literal(0xE),
-                    OperationComponent::Literal(0x0),
-                ],
-                op_code: OpCode::Cls,
-            },
-            Self {
-                definition: vec![OperationComponent::Literal(0x1), OperationComponent::Nnn],
-                op_code: OpCode::Jmp,
-            },
-            Self {
-                definition: vec![
-                    OperationComponent::Literal(0x6),
-                    OperationComponent::X,
-                    OperationComponent::Kk,
-                ],
-                op_code: OpCode::LdVxBy,
-            },
-            Self {
-                definition: vec![
-                    OperationComponent::Literal(0x7),
-                    OperationComponent::X,
-                    OperationComponent::Kk,
-                ],
-                op_code: OpCode::Add,
-            },
-            Self {
-                definition: vec![OperationComponent::Literal(0xA), OperationComponent::Nnn],
-                op_code: OpCode::LdI,
-            },
-            Self {
-                definition: vec![
-                    OperationComponent::Literal(0xD),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::N,
-                ],
-                op_code: OpCode::Display,
-            },
-            Self::new(
-                OpCode::Ret,
-                vec![
-                    OperationComponent::Literal(0x0),
-                    OperationComponent::Literal(0x0),
-                    OperationComponent::Literal(0xE),
-                    OperationComponent::Literal(0xE),
-                ],
-            ),
-            Self::new(
-                OpCode::Call,
-                vec![OperationComponent::Literal(0x2), OperationComponent::Nnn],
-            ),
-            Self::new(
-                OpCode::SeVxBy,
-                vec![
-                    OperationComponent::Literal(0x3),
-                    OperationComponent::X,
-                    OperationComponent::Kk,
-                ],
-            ),
-            Self::new(
-                OpCode::SneVxBy,
-                vec![
-                    OperationComponent::Literal(0x4),
-                    OperationComponent::X,
-                    OperationComponent::Kk,
-                ],
-            ),
-            Self::new(
-                OpCode::SeVxVy,
-                vec![
-                    OperationComponent::Literal(0x5),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x0),
-                ],
-            ),
-            Self::new(
-                OpCode::AddVxBy,
-                vec![
-                    OperationComponent::Literal(0x7),
-                    OperationComponent::X,
-                    OperationComponent::Kk,
-                ],
-            ),
-            Self::new(
-                OpCode::LdVxVy,
-                vec![
-                    OperationComponent::Literal(0x8),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x0),
-                ],
-            ),
-            Self::new(
-                OpCode::OrVxVy,
-                vec![
-                    OperationComponent::Literal(0x8),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x1),
-                ],
-            ),
-            Self::new(
-                OpCode::AddVxVy,
-                vec![
-                    OperationComponent::Literal(0x8),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x4),
-                ],
-            ),
-            Self::new(
-                OpCode::XorVxVy,
-                vec![
-                    OperationComponent::Literal(0x8),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x3),
-                ],
-            ),
-            Self::new(
-                OpCode::AndVxVy,
-                vec![
-                    OperationComponent::Literal(0x8),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x2),
-                ],
-            ),
-            Self::new(
-                OpCode::SubVxVy,
-                vec![
-                    OperationComponent::Literal(0x8),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x5),
-                ],
-            ),
-            Self::new(
-                OpCode::ShrVxVy,
-                vec![
-                    OperationComponent::Literal(0x8),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x6),
-                ],
-            ),
-            Self::new(
-                OpCode::SubnVxVy,
-                vec![
-                    OperationComponent::Literal(0x8),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x7),
-                ],
-            ),
-            Self::new(
-                OpCode::ShlVxVy,
-                vec![
-                    OperationComponent::Literal(0x8),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0xE),
-                ],
-            ),
-            Self::new(
-                OpCode::SneVxVy,
-                vec![
-                    OperationComponent::Literal(0x9),
-                    OperationComponent::X,
-                    OperationComponent::Y,
-                    OperationComponent::Literal(0x0),
-                ],
-            ),
-            Self::new(
-                OpCode::JpV0Addr,
-                vec![OperationComponent::Literal(0xB), OperationComponent::Nnn],
-            ),
-            Self::new(
-                OpCode::RndVxBy,
-                vec![
-                    OperationComponent::Literal(0xC),
-                    OperationComponent::X,
-                    OperationComponent::Kk,
-                ],
-            ),
-            Self::new(
-                OpCode::SkpVx,
-                vec![
-                    OperationComponent::Literal(0xE),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x9),
-                    OperationComponent::Literal(0xE),
-                ],
-            ),
-            Self::new(
-                OpCode::SknpVx,
-                vec![
-                    OperationComponent::Literal(0xE),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0xA),
-                    OperationComponent::Literal(0x1),
-                ],
-            ),
-            Self::new(
-                OpCode::LdVxDt,
-                vec![
-                    OperationComponent::Literal(0xF),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x0),
-                    OperationComponent::Literal(0x7),
-                ],
-            ),
-            Self::new(
-                OpCode::LdVxK,
-                vec![
-                    OperationComponent::Literal(0xF),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x0),
-                    OperationComponent::Literal(0xA),
-                ],
-            ),
-            Self::new(
-                OpCode::LdDtVx,
-                vec![
-                    OperationComponent::Literal(0xF),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x1),
-                    OperationComponent::Literal(0x5),
-                ],
-            ),
-            Self::new(
-                OpCode::LdStVx,
-                vec![
-                    OperationComponent::Literal(0xF),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x1),
-                    OperationComponent::Literal(0x8),
-                ],
-            ),
-            Self::new(
-                OpCode::AddIVx,
-                vec![
-                    OperationComponent::Literal(0xF),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x1),
-                    OperationComponent::Literal(0xE),
-                ],
-            ),
-            Self::new(
-                OpCode::LdFVx,
-                vec![
-                    OperationComponent::Literal(0xF),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x2),
-                    OperationComponent::Literal(0x9),
-                ],
-            ),
-            Self::new(
-                OpCode::LdBVx,
-                vec![
-                    OperationComponent::Literal(0xF),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x3),
-                    OperationComponent::Literal(0x3),
-                ],
-            ),
-            Self::new(
-                OpCode::LdIVx,
-                vec![
-                    OperationComponent::Literal(0xF),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x5),
-                    OperationComponent::Literal(0x5),
-                ],
-            ),
-            Self::new(
-                OpCode::LdVxI,
-                vec![
-                    OperationComponent::Literal(0xF),
-                    OperationComponent::X,
-                    OperationComponent::Literal(0x6),
-                    OperationComponent::Literal(0x5),
-                ],
-            ),
-        ]
-    }
-}
-#[derive(Copy, Clone)]
-enum OpCode
-{
-    Cls,
-    Jmp,
-    LdVxBy,
-    Add,
-    LdI,
-    Display,
-    Ret,
-    Call,
-    SeVxBy,
-    SneVxBy,
-    SeVxVy,
-    AddVxBy,
-    LdVxVy,
-    OrVxVy,
-    AndVxVy,
-    XorVxVy,
-    AddVxVy,
-    SubVxVy,
-    ShrVxVy,
-    SubnVxVy,
-    ShlVxVy,
-    SneVxVy,
-    JpV0Addr,
-    RndVxBy,
-    SkpVx,
-    SknpVx,
-    LdVxDt,
-    LdVxK,
-    LdDtVx,
-    LdStVx,
-    AddIVx,
-    LdFVx,
-    LdBVx,
-    LdIVx,
-    LdVxI,
+        Ok(config) => config,
+        Err(e) =>
+        {
+            eprintln!("error: could not load the config file: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let keymap = config.keymap.keymap_for(&rom, options.layout.unwrap_or_default());
+    let presenter = match make_presenter(&options, &config)
+    {
+        Ok(presenter) => presenter,
+        Err(e) =>
+        {
+            eprintln!("error: {}", e);
+            return ExitCode::from(2);
+        }
+    };
+    run_interactive(machine, keymap, presenter, &options)
 }
-#[derive(Copy, Clone)]
-enum OperationComponent
+
+/// Loads `--config`, or the config file in the config directory if there is
+/// one.
+fn load_config(options: &Options) -> Result<Config, ConfigError>
 {
-    Literal(u8),
-    Nnn,
-    N,
-    X,
-    Y,
-    Kk,
+    let path = options.config.clone().or_else(|| {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("chip-eight").join("config.toml")).filter(|path| path.exists())
+    });
+    match path
+    {
+        Some(path) => Config::load(&path),
+        None => Ok(Config::default()),
+    }
 }
 
-#[derive(Debug)]
-struct Instruction
+/// Colours are off unless a palette or colour mode is asked for.
+fn make_presenter(options: &Options, config: &Config) -> Result<Presenter, String>
 {
-    pub data: [u8; 2],
+    let renderer = options.renderer.unwrap_or_default().renderer();
+    let palette_name = options.palette.as_ref().or(config.palette.as_ref());
+    let palette = match palette_name
+    {
+        Some(name) => config.find_palette(name).ok_or_else(|| {
+            format!(
+                "unknown palette '{}', expected one of: {} or a palette from the config file",
+                name,
+                Palette::PRESET_NAMES.join(", ")
+            )
+        })?,
+        None => Palette::default(),
+    };
+    let color_mode = match (options.color, palette_name)
+    {
+        (Some(color_mode), _) => color_mode,
+        (None, Some(_)) => ColorMode::detect(),
+        (None, None) => ColorMode::Monochrome,
+    };
+    Ok(Presenter::new(renderer).with_colors(palette, color_mode))
 }
 
-impl fmt::Display for Instruction
+/// Reads debugger commands from stdin until `quit` or the end of input.
+fn run_debugger(mut machine: Chip8, options: &Options) -> ExitCode
 {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    let mut debugger = Debugger::new(options.cycles_per_frame());
+    // Ctrl-C pauses the program instead of ending the process.
+    if let Err(e) = signal_hook::flag::register(SIGINT, debugger.interrupt_flag())
     {
-        write!(f, "\r\n Instruction display:").unwrap();
-        for i in self.data
+        eprintln!("warning: could not install signal handlers: {}", e);
+    }
+    println!("{}", disasm::line_at(&machine.ram, machine.registers.pc));
+    println!("type 'help' for a list of commands");
+    let mut last_command = None;
+    loop
+    {
+        print!("(chip8) ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line)
+        {
+            Ok(0) => return ExitCode::SUCCESS,
+            Ok(_) => (),
+            Err(e) =>
+            {
+                eprintln!("error: could not read a command: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+        let command = match line.parse::<DebugCommand>()
+        {
+            Ok(command) => command,
+            Err(DebugError::Empty) => match last_command
+            {
+                Some(command) => command,
+                None => continue,
+            },
+            Err(e) =>
+            {
+                eprintln!("error: {}", e);
+                continue;
+            }
+        };
+        if command == DebugCommand::Quit
         {
-            write!(f, "\r\n {:x?}", i).unwrap();
+            return ExitCode::SUCCESS;
         }
-        Ok(())
+        last_command = Some(command);
+        print!("{}", debugger.execute(&mut machine, command));
     }
 }
 
-impl Instruction
+fn time_seed() -> u64
 {
-    pub fn get_next_instruction(
-        ram: [u8; 4096],
-        registers: &mut MutexGuard<ChipRegisters>,
-    ) -> Instruction
-    {
-        let next_instruction = [ram[registers.pc as usize], ram[registers.pc as usize + 1]];
-        registers.pc += 2;
-        Instruction::new(next_instruction)
-    }
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
 
-    pub fn get_nnn(&self) -> u16
-    {
-        let mut k = self.data[1] as u16;
-        let mut j = (self.data[0] & 0b1111) as u16;
-        j <<= 8;
-        k |= j;
-        k
-    }
-    pub fn get_n(&self) -> u8
-    {
-        self.data[1] & 0b1111
-    }
-    pub fn get_x(&self) -> u8
+fn run_interactive(
+    mut machine: Chip8,
+    keymap: Keymap,
+    mut presenter: Presenter,
+    options: &Options,
+) -> ExitCode
+{
+    let session = match TerminalSession::start()
     {
-        self.data[0] & 0b1111
-    }
-    pub fn get_y(&self) -> u8
+        Ok(session) => session,
+        Err(e) =>
+        {
+            eprintln!("error: could not set up the terminal: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Bitmap renderers need the terminal's answer, so they are only switched
+    // on once the session has started.
+    let capabilities = session.capabilities();
+    let requested_protocol = options.renderer.and_then(|kind| kind.bitmap_protocol());
+    let bitmap_protocol =
+        requested_protocol.filter(|protocol| protocol.is_supported(&capabilities));
+    if let Some(protocol) = bitmap_protocol
     {
-        self.data[1] >> 4
+        presenter = presenter.with_bitmap(protocol, options.scale.unwrap_or(DEFAULT_SCALE));
     }
-    pub fn get_kk(&self) -> u8
+    let quit = QuitSignal::new();
+    if let Err(e) = quit.register_signals()
     {
-        self.data[1]
+        eprintln!("warning: could not install signal handlers: {}", e);
     }
-    pub fn get_bits(&self) -> [u8; 4]
+    let mut terminal = Terminal::new();
+    let hold_timeout = options.key_hold_ms.map_or(DEFAULT_HOLD_TIMEOUT, Duration::from_millis);
+    let input_threaded = Input::get_threaded_input(hold_timeout);
+    input_threaded.lock().unwrap().set_release_events(capabilities.key_releases);
+    let mut input_threaded_clone = input_threaded.clone();
+
+    let (tx, rx) = mpsc::channel();
+    let input_quit = quit.clone();
+    let key_read_handle = thread::spawn(move || {
+        terminal.key_update_loop(&keymap, tx, &mut input_threaded_clone, &input_quit);
+    });
+
+    let scheduler = Scheduler::new(options.cycles_per_frame()).with_pacing(true);
+    let result = emulate(
+        &mut machine,
+        scheduler,
+        &mut presenter,
+        &mut io::stdout().lock(),
+        &input_threaded,
+        &rx,
+        &quit,
+    );
+
+    quit.request();
+    let _ = key_read_handle.join();
+    drop(session);
+    if requested_protocol.is_some() && bitmap_protocol.is_none()
     {
-        [
-            self.data[0] >> 4,
-            self.data[0] & 0b1111,
-            self.data[1] >> 4,
-            self.data[1] & 0b1111,
-        ]
+        eprintln!("warning: the terminal cannot show images, so half blocks were used instead");
     }
 
-    fn new(next_instruction: [u8; 2]) -> Instruction
+    match (result, quit.signal())
     {
-        Self {
-            data: next_instruction,
+        (Err(RunError::Fault(fault)), _) =>
+        {
+            report_fault(&machine, &fault);
+            ExitCode::FAILURE
+        }
+        (Err(RunError::Io(e)), _) =>
+        {
+            eprintln!("error: could not write to the terminal: {}", e);
+            ExitCode::FAILURE
         }
+        // Report death by signal the way a shell would.
+        (Ok(()), Some(signal)) => ExitCode::from(128 + signal as u8),
+        (Ok(()), None) => ExitCode::SUCCESS,
     }
 }
 
-fn load_rom_into_ram(rom_name: &str, ram: &mut [u8; 4096])
+/// Why an interactive run stopped early.
+enum RunError
 {
-    let mut file_handle = File::open(rom_name).unwrap();
-    let mut buf: Vec<u8> = Vec::new();
-    file_handle.read_to_end(&mut buf).unwrap();
-    let base_ram_position = 0x200usize;
-    load_into_ram(&buf, ram, base_ram_position);
+    /// The guest program did something the machine cannot execute.
+    Fault(Chip8Error),
+    /// Drawing to the host terminal failed; the machine itself is fine.
+    Io(io::Error),
 }
 
-fn load_into_ram(buf: &[u8], ram: &mut [u8; 4096], base_ram_position: usize)
+impl From<io::Error> for RunError
 {
-    for (i, val) in buf.iter().enumerate()
+    fn from(e: io::Error) -> Self
     {
-        ram[i + base_ram_position] = *val;
+        RunError::Io(e)
     }
 }
 
-type ChipRam = [u8; 4096];
-
-struct ChipRegisters
+/// Runs frames until the machine halts or faults, or something asks to quit.
+fn emulate(
+    machine: &mut Chip8,
+    mut scheduler: Scheduler,
+    presenter: &mut Presenter,
+    out: &mut impl Write,
+    input: &ThreadedInput,
+    actions: &mpsc::Receiver<Action>,
+    quit: &QuitSignal,
+) -> Result<(), RunError>
 {
-    v: [u8; 16],
-    i: u16,
-    delay: u8,
-    sound: u8,
-    pc: u16,
-    sp: i8,
-    stack: [u16; 16],
-}
-impl ChipRegisters
-{
-    fn new() -> ChipRegisters
+    let power_on = machine.save_state();
+    let mut saved = None;
+    let mut paused = false;
+    while !quit.is_requested()
     {
-        Self {
-            v: [0u8; 16],
-            i: 0u16,
-            delay: 0u8,
-            sound: 0u8,
-            pc: 0u16,
-            sp: -1i8,
-            stack: [0u16; 16],
+        for action in actions.try_iter()
+        {
+            match action
+            {
+                Action::Quit => return Ok(()),
+                Action::Pause => paused = !paused,
+                Action::Reset => machine.load_state(&power_on),
+                Action::SaveState => saved = Some(machine.save_state()),
+                Action::LoadState =>
+                {
+                    if let Some(saved) = &saved
+                    {
+                        machine.load_state(saved);
+                    }
+                }
+                Action::SpeedUp =>
+                {
+                    // A faster speed given on the command line is kept.
+                    let cap = MAX_CYCLES_PER_FRAME.max(scheduler.cycles_per_frame);
+                    scheduler.cycles_per_frame =
+                        scheduler.cycles_per_frame.saturating_mul(2).min(cap)
+                }
+                Action::SpeedDown =>
+                {
+                    scheduler.cycles_per_frame = (scheduler.cycles_per_frame / 2).max(1)
+                }
+            }
+            if matches!(action, Action::Reset | Action::LoadState)
+            {
+                presenter.present(&machine.display, out)?;
+            }
+        }
+        if paused
+        {
+            thread::sleep(FRAME_DURATION);
+            continue;
+        }
+
+        let keypad = input.lock().unwrap().keypad();
+        machine.set_keypad(keypad);
+        if scheduler.run_frame(machine).map_err(RunError::Fault)?
+        {
+            presenter.present(&machine.display, out)?;
+        }
+        if machine.state == MachineState::Halted
+        {
+            break;
         }
     }
-    pub fn get_new_threaded() -> ThreadedChipRegisters
-    {
-        Arc::new(Mutex::new(ChipRegisters::new()))
-    }
+    Ok(())
 }
 
-type ThreadedChipRegisters = Arc<Mutex<ChipRegisters>>;
-
-impl Default for ChipRegisters
+fn report_fault(machine: &Chip8, fault: &Chip8Error)
 {
-    fn default() -> Self
+    eprintln!("\nfault: {}", fault);
+    if let Some(pc) = fault.pc()
     {
-        Self::new()
+        let pc = pc as usize;
+        if let Some(opcode) = machine.ram.get(pc..pc + 2)
+        {
+            eprintln!("opcode: {:02X}{:02X}", opcode[0], opcode[1]);
+        }
     }
+    eprintln!("{}", machine.registers);
 }
@@ -0,0 +1,1209 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::asm::{AsmError, Location};
+use crate::instruction::OpCode;
+
+/// Address the program is compiled for.
+const START: usize = 0x200;
+
+/// Macro expansions allowed in one program, to stop runaway recursion.
+const MAX_EXPANSIONS: usize = 10_000;
+
+/// Compiles the Octo program in the file at `path`.
+pub fn compile_file(path: &Path) -> Result<Vec<u8>, AsmError>
+{
+    let source = fs::read_to_string(path)?;
+    compile_named(&source, &path.display().to_string())
+}
+
+/// Compiles an Octo program into a ROM to be loaded at 0x200.
+///
+/// This covers the core language: labels, `:const`, `:alias`, `:macro`,
+/// `:calc`, `:byte`, `:org`, `:call`, `:next`, `:unpack`, every
+/// instruction statement, `if ... then`, `if ... begin ... else ... end`,
+/// `loop ... while ... again`, and bare numbers as data for sprites. As in
+/// Octo, the program starts with a jump to the `main` label.
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError>
+{
+    compile_named(source, "<source>")
+}
+
+fn compile_named(source: &str, name: &str) -> Result<Vec<u8>, AsmError>
+{
+    let mut tokens = tokenize(source, name);
+    let end = Location {
+        file: name.to_string(),
+        line: source.lines().count().max(1),
+        column: 1,
+    };
+    // The compiler pops tokens off the end.
+    tokens.reverse();
+    let mut compiler = Compiler {
+        tokens,
+        end,
+        rom: Vec::new(),
+        here: START,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        expansions: 0,
+    };
+    compiler.compile()?;
+    Ok(compiler.rom)
+}
+
+#[derive(Clone, Debug)]
+struct Token
+{
+    text: String,
+    location: Location,
+}
+
+/// Splits the source on whitespace, dropping `#` comments.
+fn tokenize(source: &str, name: &str) -> Vec<Token>
+{
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate()
+    {
+        let mut start = None;
+        for (column, c) in line.chars().chain([' ']).enumerate()
+        {
+            match (c.is_whitespace(), start)
+            {
+                (false, None) if c == '#' => break,
+                (false, None) => start = Some(column),
+                (true, Some(first)) =>
+                {
+                    tokens.push(Token {
+                        text: line.chars().skip(first).take(column - first).collect(),
+                        location: Location {
+                            file: name.to_string(),
+                            line: index + 1,
+                            column: first + 1,
+                        },
+                    });
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+    }
+    tokens
+}
+
+struct Macro
+{
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Where an address has to be filled in once every label is known.
+#[derive(Copy, Clone, Debug)]
+enum Fixup
+{
+    /// The low 12 bits of the instruction at this ROM offset.
+    Address(usize),
+    /// The operand of `i := long`.
+    Long(usize),
+    /// `:unpack`: the high nibble of the address goes in the first
+    /// instruction's byte, after the given nibble, and the low byte in the
+    /// second one's.
+    Unpack(usize, u8),
+}
+
+/// An open `begin` or `loop`, with the offsets of jumps that go to its end.
+enum Block
+{
+    Branch
+    {
+        pending: usize,
+        else_seen: bool,
+        location: Location,
+    },
+    Loop
+    {
+        start: usize,
+        breaks: Vec<usize>,
+        location: Location,
+    },
+}
+
+struct Compiler
+{
+    /// The remaining tokens, last one first.
+    tokens: Vec<Token>,
+    /// Where "unexpected end of file" points.
+    end: Location,
+    rom: Vec<u8>,
+    /// The address the next byte goes to.
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(Fixup, String, Location)>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+impl Compiler
+{
+    fn compile(&mut self) -> Result<(), AsmError>
+    {
+        // Patched to jump to main at the end.
+        self.emit_op(OpCode::Jmp { nnn: 0 });
+        let main_jump = Token {
+            text: "main".to_string(),
+            location: Location {
+                line: 1,
+                ..self.end.clone()
+            },
+        };
+        self.fixups.push((Fixup::Address(0), main_jump.text, main_jump.location));
+        while let Some(token) = self.tokens.pop()
+        {
+            self.statement(token)?;
+        }
+        if let Some(block) = self.blocks.last()
+        {
+            let (location, what) = match block
+            {
+                Block::Branch { location, .. } => (location, "'begin' without 'end'"),
+                Block::Loop { location, .. } => (location, "'loop' without 'again'"),
+            };
+            return Err(AsmError::at(location, what.to_string()));
+        }
+        for (fixup, name, location) in std::mem::take(&mut self.fixups)
+        {
+            let Some(&address) = self.labels.get(&name)
+            else
+            {
+                let message = if name == "main"
+                {
+                    "the program has no 'main' label".to_string()
+                }
+                else
+                {
+                    format!("'{}' is not defined", name)
+                };
+                return Err(AsmError::at(&location, message));
+            };
+            match fixup
+            {
+                Fixup::Address(offset) =>
+                {
+                    if address > 0xFFF
+                    {
+                        return Err(AsmError::at(
+                            &location,
+                            format!("'{}' is above 0xFFF; use 'i := long'", name),
+                        ));
+                    }
+                    self.patch_address(offset, address);
+                }
+                Fixup::Long(offset) =>
+                {
+                    self.rom[offset..offset + 2].copy_from_slice(&(address as u16).to_be_bytes());
+                }
+                Fixup::Unpack(offset, nibble) =>
+                {
+                    self.rom[offset + 1] = nibble << 4 | (address >> 8) as u8 & 0xF;
+                    self.rom[offset + 3] = address as u8;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError>
+    {
+        let location = &token.location;
+        match token.text.as_str()
+        {
+            ":" =>
+            {
+                let name = self.name()?;
+                self.define_label(name, self.here, location)?;
+            }
+            ":next" =>
+            {
+                let name = self.name()?;
+                self.define_label(name, self.here + 1, location)?;
+            }
+            ":const" =>
+            {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" =>
+            {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":macro" => self.define_macro()?,
+            ":calc" =>
+            {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":byte" =>
+            {
+                let value = if self.peek_is("{")
+                {
+                    self.calc()?
+                }
+                else
+                {
+                    self.value()?
+                };
+                self.emit_byte(value, location)?;
+            }
+            ":org" =>
+            {
+                let address = self.value()?;
+                if !(START as i64..=0xFFFF).contains(&address)
+                {
+                    return Err(AsmError::at(
+                        location,
+                        format!("cannot :org to {}", address),
+                    ));
+                }
+                self.here = address as usize;
+            }
+            ":call" =>
+            {
+                let target = self.next()?;
+                self.emit_addressed(|nnn| OpCode::Call { nnn }, &target)?;
+            }
+            ":unpack" =>
+            {
+                let nibble = self.value_in(0, 0xF)? as u8;
+                let target = self.next()?;
+                let offset = self.offset();
+                self.emit_op(OpCode::LdVxBy { x: 0, kk: 0 });
+                self.emit_op(OpCode::LdVxBy { x: 1, kk: 0 });
+                self.fixups.push((Fixup::Unpack(offset, nibble), target.text, target.location));
+            }
+            ";" | "return" => self.emit_op(OpCode::Ret),
+            "clear" => self.emit_op(OpCode::Cls),
+            "exit" => self.emit_op(OpCode::Exit),
+            "lores" => self.emit_op(OpCode::Lores),
+            "hires" => self.emit_op(OpCode::Hires),
+            "scroll-right" => self.emit_op(OpCode::ScrollRight),
+            "scroll-left" => self.emit_op(OpCode::ScrollLeft),
+            "audio" => self.emit_op(OpCode::Audio),
+            "scroll-down" =>
+            {
+                let n = self.value_in(0, 0xF)? as u8;
+                self.emit_op(OpCode::ScrollDown { n });
+            }
+            "scroll-up" =>
+            {
+                let n = self.value_in(0, 0xF)? as u8;
+                self.emit_op(OpCode::ScrollUp { n });
+            }
+            "plane" =>
+            {
+                let n = self.value_in(0, 0xF)? as u8;
+                self.emit_op(OpCode::Plane { n });
+            }
+            "bcd" =>
+            {
+                let x = self.register()?;
+                self.emit_op(OpCode::LdBVx { x });
+            }
+            "save" | "load" =>
+            {
+                let x = self.register()?;
+                let op = if self.peek_is("-")
+                {
+                    self.tokens.pop();
+                    let y = self.register()?;
+                    if token.text == "save"
+                    {
+                        OpCode::SaveVxVy { x, y }
+                    }
+                    else
+                    {
+                        OpCode::LoadVxVy { x, y }
+                    }
+                }
+                else if token.text == "save"
+                {
+                    OpCode::LdIVx { x }
+                }
+                else
+                {
+                    OpCode::LdVxI { x }
+                };
+                self.emit_op(op);
+            }
+            "saveflags" =>
+            {
+                let x = self.register()?;
+                self.emit_op(OpCode::LdRVx { x });
+            }
+            "loadflags" =>
+            {
+                let x = self.register()?;
+                self.emit_op(OpCode::LdVxR { x });
+            }
+            "sprite" =>
+            {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.value_in(0, 0xF)? as u8;
+                self.emit_op(OpCode::Display { x, y, n });
+            }
+            "jump" =>
+            {
+                let target = self.next()?;
+                self.emit_addressed(|nnn| OpCode::Jmp { nnn }, &target)?;
+            }
+            "jump0" =>
+            {
+                let target = self.next()?;
+                self.emit_addressed(|nnn| OpCode::JpV0Addr { nnn }, &target)?;
+            }
+            "delay" | "buzzer" | "pitch" =>
+            {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit_op(match token.text.as_str()
+                {
+                    "delay" => OpCode::LdDtVx { x },
+                    "buzzer" => OpCode::LdStVx { x },
+                    _ => OpCode::LdPitchVx { x },
+                });
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement(location)?,
+            "else" =>
+            {
+                let offset = self.offset();
+                let from = match self.blocks.last_mut()
+                {
+                    Some(Block::Branch {
+                        pending, else_seen, ..
+                    }) if !*else_seen =>
+                    {
+                        *else_seen = true;
+                        std::mem::replace(pending, offset)
+                    }
+                    _ => return Err(AsmError::at(location, "'else' without 'begin'".to_string())),
+                };
+                self.emit_op(OpCode::Jmp { nnn: 0 });
+                self.patch_address(from, self.here);
+            }
+            "end" => match self.blocks.pop()
+            {
+                Some(Block::Branch { pending, .. }) => self.patch_address(pending, self.here),
+                _ => return Err(AsmError::at(location, "'end' without 'begin'".to_string())),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here,
+                breaks: Vec::new(),
+                location: location.clone(),
+            }),
+            "while" =>
+            {
+                if !self.blocks.iter().any(|block| matches!(block, Block::Loop { .. }))
+                {
+                    return Err(AsmError::at(
+                        location,
+                        "'while' outside of a loop".to_string(),
+                    ));
+                }
+                self.condition(true)?;
+                let offset = self.offset();
+                self.emit_op(OpCode::Jmp { nnn: 0 });
+                if let Some(Block::Loop { breaks, .. }) =
+                    self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    breaks.push(offset);
+                }
+            }
+            "again" => match self.blocks.pop()
+            {
+                Some(Block::Loop { start, breaks, .. }) =>
+                {
+                    self.emit_op(OpCode::Jmp { nnn: start as u16 });
+                    for offset in breaks
+                    {
+                        self.patch_address(offset, self.here);
+                    }
+                }
+                _ => return Err(AsmError::at(location, "'again' without 'loop'".to_string())),
+            },
+            _ if self.register_named(&token.text).is_some() => self.register_statement(&token)?,
+            _ if self.macros.contains_key(&token.text) => self.expand_macro(&token)?,
+            text =>
+            {
+                let value = self.literal(text).or_else(|| self.constants.get(text).copied());
+                if let Some(value) = value
+                {
+                    self.emit_byte(value, location)?;
+                }
+                else
+                {
+                    // Anything else names a subroutine to call.
+                    self.emit_addressed(|nnn| OpCode::Call { nnn }, &token)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn i_statement(&mut self) -> Result<(), AsmError>
+    {
+        let op = self.next()?;
+        match op.text.as_str()
+        {
+            ":=" => (),
+            "+=" =>
+            {
+                let x = self.register()?;
+                self.emit_op(OpCode::AddIVx { x });
+                return Ok(());
+            }
+            _ =>
+            {
+                return Err(AsmError::at(
+                    &op.location,
+                    format!("unexpected '{}'", op.text),
+                ))
+            }
+        }
+        let target = self.next()?;
+        match target.text.as_str()
+        {
+            "hex" =>
+            {
+                let x = self.register()?;
+                self.emit_op(OpCode::LdFVx { x });
+            }
+            "bighex" =>
+            {
+                let x = self.register()?;
+                self.emit_op(OpCode::LdHfVx { x });
+            }
+            "long" =>
+            {
+                let target = self.next()?;
+                match self.known_value(&target.text)
+                {
+                    Some(nnnn) => self.emit_op(OpCode::LdILong { nnnn: nnnn as u16 }),
+                    None =>
+                    {
+                        let offset = self.offset();
+                        self.emit_op(OpCode::LdILong { nnnn: 0 });
+                        self.fixups.push((Fixup::Long(offset + 2), target.text, target.location));
+                    }
+                }
+            }
+            _ => self.emit_addressed(|nnn| OpCode::LdI { nnn }, &target)?,
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, register: &Token) -> Result<(), AsmError>
+    {
+        let x = self.register_named(&register.text).unwrap_or(0);
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let y = self.register_named(&rhs.text);
+        let instruction = match (op.text.as_str(), y)
+        {
+            (":=", Some(y)) => OpCode::LdVxVy { x, y },
+            (":=", None) if rhs.text == "random" =>
+            {
+                let kk = self.value_in(-0x80, 0xFF)? as u8;
+                OpCode::RndVxBy { x, kk }
+            }
+            (":=", None) if rhs.text == "delay" => OpCode::LdVxDt { x },
+            (":=", None) if rhs.text == "key" => OpCode::LdVxK { x },
+            (":=", None) => OpCode::LdVxBy {
+                x,
+                kk: self.byte_value(&rhs)?,
+            },
+            ("+=", Some(y)) => OpCode::AddVxVy { x, y },
+            ("+=", None) => OpCode::Add {
+                x,
+                kk: self.byte_value(&rhs)?,
+            },
+            ("-=", Some(y)) => OpCode::SubVxVy { x, y },
+            ("-=", None) => OpCode::Add {
+                x,
+                kk: self.byte_value(&rhs)?.wrapping_neg(),
+            },
+            ("=-", Some(y)) => OpCode::SubnVxVy { x, y },
+            ("|=", Some(y)) => OpCode::OrVxVy { x, y },
+            ("&=", Some(y)) => OpCode::AndVxVy { x, y },
+            ("^=", Some(y)) => OpCode::XorVxVy { x, y },
+            (">>=", Some(y)) => OpCode::ShrVxVy { x, y },
+            ("<<=", Some(y)) => OpCode::ShlVxVy { x, y },
+            (_, None) if ["=-", "|=", "&=", "^=", ">>=", "<<="].contains(&op.text.as_str()) =>
+            {
+                return Err(AsmError::at(
+                    &rhs.location,
+                    format!("'{}' needs a register, found '{}'", op.text, rhs.text),
+                ));
+            }
+            _ =>
+            {
+                return Err(AsmError::at(
+                    &op.location,
+                    format!("unexpected '{}'", op.text),
+                ))
+            }
+        };
+        self.emit_op(instruction);
+        Ok(())
+    }
+
+    fn if_statement(&mut self, location: &Location) -> Result<(), AsmError>
+    {
+        // Peek past the condition to see whether this is a one-liner.
+        let kind_index = self
+            .tokens
+            .iter()
+            .rev()
+            .position(|token| token.text == "then" || token.text == "begin");
+        let Some(kind) =
+            kind_index.map(|index| self.tokens[self.tokens.len() - 1 - index].text.clone())
+        else
+        {
+            return Err(AsmError::at(
+                location,
+                "'if' without 'then' or 'begin'".to_string(),
+            ));
+        };
+        if kind == "then"
+        {
+            self.condition(false)?;
+            self.expect("then")?;
+        }
+        else
+        {
+            self.condition(true)?;
+            self.expect("begin")?;
+            let pending = self.offset();
+            self.emit_op(OpCode::Jmp { nnn: 0 });
+            self.blocks.push(Block::Branch {
+                pending,
+                else_seen: false,
+                location: location.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Emits code after which the next instruction only runs if the
+    /// condition holds, or only if it does not when `negate` is set.
+    fn condition(&mut self, negate: bool) -> Result<(), AsmError>
+    {
+        let x = self.register()?;
+        let op = self.next()?;
+        let mut comparison = match op.text.as_str()
+        {
+            "key" | "-key" | "==" | "!=" | "<" | ">" | "<=" | ">=" => op.text.clone(),
+            _ =>
+            {
+                return Err(AsmError::at(
+                    &op.location,
+                    format!("unknown condition '{}'", op.text),
+                ))
+            }
+        };
+        if negate
+        {
+            comparison = match comparison.as_str()
+            {
+                "key" => "-key",
+                "-key" => "key",
+                "==" => "!=",
+                "!=" => "==",
+                "<" => ">=",
+                ">=" => "<",
+                ">" => "<=",
+                _ => ">",
+            }
+            .to_string();
+        }
+        match comparison.as_str()
+        {
+            "key" => self.emit_op(OpCode::SknpVx { x }),
+            "-key" => self.emit_op(OpCode::SkpVx { x }),
+            "==" | "!=" =>
+            {
+                let rhs = self.next()?;
+                let equal = comparison == "==";
+                let op = match (self.register_named(&rhs.text), equal)
+                {
+                    (Some(y), true) => OpCode::SneVxVy { x, y },
+                    (Some(y), false) => OpCode::SeVxVy { x, y },
+                    (None, true) => OpCode::SneVxBy {
+                        x,
+                        kk: self.byte_value(&rhs)?,
+                    },
+                    (None, false) => OpCode::SeVxBy {
+                        x,
+                        kk: self.byte_value(&rhs)?,
+                    },
+                };
+                self.emit_op(op);
+            }
+            _ =>
+            {
+                // Octo's comparisons: subtract through vf and test the borrow.
+                let rhs = self.next()?;
+                match self.register_named(&rhs.text)
+                {
+                    Some(y) => self.emit_op(OpCode::LdVxVy { x: 0xF, y }),
+                    None =>
+                    {
+                        let kk = self.byte_value(&rhs)?;
+                        self.emit_op(OpCode::LdVxBy { x: 0xF, kk });
+                    }
+                }
+                // vf ends up 1 if x >= rhs for `=-` and if rhs >= x for `-=`.
+                if matches!(comparison.as_str(), "<" | ">=")
+                {
+                    self.emit_op(OpCode::SubnVxVy { x: 0xF, y: x });
+                }
+                else
+                {
+                    self.emit_op(OpCode::SubVxVy { x: 0xF, y: x });
+                }
+                if matches!(comparison.as_str(), ">=" | "<=")
+                {
+                    self.emit_op(OpCode::SeVxBy { x: 0xF, kk: 0 });
+                }
+                else
+                {
+                    self.emit_op(OpCode::SneVxBy { x: 0xF, kk: 0 });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError>
+    {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop
+        {
+            let token = self.next()?;
+            if token.text == "{"
+            {
+                break;
+            }
+            params.push(token.text);
+        }
+        let body = self.braced()?;
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, call: &Token) -> Result<(), AsmError>
+    {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS
+        {
+            return Err(AsmError::at(
+                &call.location,
+                "too many macro expansions; is a macro calling itself?".to_string(),
+            ));
+        }
+        let params = self.macros[&call.text].params.clone();
+        let mut args = HashMap::new();
+        for param in params
+        {
+            args.insert(param, self.next()?.text);
+        }
+        let body = &self.macros[&call.text].body;
+        let expanded: Vec<Token> = body
+            .iter()
+            .rev()
+            .map(|token| Token {
+                text: args.get(&token.text).cloned().unwrap_or_else(|| token.text.clone()),
+                location: token.location.clone(),
+            })
+            .collect();
+        self.tokens.extend(expanded);
+        Ok(())
+    }
+
+    /// The tokens up to the `}` matching an already read `{`.
+    fn braced(&mut self) -> Result<Vec<Token>, AsmError>
+    {
+        let mut depth = 0;
+        let mut body = Vec::new();
+        loop
+        {
+            let token = self.next()?;
+            match token.text.as_str()
+            {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+    }
+
+    /// Evaluates `{ expression }`. As in Octo, operators have no precedence
+    /// and are applied from right to left.
+    fn calc(&mut self) -> Result<i64, AsmError>
+    {
+        self.expect("{")?;
+        let mut tokens = self.braced()?;
+        let end = tokens.last().map_or(self.end.clone(), |token| token.location.clone());
+        tokens.reverse();
+        let value = self.calc_expr(&mut tokens, &end)?;
+        if let Some(token) = tokens.pop()
+        {
+            return Err(AsmError::at(
+                &token.location,
+                format!("unexpected '{}'", token.text),
+            ));
+        }
+        Ok(value)
+    }
+
+    fn calc_expr(&self, tokens: &mut Vec<Token>, end: &Location) -> Result<i64, AsmError>
+    {
+        let left = self.calc_term(tokens, end)?;
+        let Some(op) = tokens.last().filter(|token| token.text != ")").cloned()
+        else
+        {
+            return Ok(left);
+        };
+        tokens.pop();
+        let right = self.calc_expr(tokens, end)?;
+        let value = match op.text.as_str()
+        {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" => left.checked_div(right).unwrap_or(0),
+            "%" => left.checked_rem(right).unwrap_or(0),
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64,
+            ">" => (left > right) as i64,
+            "<=" => (left <= right) as i64,
+            ">=" => (left >= right) as i64,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            _ =>
+            {
+                return Err(AsmError::at(
+                    &op.location,
+                    format!("unknown operator '{}'", op.text),
+                ))
+            }
+        };
+        Ok(value)
+    }
+
+    fn calc_term(&self, tokens: &mut Vec<Token>, end: &Location) -> Result<i64, AsmError>
+    {
+        let Some(token) = tokens.pop()
+        else
+        {
+            return Err(AsmError::at(end, "expected a value".to_string()));
+        };
+        match token.text.as_str()
+        {
+            "-" => Ok(self.calc_term(tokens, end)?.wrapping_neg()),
+            "~" => Ok(!self.calc_term(tokens, end)?),
+            "!" => Ok((self.calc_term(tokens, end)? == 0) as i64),
+            "(" =>
+            {
+                let value = self.calc_expr(tokens, end)?;
+                match tokens.pop()
+                {
+                    Some(close) if close.text == ")" => Ok(value),
+                    _ => Err(AsmError::at(&token.location, "unclosed '('".to_string())),
+                }
+            }
+            "HERE" => Ok(self.here as i64),
+            text => self.known_value(text).ok_or_else(|| {
+                AsmError::at(&token.location, format!("'{}' is not a known value", text))
+            }),
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError>
+    {
+        self.tokens
+            .pop()
+            .ok_or_else(|| AsmError::at(&self.end, "unexpected end of file".to_string()))
+    }
+
+    fn peek_is(&self, text: &str) -> bool
+    {
+        self.tokens.last().is_some_and(|token| token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError>
+    {
+        let token = self.next()?;
+        if token.text == text
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(AsmError::at(
+                &token.location,
+                format!("expected '{}', found '{}'", text, token.text),
+            ))
+        }
+    }
+
+    /// A name for a new label, constant, alias or macro.
+    fn name(&mut self) -> Result<String, AsmError>
+    {
+        let token = self.next()?;
+        if self.literal(&token.text).is_some() || self.register_named(&token.text).is_some()
+        {
+            return Err(AsmError::at(
+                &token.location,
+                format!("'{}' cannot be used as a name", token.text),
+            ));
+        }
+        Ok(token.text)
+    }
+
+    fn define_label(
+        &mut self,
+        name: String,
+        address: usize,
+        location: &Location,
+    ) -> Result<(), AsmError>
+    {
+        if self.labels.insert(name.clone(), address).is_some()
+        {
+            return Err(AsmError::at(
+                location,
+                format!("'{}' is already defined", name),
+            ));
+        }
+        Ok(())
+    }
+
+    fn register_named(&self, text: &str) -> Option<u8>
+    {
+        if let Some(&register) = self.aliases.get(text)
+        {
+            return Some(register);
+        }
+        let digit = text.strip_prefix(['v', 'V']).filter(|digit| digit.len() == 1)?;
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError>
+    {
+        let token = self.next()?;
+        self.register_named(&token.text).ok_or_else(|| {
+            AsmError::at(
+                &token.location,
+                format!("expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    /// A number written out, e.g. `12`, `-1`, `0xF0` or `0b1010`.
+    fn literal(&self, text: &str) -> Option<i64>
+    {
+        let (negative, digits) = match text.strip_prefix('-')
+        {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x")
+        {
+            i64::from_str_radix(hex, 16).ok()?
+        }
+        else if let Some(binary) = digits.strip_prefix("0b")
+        {
+            i64::from_str_radix(binary, 2).ok()?
+        }
+        else if digits.starts_with(|c: char| c.is_ascii_digit())
+        {
+            digits.parse().ok()?
+        }
+        else
+        {
+            return None;
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    /// A literal, constant or already defined label.
+    fn known_value(&self, text: &str) -> Option<i64>
+    {
+        self.literal(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|address| *address as i64))
+    }
+
+    fn value(&mut self) -> Result<i64, AsmError>
+    {
+        let token = self.next()?;
+        self.known_value(&token.text).ok_or_else(|| {
+            AsmError::at(
+                &token.location,
+                format!("expected a number, found '{}'", token.text),
+            )
+        })
+    }
+
+    fn value_in(&mut self, min: i64, max: i64) -> Result<i64, AsmError>
+    {
+        let location = self.tokens.last().map(|token| token.location.clone());
+        let value = self.value()?;
+        if (min..=max).contains(&value)
+        {
+            Ok(value)
+        }
+        else
+        {
+            Err(AsmError::at(
+                &location.unwrap_or_else(|| self.end.clone()),
+                format!("{} is out of range", value),
+            ))
+        }
+    }
+
+    fn byte_value(&self, token: &Token) -> Result<u8, AsmError>
+    {
+        match self.known_value(&token.text)
+        {
+            Some(value) if (-0x80..=0xFF).contains(&value) => Ok(value as u8),
+            Some(value) => Err(AsmError::at(
+                &token.location,
+                format!("{} does not fit in a byte", value),
+            )),
+            None => Err(AsmError::at(
+                &token.location,
+                format!("expected a number, found '{}'", token.text),
+            )),
+        }
+    }
+
+    /// The ROM offset of `here`.
+    fn offset(&self) -> usize
+    {
+        self.here - START
+    }
+
+    fn emit_byte(&mut self, value: i64, location: &Location) -> Result<(), AsmError>
+    {
+        if !(-0x80..=0xFF).contains(&value)
+        {
+            return Err(AsmError::at(
+                location,
+                format!("{} does not fit in a byte", value),
+            ));
+        }
+        let offset = self.offset();
+        if self.rom.len() <= offset
+        {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = value as u8;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_op(&mut self, op: OpCode)
+    {
+        for byte in op.encode()
+        {
+            let offset = self.offset();
+            if self.rom.len() <= offset
+            {
+                self.rom.resize(offset + 1, 0);
+            }
+            self.rom[offset] = byte;
+            self.here += 1;
+        }
+    }
+
+    /// Emits an instruction that takes a 12-bit address, which may be a label
+    /// defined further down.
+    fn emit_addressed(&mut self, op: impl Fn(u16) -> OpCode, target: &Token)
+        -> Result<(), AsmError>
+    {
+        if let Some(address) = self.known_value(&target.text)
+        {
+            if !(0..=0xFFF).contains(&address)
+            {
+                return Err(AsmError::at(
+                    &target.location,
+                    format!("{} is not a 12-bit address", address),
+                ));
+            }
+            self.emit_op(op(address as u16));
+        }
+        else
+        {
+            let offset = self.offset();
+            self.emit_op(op(0));
+            self.fixups.push((
+                Fixup::Address(offset),
+                target.text.clone(),
+                target.location.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn patch_address(&mut self, offset: usize, address: usize)
+    {
+        self.rom[offset] = self.rom[offset] & 0xF0 | (address >> 8) as u8 & 0xF;
+        self.rom[offset + 1] = address as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::disasm::{self, Syntax};
+    use crate::machine::Chip8;
+
+    const SOURCE: &str = r#"
+        # Count v0 up to a limit, summing into v1.
+        :alias total v1
+        :const LIMIT 5
+        :calc DOUBLE { LIMIT * 2 }
+        :macro bump reg { reg += 1 }
+
+        : main
+            v0 := 0
+            total := 0
+            loop
+                bump v0
+                total += v0
+                while v0 < LIMIT
+            again
+            if total == 15 then v2 := DOUBLE
+            if v0 >= 6 begin
+                v3 := 1
+            else
+                v3 := 2
+            end
+            if v2 > v3 then v4 := 0xAA
+            i := sprite
+            add-one
+            loop again
+
+        : add-one
+            v5 += 1
+        ;
+
+        : sprite
+            0b11110000 0x90 LIMIT
+    "#;
+
+    /// The line, column and message of the error compiling `source`.
+    fn error(source: &str) -> (usize, usize, String)
+    {
+        match compile(source)
+        {
+            Err(AsmError::Source { location, message }) =>
+            {
+                (location.line, location.column, message)
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn compile_test()
+    {
+        let rom = compile(SOURCE).unwrap();
+        assert_eq!(&rom[..6], [0x12, 0x02, 0x60, 0x00, 0x61, 0x00]);
+        assert_eq!(&rom[rom.len() - 3..], [0xF0, 0x90, 0x05]);
+    }
+
+    #[test]
+    fn compiled_program_runs_test()
+    {
+        let rom = compile(SOURCE).unwrap();
+        let mut machine = Chip8::new();
+        machine.load_rom(&rom).unwrap();
+        machine.run_cycles(200).unwrap();
+        assert_eq!(machine.registers.v[..6], [5, 15, 10, 2, 0xAA, 1]);
+        assert_eq!(machine.registers.i as usize, 0x200 + rom.len() - 3);
+    }
+
+    #[test]
+    fn disassembles_as_octo_test()
+    {
+        let lines = disasm::disassemble(&compile(SOURCE).unwrap(), 0x200);
+        assert_eq!(lines[0].text(Syntax::Octo), "jump 0x202");
+    }
+
+    #[test]
+    fn byte_out_of_range_test()
+    {
+        assert_eq!(
+            error(": main\n  v0 := 300"),
+            (2, 9, "300 does not fit in a byte".to_string())
+        );
+    }
+
+    #[test]
+    fn undefined_label_test()
+    {
+        assert_eq!(
+            error(": main\n  jump nowhere"),
+            (2, 8, "'nowhere' is not defined".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_main_test()
+    {
+        assert_eq!(
+            error("v0 := 1"),
+            (1, 1, "the program has no 'main' label".to_string())
+        );
+    }
+
+    #[test]
+    fn unclosed_loop_test()
+    {
+        assert_eq!(
+            error(": main\nloop\n  v0 += 1"),
+            (2, 1, "'loop' without 'again'".to_string())
+        );
+    }
+
+    #[test]
+    fn operator_needs_register_test()
+    {
+        assert_eq!(
+            error(": main\n  v0 |= 1"),
+            (2, 9, "'|=' needs a register, found '1'".to_string())
+        );
+    }
+}
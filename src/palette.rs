@@ -0,0 +1,235 @@
+use std::{env, fmt, str::FromStr};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl FromStr for Rgb
+{
+    type Err = String;
+
+    /// Parses `#rrggbb`.
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let invalid = || format!("'{}' is not a colour like #33ff66", s);
+        let hex = s.strip_prefix('#').filter(|hex| hex.len() == 6).ok_or_else(invalid)?;
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        Ok(Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+impl fmt::Display for Rgb
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// The colour of each pixel value. Plain CHIP-8 and SUPER-CHIP only use the
+/// first two; XO-CHIP's two bit planes use all four.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Palette
+{
+    /// Background, plane 1, plane 2, and both planes.
+    pub colors: [Rgb; 4],
+}
+
+impl Default for Palette
+{
+    fn default() -> Self
+    {
+        Palette::OCTO
+    }
+}
+
+impl Palette
+{
+    pub const PRESET_NAMES: &'static [&'static str] =
+        &["octo", "phosphor", "amber", "high-contrast"];
+
+    /// Octo's default colours.
+    pub const OCTO: Palette = Palette {
+        colors: [
+            Rgb(0x99, 0x66, 0x00),
+            Rgb(0xff, 0xcc, 0x00),
+            Rgb(0xff, 0x66, 0x00),
+            Rgb(0x66, 0x22, 0x00),
+        ],
+    };
+    /// Green phosphor on a dark tube.
+    pub const PHOSPHOR: Palette = Palette {
+        colors: [
+            Rgb(0x0a, 0x1a, 0x0a),
+            Rgb(0x33, 0xff, 0x66),
+            Rgb(0x1a, 0x80, 0x33),
+            Rgb(0xb3, 0xff, 0xc6),
+        ],
+    };
+    /// Amber monochrome monitor.
+    pub const AMBER: Palette = Palette {
+        colors: [
+            Rgb(0x1a, 0x0f, 0x00),
+            Rgb(0xff, 0xb0, 0x00),
+            Rgb(0x80, 0x58, 0x00),
+            Rgb(0xff, 0xe0, 0x99),
+        ],
+    };
+    pub const HIGH_CONTRAST: Palette = Palette {
+        colors: [
+            Rgb(0x00, 0x00, 0x00),
+            Rgb(0xff, 0xff, 0xff),
+            Rgb(0xff, 0x00, 0x00),
+            Rgb(0x00, 0xff, 0xff),
+        ],
+    };
+
+    pub fn preset(name: &str) -> Option<Palette>
+    {
+        match name.to_ascii_lowercase().as_str()
+        {
+            "octo" => Some(Palette::OCTO),
+            "phosphor" | "green" => Some(Palette::PHOSPHOR),
+            "amber" => Some(Palette::AMBER),
+            "high-contrast" => Some(Palette::HIGH_CONTRAST),
+            _ => None,
+        }
+    }
+
+    pub fn color(&self, pixel: u8) -> Rgb
+    {
+        self.colors[pixel as usize & 0b11]
+    }
+}
+
+/// How colours are sent to the terminal.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode
+{
+    /// No colour escapes; the terminal's own colours are used.
+    #[default]
+    Monochrome,
+    /// The xterm 256-colour palette.
+    Ansi256,
+    /// 24-bit colour.
+    TrueColor,
+}
+
+impl ColorMode
+{
+    pub const NAMES: &'static [&'static str] = &["auto", "none", "256", "truecolor"];
+
+    /// Guesses what the terminal supports from `COLORTERM` and `TERM`.
+    pub fn detect() -> Self
+    {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit"
+        {
+            ColorMode::TrueColor
+        }
+        else if term.contains("256color")
+        {
+            ColorMode::Ansi256
+        }
+        else
+        {
+            ColorMode::Monochrome
+        }
+    }
+
+    /// The SGR sequence that sets the foreground and background colours.
+    pub fn escape(self, fg: Rgb, bg: Rgb) -> String
+    {
+        match self
+        {
+            ColorMode::Monochrome => String::new(),
+            ColorMode::Ansi256 => format!("\x1b[38;5;{};48;5;{}m", ansi256(fg), ansi256(bg)),
+            ColorMode::TrueColor => format!(
+                "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                fg.0, fg.1, fg.2, bg.0, bg.1, bg.2
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownColorMode(pub String);
+
+impl fmt::Display for UnknownColorMode
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(
+            f,
+            "unknown colour mode '{}', expected one of: {}",
+            self.0,
+            ColorMode::NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownColorMode {}
+
+impl FromStr for ColorMode
+{
+    type Err = UnknownColorMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_ascii_lowercase().as_str()
+        {
+            "auto" => Ok(ColorMode::detect()),
+            "none" | "mono" => Ok(ColorMode::Monochrome),
+            "256" | "ansi256" => Ok(ColorMode::Ansi256),
+            "truecolor" | "24bit" => Ok(ColorMode::TrueColor),
+            _ => Err(UnknownColorMode(s.to_string())),
+        }
+    }
+}
+
+/// The closest entry in the xterm 256-colour palette, picking between the
+/// 6x6x6 colour cube and the grey ramp.
+pub fn ansi256(color: Rgb) -> u8
+{
+    const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest_level =
+        |v: u8| (0..6).min_by_key(|i| (CUBE_LEVELS[*i] as i32 - v as i32).abs()).unwrap_or(0);
+    let (r, g, b) = (
+        nearest_level(color.0),
+        nearest_level(color.1),
+        nearest_level(color.2),
+    );
+    let cube = Rgb(CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+
+    let average = (color.0 as u32 + color.1 as u32 + color.2 as u32) / 3;
+    let grey_index = (average.saturating_sub(3) / 10).min(23) as u8;
+    let grey_level = 8 + 10 * grey_index;
+    let grey = Rgb(grey_level, grey_level, grey_level);
+
+    let distance = |other: Rgb| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(color.0, other.0) + d(color.1, other.1) + d(color.2, other.2)
+    };
+    if distance(grey) < distance(cube)
+    {
+        232 + grey_index
+    }
+    else
+    {
+        16 + 36 * r as u8 + 6 * g as u8 + b as u8
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn ansi256_test()
+    {
+        assert_eq!(ansi256(Rgb(0, 0, 0)), 16);
+        assert_eq!(ansi256(Rgb(255, 0, 0)), 196);
+        assert_eq!(ansi256(Rgb(128, 128, 128)), 244);
+    }
+}
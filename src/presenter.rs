@@ -0,0 +1,257 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use crate::bitmap::{Bitmap, BitmapProtocol};
+use crate::guest_graphics::ChipDisplay;
+use crate::palette::{ColorMode, Palette};
+use crate::renderer::{Cell, Renderer};
+
+/// Draws frames to the terminal, sending only the cells that changed since
+/// the previous frame.
+///
+/// Changed cells are written in runs, each preceded by a single cursor move.
+/// The whole screen is redrawn on the first frame and whenever the size of
+/// the rendered frame changes, e.g. when a SUPER-CHIP program switches
+/// resolution.
+///
+/// With a bitmap protocol the display is sent as an image instead, redrawn
+/// whole whenever any pixel changes.
+pub struct Presenter
+{
+    renderer: Box<dyn Renderer>,
+    palette: Palette,
+    color_mode: ColorMode,
+    previous: Vec<Vec<Cell>>,
+    bitmap: Option<(BitmapProtocol, u32)>,
+    previous_bitmap: Option<Bitmap>,
+}
+
+impl Presenter
+{
+    pub fn new(renderer: Box<dyn Renderer>) -> Self
+    {
+        Self {
+            renderer,
+            palette: Palette::default(),
+            color_mode: ColorMode::Monochrome,
+            previous: Vec::new(),
+            bitmap: None,
+            previous_bitmap: None,
+        }
+    }
+
+    /// Draws pixels in the colours of `palette`, sent as `color_mode` asks.
+    pub fn with_colors(mut self, palette: Palette, color_mode: ColorMode) -> Self
+    {
+        self.palette = palette;
+        self.color_mode = color_mode;
+        self
+    }
+
+    /// Draws the display as an image scaled by `scale`; see
+    /// [`Bitmap::from_display`]. The palette colours are used whatever the
+    /// colour mode.
+    pub fn with_bitmap(mut self, protocol: BitmapProtocol, scale: u32) -> Self
+    {
+        self.bitmap = Some((protocol, scale));
+        self
+    }
+
+    /// Forgets the last frame so the next one is drawn in full.
+    pub fn invalidate(&mut self)
+    {
+        self.previous.clear();
+        self.previous_bitmap = None;
+    }
+
+    pub fn present(&mut self, display: &ChipDisplay, out: &mut impl Write) -> io::Result<()>
+    {
+        let update = self.update(display);
+        if !update.is_empty()
+        {
+            out.write_all(update.as_bytes())?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The escape sequences and text that turn the previous frame into this
+    /// one.
+    pub fn update(&mut self, display: &ChipDisplay) -> String
+    {
+        if let Some((protocol, scale)) = self.bitmap
+        {
+            return self.update_bitmap(display, protocol, scale);
+        }
+        let frame = self.renderer.render_cells(display);
+        let same_shape = frame.len() == self.previous.len()
+            && frame.iter().zip(&self.previous).all(|(new, old)| new.len() == old.len());
+
+        let mut update = String::new();
+        // The (fg, bg) pixel values the terminal is currently drawing with.
+        let mut colors = None;
+        if !same_shape
+        {
+            // Clearing with the background colour set fills the whole screen.
+            update.push_str(&self.color_mode.escape(self.palette.color(0), self.palette.color(0)));
+            update.push_str("\x1b[2J");
+            colors = Some((0, 0));
+        }
+        let cell_width = self.renderer.cell_width();
+        for (row, cells) in frame.iter().enumerate()
+        {
+            let old = self.previous.get(row).filter(|_| same_shape);
+            let mut cursor_at = None;
+            for (column, cell) in cells.iter().enumerate()
+            {
+                if old.is_some_and(|old| old[column] == *cell)
+                {
+                    continue;
+                }
+                if cursor_at != Some(column)
+                {
+                    // Terminal rows and columns count from 1.
+                    let _ = write!(update, "\x1b[{};{}H", row + 1, column * cell_width + 1);
+                }
+                if colors != Some((cell.fg, cell.bg))
+                {
+                    let (fg, bg) = (self.palette.color(cell.fg), self.palette.color(cell.bg));
+                    update.push_str(&self.color_mode.escape(fg, bg));
+                    colors = Some((cell.fg, cell.bg));
+                }
+                update.push(cell.ch);
+                cursor_at = Some(column + 1);
+            }
+        }
+        if colors.is_some() && self.color_mode != ColorMode::Monochrome
+        {
+            update.push_str("\x1b[0m");
+        }
+        self.previous = frame;
+        update
+    }
+
+    fn update_bitmap(
+        &mut self,
+        display: &ChipDisplay,
+        protocol: BitmapProtocol,
+        scale: u32,
+    ) -> String
+    {
+        let bitmap = Bitmap::from_display(display, scale);
+        if self.previous_bitmap.as_ref() == Some(&bitmap)
+        {
+            return String::new();
+        }
+        let same_size = self.previous_bitmap.as_ref().is_some_and(|previous| {
+            (previous.width, previous.height) == (bitmap.width, bitmap.height)
+        });
+        let mut update = String::new();
+        if !same_size
+        {
+            // A smaller image would leave the edges of the old one behind.
+            update.push_str("\x1b[2J");
+        }
+        update.push_str("\x1b[1;1H");
+        update.push_str(&protocol.encode(&bitmap, &self.palette));
+        self.previous_bitmap = Some(bitmap);
+        update
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::renderer::RendererKind;
+
+    fn lit(pixels: &[(u8, u8)]) -> ChipDisplay
+    {
+        let mut display = ChipDisplay::new();
+        for (x, y) in pixels
+        {
+            display.set_pixel(*x, *y, true);
+        }
+        display.debuff();
+        display
+    }
+
+    #[test]
+    fn first_frame_draws_everything_test()
+    {
+        let mut presenter = Presenter::new(RendererKind::Ascii.renderer());
+        let first = presenter.update(&lit(&[]));
+        assert!(first.starts_with("\x1b[2J\x1b[1;1H"));
+        assert_eq!(first.matches("\x1b[").count(), 1 + 32);
+        assert_eq!(presenter.update(&lit(&[])), "");
+    }
+
+    #[test]
+    fn only_changed_cells_are_drawn_test()
+    {
+        let mut presenter = Presenter::new(RendererKind::Ascii.renderer());
+        presenter.update(&lit(&[]));
+        assert_eq!(
+            presenter.update(&lit(&[(5, 2), (6, 2), (63, 31)])),
+            "\x1b[3;6H##\x1b[32;64H#"
+        );
+    }
+
+    #[test]
+    fn wide_cells_test()
+    {
+        let mut emoji = Presenter::new(RendererKind::Emoji.renderer());
+        emoji.update(&lit(&[(5, 2), (6, 2)]));
+        assert_eq!(emoji.update(&lit(&[(6, 2)])), "\x1b[3;11H⬜");
+    }
+
+    #[test]
+    fn resolution_change_redraws_test()
+    {
+        let mut presenter = Presenter::new(RendererKind::Ascii.renderer());
+        let mut display = lit(&[]);
+        presenter.update(&display);
+        display.set_hires(true);
+        display.debuff();
+        assert!(presenter.update(&display).starts_with("\x1b[2J"));
+        presenter.invalidate();
+        assert!(presenter.update(&display).starts_with("\x1b[2J"));
+    }
+
+    #[test]
+    fn truecolor_test()
+    {
+        let mut display = ChipDisplay::new();
+        display.buffer[ChipDisplay::get_buffer_position_from_x_and_y(0, 0)] = 1;
+        display.buffer[ChipDisplay::get_buffer_position_from_x_and_y(0, 1)] = 2;
+        display.debuff();
+        let mut presenter = Presenter::new(RendererKind::HalfBlock.renderer())
+            .with_colors(Palette::HIGH_CONTRAST, ColorMode::TrueColor);
+        let update = presenter.update(&display);
+        assert!(update.starts_with("\x1b[38;2;0;0;0;48;2;0;0;0m\x1b[2J\x1b[1;1H"));
+        assert!(update.contains("\x1b[38;2;255;255;255;48;2;255;0;0m▀"));
+        assert!(update.ends_with("\x1b[0m"));
+
+        display.buffer[ChipDisplay::get_buffer_position_from_x_and_y(0, 1)] = 0;
+        display.debuff();
+        assert_eq!(
+            presenter.update(&display),
+            "\x1b[1;1H\x1b[38;2;255;255;255;48;2;0;0;0m▀\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn bitmap_presenter_test()
+    {
+        let mut display = lit(&[(0, 0)]);
+        let mut presenter = Presenter::new(RendererKind::Sixel.renderer())
+            .with_bitmap(RendererKind::Sixel.bitmap_protocol().unwrap(), 1);
+        assert!(presenter.update(&display).starts_with("\x1b[2J\x1b[1;1H\x1bP"));
+        assert_eq!(presenter.update(&display), "");
+        display.set_pixel(1, 0, true);
+        display.debuff();
+        assert!(presenter.update(&display).starts_with("\x1b[1;1H\x1bP"));
+    }
+}
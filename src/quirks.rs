@@ -0,0 +1,160 @@
+use std::{fmt, str::FromStr};
+
+use crate::machine::{RAM_SIZE, XO_RAM_SIZE};
+
+/// What `FX55`/`FX65` leave in I after copying registers to or from memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemoryIncrement
+{
+    /// I ends up pointing just past the last byte (`I += x + 1`).
+    XPlusOne,
+    /// I is advanced by `x`, as on the CHIP-48.
+    X,
+    /// I is left untouched.
+    Unchanged,
+}
+
+/// Behaviour of the instructions that differ between CHIP-8 implementations.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Quirks
+{
+    /// `8XY6`/`8XYE` shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    pub memory_increment: MemoryIncrement,
+    /// `8XY1`/`8XY2`/`8XY3` set VF to 0.
+    pub vf_reset: bool,
+    /// `BNNN` jumps to `NNN + Vx` where x is the high nibble of NNN.
+    pub jump_uses_vx: bool,
+    /// Sprites are cut off at the screen edge rather than wrapping around.
+    pub clip_sprites: bool,
+    /// `DXYN` waits for the next frame before the machine continues.
+    pub display_wait: bool,
+    /// `FX0A` completes when the key is released rather than when it is
+    /// pressed.
+    pub key_wait_release: bool,
+    /// Size of the address space; 64 KiB on XO-CHIP, 4 KiB elsewhere.
+    pub memory_size: usize,
+}
+
+impl Default for Quirks
+{
+    fn default() -> Self
+    {
+        QuirkProfile::default().quirks()
+    }
+}
+
+/// Named sets of [`Quirks`] matching well-known interpreters.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum QuirkProfile
+{
+    /// The original COSMAC VIP interpreter.
+    #[default]
+    CosmacVip,
+    /// CHIP-48 on the HP-48 calculators.
+    Chip48,
+    /// SUPER-CHIP 1.1.
+    SuperChip,
+    /// XO-CHIP as implemented by Octo.
+    XoChip,
+}
+
+impl QuirkProfile
+{
+    pub const NAMES: &'static [&'static str] = &["vip", "chip48", "schip", "xochip"];
+
+    pub fn quirks(self) -> Quirks
+    {
+        match self
+        {
+            QuirkProfile::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                memory_increment: MemoryIncrement::XPlusOne,
+                vf_reset: true,
+                jump_uses_vx: false,
+                clip_sprites: true,
+                display_wait: true,
+                key_wait_release: true,
+                memory_size: RAM_SIZE,
+            },
+            QuirkProfile::Chip48 => Quirks {
+                shift_uses_vy: false,
+                memory_increment: MemoryIncrement::X,
+                vf_reset: false,
+                jump_uses_vx: true,
+                clip_sprites: true,
+                display_wait: false,
+                key_wait_release: true,
+                memory_size: RAM_SIZE,
+            },
+            QuirkProfile::SuperChip => Quirks {
+                shift_uses_vy: false,
+                memory_increment: MemoryIncrement::Unchanged,
+                vf_reset: false,
+                jump_uses_vx: true,
+                clip_sprites: true,
+                display_wait: false,
+                key_wait_release: true,
+                memory_size: RAM_SIZE,
+            },
+            QuirkProfile::XoChip => Quirks {
+                shift_uses_vy: true,
+                memory_increment: MemoryIncrement::XPlusOne,
+                vf_reset: false,
+                jump_uses_vx: false,
+                clip_sprites: false,
+                display_wait: false,
+                key_wait_release: true,
+                memory_size: XO_RAM_SIZE,
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownProfile(pub String);
+
+impl fmt::Display for UnknownProfile
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(
+            f,
+            "unknown quirk profile '{}', expected one of: {}",
+            self.0,
+            QuirkProfile::NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownProfile {}
+
+impl FromStr for QuirkProfile
+{
+    type Err = UnknownProfile;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_ascii_lowercase().as_str()
+        {
+            "vip" | "cosmac-vip" | "chip8" => Ok(QuirkProfile::CosmacVip),
+            "chip48" => Ok(QuirkProfile::Chip48),
+            "schip" | "superchip" => Ok(QuirkProfile::SuperChip),
+            "xochip" | "xo-chip" => Ok(QuirkProfile::XoChip),
+            _ => Err(UnknownProfile(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn profile_names_test()
+    {
+        assert_eq!("chip48".parse(), Ok(QuirkProfile::Chip48));
+        assert!("octo".parse::<QuirkProfile>().is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use std::{fmt, str::FromStr};
+
+use crate::bitmap::BitmapProtocol;
+use crate::guest_graphics::ChipDisplay;
+
+/// One character of rendered output. The colours are pixel values, which a
+/// [`crate::palette::Palette`] turns into actual colours.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cell
+{
+    pub ch: char,
+    pub fg: u8,
+    pub bg: u8,
+}
+
+impl Cell
+{
+    fn new(ch: char, fg: u8, bg: u8) -> Self
+    {
+        Self { ch, fg, bg }
+    }
+}
+
+/// Turns the framebuffer into lines of terminal text.
+pub trait Renderer
+{
+    /// One row of cells per terminal line.
+    fn render_cells(&self, display: &ChipDisplay) -> Vec<Vec<Cell>>;
+
+    /// The characters of [`Renderer::render_cells`] without the colours.
+    fn render(&self, display: &ChipDisplay) -> Vec<String>
+    {
+        self.render_cells(display)
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.ch).collect())
+            .collect()
+    }
+
+    /// How many terminal columns each character of the output takes up.
+    fn cell_width(&self) -> usize
+    {
+        1
+    }
+}
+
+/// Two wide emoji per pixel and one line per pixel row.
+pub struct EmojiRenderer;
+
+/// Two pixel rows per line using `▀`, `▄` and `█`.
+pub struct HalfBlockRenderer;
+
+/// A 2x4 block of pixels per braille character.
+pub struct BrailleRenderer;
+
+/// `#` for lit pixels and a space for dark ones.
+pub struct AsciiRenderer;
+
+/// The pixel value at `(x, y)`, or 0 off the screen.
+fn pixel(display: &ChipDisplay, x: usize, y: usize) -> u8
+{
+    if x < display.width() && y < display.height()
+    {
+        display.get_pixel(x as u8, y as u8).unwrap_or(0)
+    }
+    else
+    {
+        0
+    }
+}
+
+/// Renders each block of `cell_width` x `cell_height` pixels as one cell.
+fn render_cells(
+    display: &ChipDisplay,
+    cell_width: usize,
+    cell_height: usize,
+    cell: impl Fn(usize, usize) -> Cell,
+) -> Vec<Vec<Cell>>
+{
+    (0..display.height().div_ceil(cell_height))
+        .map(|row| {
+            (0..display.width().div_ceil(cell_width))
+                .map(|column| cell(column * cell_width, row * cell_height))
+                .collect()
+        })
+        .collect()
+}
+
+impl Renderer for EmojiRenderer
+{
+    fn render_cells(&self, display: &ChipDisplay) -> Vec<Vec<Cell>>
+    {
+        render_cells(display, 1, 1, |x, y| {
+            let value = pixel(display, x, y);
+            Cell::new(if value != 0 { '⬛' } else { '⬜' }, value, value)
+        })
+    }
+
+    fn cell_width(&self) -> usize
+    {
+        2
+    }
+}
+
+impl Renderer for HalfBlockRenderer
+{
+    fn render_cells(&self, display: &ChipDisplay) -> Vec<Vec<Cell>>
+    {
+        render_cells(display, 1, 2, |x, y| {
+            let (top, bottom) = (pixel(display, x, y), pixel(display, x, y + 1));
+            match (top, bottom)
+            {
+                (0, 0) => Cell::new(' ', 0, 0),
+                (_, 0) => Cell::new('▀', top, 0),
+                (0, _) => Cell::new('▄', bottom, 0),
+                _ if top == bottom => Cell::new('█', top, 0),
+                _ => Cell::new('▀', top, bottom),
+            }
+        })
+    }
+}
+
+impl Renderer for BrailleRenderer
+{
+    fn render_cells(&self, display: &ChipDisplay) -> Vec<Vec<Cell>>
+    {
+        // Dot bits of a braille cell, indexed by [row][column].
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+        render_cells(display, 2, 4, |x, y| {
+            let mut bits = 0;
+            // A cell has a single foreground colour, so the most common
+            // pixel value wins.
+            let mut counts = [0; 4];
+            for (dy, row) in DOTS.iter().enumerate()
+            {
+                for (dx, bit) in row.iter().enumerate()
+                {
+                    let value = pixel(display, x + dx, y + dy);
+                    if value != 0
+                    {
+                        bits |= bit;
+                        counts[value as usize & 0b11] += 1;
+                    }
+                }
+            }
+            let fg = (1..4).rev().max_by_key(|value| counts[*value]).unwrap_or(1) as u8;
+            let fg = if bits == 0 { 0 } else { fg };
+            Cell::new(char::from_u32(0x2800 + bits).unwrap_or(' '), fg, 0)
+        })
+    }
+}
+
+impl Renderer for AsciiRenderer
+{
+    fn render_cells(&self, display: &ChipDisplay) -> Vec<Vec<Cell>>
+    {
+        render_cells(display, 1, 1, |x, y| {
+            let value = pixel(display, x, y);
+            Cell::new(if value != 0 { '#' } else { ' ' }, value, 0)
+        })
+    }
+}
+
+/// The renderers that can be picked on the command line.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RendererKind
+{
+    Emoji,
+    #[default]
+    HalfBlock,
+    Braille,
+    Ascii,
+    /// Real pixels as sixel graphics.
+    Sixel,
+    /// Real pixels through the kitty graphics protocol.
+    Kitty,
+}
+
+impl RendererKind
+{
+    pub const NAMES: &'static [&'static str] =
+        &["emoji", "half-block", "braille", "ascii", "sixel", "kitty"];
+
+    /// The text renderer to use. Bitmap kinds get half blocks, which is what
+    /// they fall back to when the terminal cannot show images.
+    pub fn renderer(self) -> Box<dyn Renderer>
+    {
+        match self
+        {
+            RendererKind::Emoji => Box::new(EmojiRenderer),
+            RendererKind::HalfBlock | RendererKind::Sixel | RendererKind::Kitty =>
+            {
+                Box::new(HalfBlockRenderer)
+            }
+            RendererKind::Braille => Box::new(BrailleRenderer),
+            RendererKind::Ascii => Box::new(AsciiRenderer),
+        }
+    }
+
+    pub fn bitmap_protocol(self) -> Option<BitmapProtocol>
+    {
+        match self
+        {
+            RendererKind::Sixel => Some(BitmapProtocol::Sixel),
+            RendererKind::Kitty => Some(BitmapProtocol::Kitty),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownRenderer(pub String);
+
+impl fmt::Display for UnknownRenderer
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(
+            f,
+            "unknown renderer '{}', expected one of: {}",
+            self.0,
+            RendererKind::NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownRenderer {}
+
+impl FromStr for RendererKind
+{
+    type Err = UnknownRenderer;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.to_ascii_lowercase().as_str()
+        {
+            "emoji" => Ok(RendererKind::Emoji),
+            "half-block" | "halfblock" | "blocks" => Ok(RendererKind::HalfBlock),
+            "braille" => Ok(RendererKind::Braille),
+            "ascii" => Ok(RendererKind::Ascii),
+            "sixel" => Ok(RendererKind::Sixel),
+            "kitty" => Ok(RendererKind::Kitty),
+            _ => Err(UnknownRenderer(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// A 4x4 pattern in the top-left corner:
+    /// ```text
+    /// #...
+    /// .#..
+    /// ...#
+    /// #...
+    /// ```
+    fn pattern() -> ChipDisplay
+    {
+        let mut display = ChipDisplay::new();
+        for (x, y) in [(0, 0), (1, 1), (3, 2), (0, 3)]
+        {
+            display.set_pixel(x, y, true);
+        }
+        display.debuff();
+        display
+    }
+
+    #[test]
+    fn ascii_test()
+    {
+        let ascii = AsciiRenderer.render(&pattern());
+        assert_eq!(ascii.len(), 32);
+        assert_eq!(&ascii[0][..4], "#   ");
+        assert_eq!(&ascii[3][..4], "#   ");
+        assert!(ascii.iter().all(|line| line.len() == 64));
+    }
+
+    #[test]
+    fn half_block_test()
+    {
+        let half = HalfBlockRenderer.render(&pattern());
+        assert_eq!(half.len(), 16);
+        assert!(half[0].starts_with("▀▄  "));
+        assert!(half[1].starts_with("▄  ▀"));
+        assert_eq!(half[1].chars().count(), 64);
+    }
+
+    #[test]
+    fn braille_test()
+    {
+        let braille = BrailleRenderer.render(&pattern());
+        assert_eq!(braille.len(), 8);
+        assert!(braille[0].starts_with("⡑⠠"));
+        assert_eq!(braille[0].chars().count(), 32);
+        assert!(braille[7].chars().all(|c| c == '\u{2800}'));
+    }
+
+    #[test]
+    fn emoji_test()
+    {
+        let emoji = EmojiRenderer.render(&pattern());
+        assert!(emoji[1].starts_with("⬜⬛⬜"));
+    }
+
+    #[test]
+    fn hires_test()
+    {
+        let mut display = ChipDisplay::new();
+        display.set_hires(true);
+        display.debuff();
+        assert_eq!(HalfBlockRenderer.render(&display).len(), 32);
+        assert_eq!(BrailleRenderer.render(&display)[0].chars().count(), 64);
+    }
+
+    #[test]
+    fn planes_in_half_block_cell_test()
+    {
+        // XO-CHIP: plane 1 above plane 2 in one half-block cell.
+        let mut display = ChipDisplay::new();
+        display.buffer[ChipDisplay::get_buffer_position_from_x_and_y(0, 0)] = 1;
+        display.buffer[ChipDisplay::get_buffer_position_from_x_and_y(0, 1)] = 2;
+        display.debuff();
+        let cells = HalfBlockRenderer.render_cells(&display);
+        assert_eq!(
+            cells[0][0],
+            Cell {
+                ch: '▀',
+                fg: 1,
+                bg: 2
+            }
+        );
+    }
+
+    #[test]
+    fn renderer_names_test()
+    {
+        assert_eq!("Braille".parse::<RendererKind>(), Ok(RendererKind::Braille));
+        assert!("vt340".parse::<RendererKind>().is_err());
+    }
+}
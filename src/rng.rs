@@ -0,0 +1,47 @@
+/// The pseudo-random number generator behind `CXKK`.
+///
+/// This is SplitMix64: tiny, fast and fully determined by its seed, so a run
+/// can be reproduced by recording the seed. The current state is part of the
+/// machine's save states.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rng
+{
+    seed: u64,
+    state: u64,
+}
+
+impl Rng
+{
+    pub fn new(seed: u64) -> Self
+    {
+        Self { seed, state: seed }
+    }
+
+    /// The seed this generator was created with.
+    pub fn seed(&self) -> u64
+    {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64
+    {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u8(&mut self) -> u8
+    {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+impl Default for Rng
+{
+    fn default() -> Self
+    {
+        Self::new(0)
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::error::Chip8Error;
+use crate::machine::{Chip8, MachineState};
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / FRAMES_PER_SECOND as u64);
+/// The most instructions per frame the speed-up key goes to.
+pub const MAX_CYCLES_PER_FRAME: usize = 10_000;
+/// How far pacing may fall behind before it gives up catching up.
+const MAX_FRAME_LAG: u32 = 4;
+
+/// Drives a [`Chip8`] in 60 Hz frames.
+///
+/// Every frame executes `cycles_per_frame` instructions, decrements the
+/// timers exactly once and then presents the framebuffer. Nothing depends on
+/// the wall clock unless pacing is turned on, in which case the scheduler
+/// sleeps between frames so they are shown at 60 Hz.
+pub struct Scheduler
+{
+    pub cycles_per_frame: usize,
+    pacing: bool,
+    next_frame: Option<Instant>,
+    /// Instructions executed so far.
+    pub cycles: u64,
+    /// Frames completed so far.
+    pub frames: u64,
+}
+
+impl Scheduler
+{
+    pub fn new(cycles_per_frame: usize) -> Self
+    {
+        Self {
+            cycles_per_frame: cycles_per_frame.max(1),
+            pacing: false,
+            next_frame: None,
+            cycles: 0,
+            frames: 0,
+        }
+    }
+
+    /// Enables sleeping between frames to match real time.
+    pub fn with_pacing(mut self, pacing: bool) -> Self
+    {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Runs one full frame and reports whether the display changed.
+    pub fn run_frame(&mut self, machine: &mut Chip8) -> Result<bool, Chip8Error>
+    {
+        self.run_cycles(machine, self.cycles_per_frame)?;
+        Ok(self.end_frame(machine))
+    }
+
+    /// Executes up to `n` instructions without ending the frame, stopping
+    /// early if the machine halts. Cycles spent waiting for a key still count.
+    pub fn run_cycles(&mut self, machine: &mut Chip8, n: usize) -> Result<(), Chip8Error>
+    {
+        for _ in 0..n
+        {
+            if machine.state == MachineState::Halted
+            {
+                break;
+            }
+            machine.step()?;
+            self.cycles += 1;
+        }
+        Ok(())
+    }
+
+    /// Executes a single instruction and returns whether that was the last
+    /// one in the frame, in which case [`Scheduler::end_frame`] is due.
+    pub fn step(&mut self, machine: &mut Chip8) -> Result<bool, Chip8Error>
+    {
+        let cycles = self.cycles;
+        self.run_cycles(machine, 1)?;
+        Ok(self.cycles != cycles && self.cycles.is_multiple_of(self.cycles_per_frame as u64))
+    }
+
+    /// Ticks the timers and reports whether the display changed.
+    pub fn end_frame(&mut self, machine: &mut Chip8) -> bool
+    {
+        machine.tick_timers();
+        self.frames += 1;
+        let changed = machine.display.buffer_tainted;
+        if changed
+        {
+            machine.display.debuff();
+        }
+        if self.pacing
+        {
+            self.wait_for_next_frame();
+        }
+        changed
+    }
+
+    fn wait_for_next_frame(&mut self)
+    {
+        let now = Instant::now();
+        let mut deadline = *self.next_frame.get_or_insert(now);
+        if deadline > now
+        {
+            thread::sleep(deadline - now);
+        }
+        else if now - deadline > FRAME_DURATION * MAX_FRAME_LAG
+        {
+            deadline = now;
+        }
+        self.next_frame = Some(deadline + FRAME_DURATION);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Runs 30 frames of 7 cycles: LD V0, 0xFF; LD DT, V0; then count up V1
+    /// forever.
+    fn run_frames() -> (Chip8, Scheduler)
+    {
+        let mut machine = Chip8::new();
+        machine.load_rom(&[0x60, 0xFF, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04]).unwrap();
+        let mut scheduler = Scheduler::new(7);
+        for _ in 0..30
+        {
+            scheduler.run_frame(&mut machine).unwrap();
+        }
+        (machine, scheduler)
+    }
+
+    #[test]
+    fn scheduler_frame_test()
+    {
+        let (machine, scheduler) = run_frames();
+        assert_eq!(scheduler.frames, 30);
+        assert_eq!(scheduler.cycles, 210);
+        assert_eq!(machine.registers.delay, 0xFF - 30);
+    }
+
+    #[test]
+    fn scheduler_is_deterministic_test()
+    {
+        let (first, _) = run_frames();
+        let (second, _) = run_frames();
+        assert_eq!(first.registers, second.registers);
+        assert!(first.display.data == second.display.data);
+    }
+}
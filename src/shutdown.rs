@@ -0,0 +1,92 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+/// Stored instead of a signal number when quitting was asked for in-process.
+const QUIT_REQUESTED: usize = usize::MAX;
+
+/// A flag that tells every loop of the emulator to wind down.
+///
+/// Clones share the flag. It is set by [`QuitSignal::request`], [`QuitSignal::request_signal`] or, once
+/// [`QuitSignal::register_signals`] has been called, by SIGINT or SIGTERM.
+#[derive(Clone, Debug, Default)]
+pub struct QuitSignal(Arc<AtomicUsize>);
+
+impl QuitSignal
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Makes SIGINT and SIGTERM set the flag instead of killing the process.
+    pub fn register_signals(&self) -> io::Result<()>
+    {
+        for signal in [SIGINT, SIGTERM]
+        {
+            signal_hook::flag::register_usize(signal, self.0.clone(), signal as usize)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the flag, unless a signal already did.
+    pub fn request(&self)
+    {
+        let _ = self.0.compare_exchange(0, QUIT_REQUESTED, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    /// Sets the flag as if `signal` had been delivered, for keys such as
+    /// Ctrl+C that raw mode stops from raising it.
+    pub fn request_signal(&self, signal: i32)
+    {
+        self.0.store(signal as usize, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool
+    {
+        self.0.load(Ordering::SeqCst) != 0
+    }
+
+    /// The signal that set the flag, if it was set by one.
+    pub fn signal(&self) -> Option<i32>
+    {
+        match self.0.load(Ordering::SeqCst)
+        {
+            0 | QUIT_REQUESTED => None,
+            signal => Some(signal as i32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn quit_signal_test()
+    {
+        let quit = QuitSignal::new();
+        let shared = quit.clone();
+        assert!(!shared.is_requested());
+        quit.request();
+        assert!(shared.is_requested());
+        assert_eq!(shared.signal(), None);
+    }
+
+    #[test]
+    fn quit_signal_ctrl_c_test()
+    {
+        // Ctrl+C in raw mode quits with the same status as a real SIGINT.
+        let quit = QuitSignal::new();
+        quit.request_signal(SIGINT);
+        assert!(quit.is_requested());
+        assert_eq!(quit.signal(), Some(SIGINT));
+    }
+}